@@ -0,0 +1,997 @@
+//! A syntax tree for tools that look at a program without running it, like linters and formatters.
+//! The compiler doesn't go through it, it emits bytecode straight from the tokens.
+
+use crate::compiler::{BindingPower, CompileErrors, ParseError, DEFAULT_MAX_ERRORS};
+use crate::scanner::{number_value, ScanResult, Scanner, Token, TokenContents};
+use std::iter::Peekable;
+
+type ParseResult<A> = Result<A, CompileErrors>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Expression(Expr),
+    Print(Expr),
+    Var {
+        name: String,
+        initializer: Option<Expr>,
+    },
+    Function(Function),
+    Block(Vec<Stmt>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+    /// A C-style `for`, whose initializer is a `Var` or an `Expression` statement.
+    For {
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    /// `for (var name in sequence) body`.
+    ForIn {
+        name: String,
+        sequence: Expr,
+        body: Box<Stmt>,
+    },
+    Break,
+    Continue,
+    Return(Option<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// `None` for a lambda.
+    pub name: Option<String>,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Nil,
+    Variable(String),
+    /// The `print` native used as a value.
+    Print,
+    Grouping(Box<Expr>),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Logical {
+        op: LogicalOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `name = value`, or a compound assignment like `name += value` when `op` is set.
+    Assign {
+        name: String,
+        op: Option<BinaryOp>,
+        value: Box<Expr>,
+    },
+    /// `name++` or `name--`, which evaluates to the old value.
+    Increment {
+        name: String,
+        op: BinaryOp,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        target: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Map(Vec<(Expr, Expr)>),
+    Lambda(Function),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+/// Walks a tree. Every method visits the children of its node by default, so an implementation
+/// only overrides the nodes it cares about and calls the matching `walk_*` function to keep going.
+pub trait Visitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function)
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Expression(expr) | Stmt::Print(expr) => visitor.visit_expr(expr),
+        Stmt::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expr(initializer);
+            }
+        }
+        Stmt::Function(function) => visitor.visit_function(function),
+        Stmt::Block(stmts) => stmts.iter().for_each(|s| visitor.visit_stmt(s)),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_stmt(initializer);
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expr(condition);
+            }
+            if let Some(increment) = increment {
+                visitor.visit_expr(increment);
+            }
+            visitor.visit_stmt(body);
+        }
+        Stmt::ForIn { sequence, body, .. } => {
+            visitor.visit_expr(sequence);
+            visitor.visit_stmt(body);
+        }
+        Stmt::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Break | Stmt::Continue => {}
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Grouping(inner) => visitor.visit_expr(inner),
+        Expr::Unary { operand, .. } => visitor.visit_expr(operand),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Assign { value, .. } => visitor.visit_expr(value),
+        Expr::Call { callee, args } => {
+            visitor.visit_expr(callee);
+            args.iter().for_each(|a| visitor.visit_expr(a));
+        }
+        Expr::Index { target, index } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(index);
+        }
+        Expr::SetIndex {
+            target,
+            index,
+            value,
+        } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(index);
+            visitor.visit_expr(value);
+        }
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                visitor.visit_expr(key);
+                visitor.visit_expr(value);
+            }
+        }
+        Expr::Lambda(function) => visitor.visit_function(function),
+        Expr::Number(_)
+        | Expr::String(_)
+        | Expr::Bool(_)
+        | Expr::Nil
+        | Expr::Variable(_)
+        | Expr::Print
+        | Expr::Increment { .. } => {}
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &Function) {
+    function.body.iter().for_each(|s| visitor.visit_stmt(s));
+}
+
+/// Parses `source` into a tree. Only the syntax is checked, mistakes the compiler finds while
+/// resolving names, like a `return` outside of a function, aren't reported.
+pub fn parse(source: &str) -> Result<Vec<Stmt>, CompileErrors> {
+    let scanner = Scanner::new(source);
+    let mut iter = scanner.iter();
+    let mut parser = Parser {
+        iter: (&mut iter as &mut dyn Iterator<Item = _>).peekable(),
+        errors: CompileErrors::with_limit(DEFAULT_MAX_ERRORS),
+    };
+    let mut program = Vec::new();
+    while let Some(peeked) = parser.iter.peek() {
+        if parser.errors.is_full() {
+            break;
+        }
+        match peeked {
+            Ok(_) => program.extend(parser.declaration_or_synchronize()),
+            Err(e) => {
+                parser.errors.push(e.clone().into());
+                let _ = parser.iter.next();
+            }
+        }
+    }
+    if parser.errors.errors().is_empty() {
+        Ok(program)
+    } else {
+        Err(parser.errors)
+    }
+}
+
+struct Parser<'a, 'b> {
+    iter: Peekable<&'b mut dyn Iterator<Item = ScanResult<Token<'a>>>>,
+    errors: CompileErrors,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+    fn next_token(&mut self) -> ParseResult<Token<'a>> {
+        match self.iter.next() {
+            Some(token) => Ok(token?),
+            None => Err(ParseError::GeneralError("Unexpected end of stream".to_string()).into()),
+        }
+    }
+
+    fn peek_token(&mut self) -> ParseResult<&Token<'a>> {
+        match self.iter.peek() {
+            Some(Ok(token)) => Ok(token),
+            Some(Err(e)) => Err(e.clone().into()),
+            None => Err(ParseError::GeneralError("Unexpected end of stream".to_string()).into()),
+        }
+    }
+
+    fn next_is(&mut self, contents: &TokenContents) -> bool {
+        matches!(self.iter.peek(), Some(Ok(token)) if &token.contents == contents)
+    }
+
+    /// Takes the next token, which has to be `expected`, or reports it with `error`.
+    fn consume(
+        &mut self,
+        expected: TokenContents,
+        error: impl FnOnce(&Token) -> ParseError,
+    ) -> ParseResult<Token<'a>> {
+        let token = self.next_token()?;
+        if token.contents == expected {
+            Ok(token)
+        } else {
+            Err(error(&token).into())
+        }
+    }
+
+    fn consume_semicolon(&mut self) -> ParseResult<()> {
+        self.consume(TokenContents::Semicolon, |t| {
+            ParseError::MissingSemicolon(t.line, t.contents.to_string())
+        })?;
+        Ok(())
+    }
+
+    /// Parses a declaration, or records its error and skips to where the next one likely starts.
+    fn declaration_or_synchronize(&mut self) -> Option<Stmt> {
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(e) => {
+                self.synchronize(e);
+                None
+            }
+        }
+    }
+
+    fn synchronize(&mut self, e: CompileErrors) {
+        self.errors.extend(e);
+        while let Some(Ok(token)) = self.iter.next() {
+            if token.contents == TokenContents::Semicolon {
+                break;
+            }
+            if let Some(Ok(token)) = self.iter.peek() {
+                match token.contents {
+                    TokenContents::Class
+                    | TokenContents::Fun
+                    | TokenContents::Var
+                    | TokenContents::For
+                    | TokenContents::If
+                    | TokenContents::While
+                    | TokenContents::Print
+                    | TokenContents::Return => break,
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    fn declaration(&mut self) -> ParseResult<Stmt> {
+        match self.peek_token()?.contents {
+            TokenContents::Var => {
+                let _ = self.next_token();
+                let name = self.variable_name()?;
+                let initializer = self.var_initializer()?;
+                Ok(Stmt::Var { name, initializer })
+            }
+            TokenContents::Fun => {
+                let _ = self.next_token();
+                let name = self.variable_name()?;
+                Ok(Stmt::Function(self.function(Some(name))?))
+            }
+            _ => self.statement(),
+        }
+    }
+
+    fn variable_name(&mut self) -> ParseResult<String> {
+        let token = self.next_token()?;
+        match token.contents {
+            TokenContents::Identifier(id) => Ok(id.to_string()),
+            _ => Err(ParseError::NotAVariableName(token.line, token.contents.to_string()).into()),
+        }
+    }
+
+    fn var_initializer(&mut self) -> ParseResult<Option<Expr>> {
+        let initializer = if self.next_is(&TokenContents::Equal) {
+            let _ = self.next_token();
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume_semicolon()?;
+        Ok(initializer)
+    }
+
+    /// The parameters and body of a function, after `fun` and its name.
+    fn function(&mut self, name: Option<String>) -> ParseResult<Function> {
+        self.consume(TokenContents::LeftParen, |t| {
+            ParseError::MissingParameterList(t.line, t.contents.to_string())
+        })?;
+        let mut params = Vec::new();
+        if !self.next_is(&TokenContents::RightParen) {
+            loop {
+                let token = self.peek_token()?;
+                if params.len() == u8::MAX as usize {
+                    return Err(ParseError::TooManyParameters(
+                        token.line,
+                        token.contents.to_string(),
+                    )
+                    .into());
+                }
+                params.push(self.variable_name()?);
+                if !self.next_is(&TokenContents::Comma) {
+                    break;
+                }
+                let _ = self.next_token();
+            }
+        }
+        self.consume(TokenContents::RightParen, |t| {
+            ParseError::UnclosedParameterList(t.line, t.contents.to_string())
+        })?;
+        self.consume(TokenContents::LeftBrace, |t| {
+            ParseError::MissingFunctionBody(t.line, t.contents.to_string())
+        })?;
+        let body = self.block()?;
+        Ok(Function { name, params, body })
+    }
+
+    /// The declarations up to and including the closing `}`.
+    fn block(&mut self) -> ParseResult<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        loop {
+            match self.iter.peek() {
+                Some(Ok(token)) if token.contents == TokenContents::RightBrace => {
+                    let _ = self.next_token();
+                    return Ok(stmts);
+                }
+                Some(_) => stmts.extend(self.declaration_or_synchronize()),
+                None => {
+                    return Err(ParseError::GeneralError(
+                        "Didn't find matching closing brace".to_string(),
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self) -> ParseResult<Stmt> {
+        let token = self.next_token()?;
+        match token.contents {
+            TokenContents::Print => {
+                if let Some(Ok(Token {
+                    contents: TokenContents::Semicolon,
+                    line,
+                })) = self.iter.peek()
+                {
+                    return Err(ParseError::ExpectExpressionAfterPrint(*line).into());
+                }
+                let value = self.expression()?;
+                self.consume_semicolon()?;
+                Ok(Stmt::Print(value))
+            }
+            TokenContents::LeftBrace => Ok(Stmt::Block(self.block()?)),
+            TokenContents::If => self.if_statement(),
+            TokenContents::While => {
+                let condition = self.condition("while")?;
+                let body = Box::new(self.statement()?);
+                Ok(Stmt::While { condition, body })
+            }
+            TokenContents::For => self.for_statement(),
+            TokenContents::Break => {
+                self.consume_semicolon()?;
+                Ok(Stmt::Break)
+            }
+            TokenContents::Continue => {
+                self.consume_semicolon()?;
+                Ok(Stmt::Continue)
+            }
+            TokenContents::Return => {
+                if self.next_is(&TokenContents::Semicolon) {
+                    let _ = self.next_token();
+                    return Ok(Stmt::Return(None));
+                }
+                let value = self.expression()?;
+                self.consume_semicolon()?;
+                Ok(Stmt::Return(Some(value)))
+            }
+            _ => {
+                let expr = self.expression_after(token, BindingPower::None)?;
+                self.consume_semicolon()?;
+                Ok(Stmt::Expression(expr))
+            }
+        }
+    }
+
+    /// A parenthesized condition after `keyword`.
+    fn condition(&mut self, keyword: &str) -> ParseResult<Expr> {
+        self.consume(TokenContents::LeftParen, |_| {
+            ParseError::GeneralError(format!("Expected '(' after '{keyword}'"))
+        })?;
+        let condition = self.expression()?;
+        self.consume(TokenContents::RightParen, |_| {
+            ParseError::GeneralError("Expected ')' after condition".to_string())
+        })?;
+        Ok(condition)
+    }
+
+    fn if_statement(&mut self) -> ParseResult<Stmt> {
+        let condition = self.condition("if")?;
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.next_is(&TokenContents::Else) {
+            let _ = self.next_token();
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+        Ok(Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    fn for_statement(&mut self) -> ParseResult<Stmt> {
+        self.consume(TokenContents::LeftParen, |_| {
+            ParseError::GeneralError("Expected '(' after 'for'".to_string())
+        })?;
+        let close_clauses =
+            |_: &Token| ParseError::GeneralError("Expected ')' after for clauses".to_string());
+        let initializer = match self.peek_token()?.contents {
+            TokenContents::Semicolon => {
+                let _ = self.next_token();
+                None
+            }
+            TokenContents::Var => {
+                let _ = self.next_token();
+                let name = self.variable_name()?;
+                if self.next_is(&TokenContents::In) {
+                    let _ = self.next_token();
+                    let sequence = self.expression()?;
+                    self.consume(TokenContents::RightParen, close_clauses)?;
+                    let body = Box::new(self.statement()?);
+                    return Ok(Stmt::ForIn {
+                        name,
+                        sequence,
+                        body,
+                    });
+                }
+                let initializer = self.var_initializer()?;
+                Some(Box::new(Stmt::Var { name, initializer }))
+            }
+            _ => {
+                let expr = self.expression()?;
+                self.consume_semicolon()?;
+                Some(Box::new(Stmt::Expression(expr)))
+            }
+        };
+        let condition = if self.next_is(&TokenContents::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenContents::Semicolon, |_| {
+            ParseError::GeneralError("Expected ';'".to_string())
+        })?;
+        let increment = if self.next_is(&TokenContents::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenContents::RightParen, close_clauses)?;
+        let body = Box::new(self.statement()?);
+        Ok(Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        })
+    }
+
+    fn expression(&mut self) -> ParseResult<Expr> {
+        self.expression_bp(BindingPower::None)
+    }
+
+    fn expression_bp(&mut self, min_bp: BindingPower) -> ParseResult<Expr> {
+        let token = self.next_token()?;
+        self.expression_after(token, min_bp)
+    }
+
+    /// An expression whose first token, `token`, was already taken. Binds like the compiler does.
+    fn expression_after(&mut self, token: Token<'a>, min_bp: BindingPower) -> ParseResult<Expr> {
+        let can_assign = min_bp <= BindingPower::Assignment;
+        let mut expr = self.prefix(token, can_assign)?;
+        while self.iter.peek().is_some() {
+            let peek = self.peek_token()?;
+            let Some(infix_bp) = infix_binding_power(&peek.contents) else {
+                if can_assign
+                    && (peek.contents == TokenContents::Equal
+                        || compound_assignment_op(&peek.contents).is_some())
+                {
+                    return Err(ParseError::InvalidAssignmentTarget(peek.line).into());
+                }
+                if postfix_op(&peek.contents).is_some() {
+                    return Err(ParseError::InvalidIncrementTarget(
+                        peek.line,
+                        peek.contents.to_string(),
+                    )
+                    .into());
+                }
+                break;
+            };
+            if infix_bp < min_bp {
+                break;
+            }
+            let token = self.next_token()?;
+            expr = self.infix(expr, token, infix_bp, can_assign)?;
+        }
+        Ok(expr)
+    }
+
+    fn prefix(&mut self, token: Token<'a>, can_assign: bool) -> ParseResult<Expr> {
+        match token.contents {
+            TokenContents::Minus | TokenContents::Bang => {
+                let op = if token.contents == TokenContents::Minus {
+                    UnaryOp::Negate
+                } else {
+                    UnaryOp::Not
+                };
+                let operand = Box::new(self.expression_bp(BindingPower::Unary)?);
+                Ok(Expr::Unary { op, operand })
+            }
+            TokenContents::Number(number) => Ok(Expr::Number(number_value(number))),
+            TokenContents::String(s) => Ok(Expr::String(s.to_string())),
+            TokenContents::True => Ok(Expr::Bool(true)),
+            TokenContents::False => Ok(Expr::Bool(false)),
+            TokenContents::Nil => Ok(Expr::Nil),
+            TokenContents::Print => Ok(Expr::Print),
+            TokenContents::LeftParen => {
+                let inner = self.expression()?;
+                self.consume(TokenContents::RightParen, |_| {
+                    ParseError::GeneralError("Unmatched opening parenthesis".to_string())
+                })?;
+                Ok(Expr::Grouping(Box::new(inner)))
+            }
+            TokenContents::LeftBrace => self.map(&token),
+            TokenContents::Identifier(id) => self.variable(id, can_assign),
+            // A named function is a declaration, which can't appear where an expression is expected
+            TokenContents::Fun if self.next_is(&TokenContents::LeftParen) => {
+                Ok(Expr::Lambda(self.function(None)?))
+            }
+            _ => Err(ParseError::NoPrefixParser(token.line, token.contents.to_string()).into()),
+        }
+    }
+
+    fn variable(&mut self, id: &str, can_assign: bool) -> ParseResult<Expr> {
+        let name = id.to_string();
+        let Some(Ok(next)) = self.iter.peek() else {
+            return Ok(Expr::Variable(name));
+        };
+        if can_assign && next.contents == TokenContents::Equal {
+            let _ = self.next_token();
+            let value = Box::new(self.expression()?);
+            Ok(Expr::Assign {
+                name,
+                op: None,
+                value,
+            })
+        } else if let Some(op) = compound_assignment_op(&next.contents).filter(|_| can_assign) {
+            let _ = self.next_token();
+            let value = Box::new(self.expression()?);
+            Ok(Expr::Assign {
+                name,
+                op: Some(op),
+                value,
+            })
+        } else if let Some(op) = postfix_op(&next.contents) {
+            let _ = self.next_token();
+            Ok(Expr::Increment { name, op })
+        } else {
+            Ok(Expr::Variable(name))
+        }
+    }
+
+    fn infix(
+        &mut self,
+        left: Expr,
+        token: Token<'a>,
+        bp: BindingPower,
+        can_assign: bool,
+    ) -> ParseResult<Expr> {
+        let left = Box::new(left);
+        if let Some(op) = binary_op(&token.contents) {
+            let right = Box::new(self.expression_bp(bp)?);
+            return Ok(Expr::Binary { op, left, right });
+        }
+        match token.contents {
+            TokenContents::And | TokenContents::Or => {
+                let op = if token.contents == TokenContents::And {
+                    LogicalOp::And
+                } else {
+                    LogicalOp::Or
+                };
+                let right = Box::new(self.expression_bp(bp)?);
+                Ok(Expr::Logical { op, left, right })
+            }
+            TokenContents::LeftParen => self.call(left, &token),
+            TokenContents::LeftBracket => {
+                let index = Box::new(self.expression()?);
+                self.consume(TokenContents::RightBracket, |t| {
+                    ParseError::UnclosedIndex(t.line, t.contents.to_string())
+                })?;
+                if can_assign && self.next_is(&TokenContents::Equal) {
+                    let _ = self.next_token();
+                    let value = Box::new(self.expression()?);
+                    Ok(Expr::SetIndex {
+                        target: left,
+                        index,
+                        value,
+                    })
+                } else {
+                    Ok(Expr::Index {
+                        target: left,
+                        index,
+                    })
+                }
+            }
+            // There are no properties yet, so this only reports the same errors as the compiler
+            TokenContents::Dot => match self.iter.next() {
+                Some(Ok(name)) if matches!(name.contents, TokenContents::Identifier(_)) => {
+                    Err(ParseError::NoInfixParser(token.line, token.contents.to_string()).into())
+                }
+                Some(Ok(name)) => Err(ParseError::MissingPropertyName(
+                    name.line,
+                    format!("'{}'", name.contents),
+                )
+                .into()),
+                Some(Err(e)) => Err(e.into()),
+                None => Err(ParseError::MissingPropertyName(token.line, "end".to_string()).into()),
+            },
+            _ => unreachable!("Unexpected infix token, got {token:?}"),
+        }
+    }
+
+    fn call(&mut self, callee: Box<Expr>, token: &Token) -> ParseResult<Expr> {
+        let mut args = Vec::new();
+        if !self.next_is(&TokenContents::RightParen) {
+            loop {
+                args.push(self.expression()?);
+                if args.len() > u8::MAX as usize {
+                    return Err(ParseError::TooManyArguments(token.line).into());
+                }
+                if !self.next_is(&TokenContents::Comma) {
+                    break;
+                }
+                let _ = self.next_token();
+            }
+        }
+        self.consume(TokenContents::RightParen, |_| {
+            ParseError::GeneralError("Expect ')' after arguments".to_string())
+        })?;
+        Ok(Expr::Call { callee, args })
+    }
+
+    /// A map literal, with `{:}` for the empty map. At the start of a statement `{` always opens a
+    /// block instead.
+    fn map(&mut self, token: &Token) -> ParseResult<Expr> {
+        let mut entries = Vec::new();
+        // `{}` is an empty block, never an expression; the empty map is spelled `{:}`
+        if self.next_is(&TokenContents::RightBrace) {
+            return Err(ParseError::NoPrefixParser(token.line, token.contents.to_string()).into());
+        }
+        let empty = self.next_is(&TokenContents::Colon);
+        if empty {
+            let _ = self.next_token();
+        }
+        while !empty && !self.next_is(&TokenContents::RightBrace) {
+            let key = self.expression()?;
+            self.consume(TokenContents::Colon, |t| {
+                ParseError::MissingMapColon(t.line, t.contents.to_string())
+            })?;
+            entries.push((key, self.expression()?));
+            if entries.len() > u8::MAX as usize {
+                return Err(ParseError::TooManyMapEntries(token.line).into());
+            }
+            if !self.next_is(&TokenContents::Comma) {
+                break;
+            }
+            let _ = self.next_token();
+        }
+        self.consume(TokenContents::RightBrace, |t| {
+            ParseError::UnclosedMap(t.line, t.contents.to_string())
+        })?;
+        Ok(Expr::Map(entries))
+    }
+}
+
+fn infix_binding_power(contents: &TokenContents) -> Option<BindingPower> {
+    match contents {
+        TokenContents::Plus | TokenContents::Minus => Some(BindingPower::Term),
+        TokenContents::Asterisk | TokenContents::Slash | TokenContents::Percent => {
+            Some(BindingPower::Factor)
+        }
+        TokenContents::EqualEqual | TokenContents::BangEqual => Some(BindingPower::Equality),
+        TokenContents::Greater
+        | TokenContents::GreaterEqual
+        | TokenContents::Less
+        | TokenContents::LessEqual => Some(BindingPower::Comparison),
+        TokenContents::And => Some(BindingPower::And),
+        TokenContents::Or => Some(BindingPower::Or),
+        TokenContents::LeftParen | TokenContents::LeftBracket | TokenContents::Dot => {
+            Some(BindingPower::Call)
+        }
+        _ => None,
+    }
+}
+
+fn binary_op(contents: &TokenContents) -> Option<BinaryOp> {
+    match contents {
+        TokenContents::Plus => Some(BinaryOp::Add),
+        TokenContents::Minus => Some(BinaryOp::Subtract),
+        TokenContents::Asterisk => Some(BinaryOp::Multiply),
+        TokenContents::Slash => Some(BinaryOp::Divide),
+        TokenContents::Percent => Some(BinaryOp::Modulo),
+        TokenContents::EqualEqual => Some(BinaryOp::Equal),
+        TokenContents::BangEqual => Some(BinaryOp::NotEqual),
+        TokenContents::Greater => Some(BinaryOp::Greater),
+        TokenContents::GreaterEqual => Some(BinaryOp::GreaterEqual),
+        TokenContents::Less => Some(BinaryOp::Less),
+        TokenContents::LessEqual => Some(BinaryOp::LessEqual),
+        _ => None,
+    }
+}
+
+fn compound_assignment_op(contents: &TokenContents) -> Option<BinaryOp> {
+    match contents {
+        TokenContents::PlusEqual => Some(BinaryOp::Add),
+        TokenContents::MinusEqual => Some(BinaryOp::Subtract),
+        TokenContents::AsteriskEqual => Some(BinaryOp::Multiply),
+        TokenContents::SlashEqual => Some(BinaryOp::Divide),
+        TokenContents::PercentEqual => Some(BinaryOp::Modulo),
+        _ => None,
+    }
+}
+
+fn postfix_op(contents: &TokenContents) -> Option<BinaryOp> {
+    match contents {
+        TokenContents::PlusPlus => Some(BinaryOp::Add),
+        TokenContents::MinusMinus => Some(BinaryOp::Subtract),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    #[test]
+    fn parses_program_into_tree() {
+        let program = parse(
+            "fun add(a, b) { return a + b; }\n\
+             var total = add(1, 2) * 3;\n\
+             if (!done) print total; else { total += 1; }",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            [
+                Stmt::Function(Function {
+                    name: Some("add".to_string()),
+                    params: vec!["a".to_string(), "b".to_string()],
+                    body: vec![Stmt::Return(Some(Expr::Binary {
+                        op: BinaryOp::Add,
+                        left: var("a"),
+                        right: var("b"),
+                    }))],
+                }),
+                Stmt::Var {
+                    name: "total".to_string(),
+                    initializer: Some(Expr::Binary {
+                        op: BinaryOp::Multiply,
+                        left: Box::new(Expr::Call {
+                            callee: var("add"),
+                            args: vec![Expr::Number(1.0), Expr::Number(2.0)],
+                        }),
+                        right: Box::new(Expr::Number(3.0)),
+                    }),
+                },
+                Stmt::If {
+                    condition: Expr::Unary {
+                        op: UnaryOp::Not,
+                        operand: var("done"),
+                    },
+                    then_branch: Box::new(Stmt::Print(Expr::Variable("total".to_string()))),
+                    else_branch: Some(Box::new(Stmt::Block(vec![Stmt::Expression(
+                        Expr::Assign {
+                            name: "total".to_string(),
+                            op: Some(BinaryOp::Add),
+                            value: Box::new(Expr::Number(1.0)),
+                        }
+                    )]))),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn precedence_matches_compiler() {
+        let program = parse("a or b and 1 + 2 * 3 < 4;").unwrap();
+        let [Stmt::Expression(Expr::Logical {
+            op: LogicalOp::Or,
+            right,
+            ..
+        })] = program.as_slice()
+        else {
+            panic!("{program:?}");
+        };
+        let Expr::Logical {
+            op: LogicalOp::And,
+            right,
+            ..
+        } = right.as_ref()
+        else {
+            panic!("{right:?}");
+        };
+        assert!(matches!(
+            right.as_ref(),
+            Expr::Binary {
+                op: BinaryOp::Less,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_loops() {
+        let program =
+            parse("for (var i = 0; i < 3; i++) { continue; } for (var c in \"ab\") break;")
+                .unwrap();
+        assert!(matches!(
+            &program[0],
+            Stmt::For {
+                initializer: Some(_),
+                condition: Some(_),
+                increment: Some(Expr::Increment {
+                    op: BinaryOp::Add,
+                    ..
+                }),
+                ..
+            }
+        ));
+        assert_eq!(
+            program[1],
+            Stmt::ForIn {
+                name: "c".to_string(),
+                sequence: Expr::String("ab".to_string()),
+                body: Box::new(Stmt::Break),
+            }
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let errors = parse("var a = 1\nprint a;\n1 + 2 = 3;").unwrap_err();
+        let messages: Vec<_> = errors.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "[line 2] Error at 'print': Expect ';' after expression.",
+                "[line 3] Error at '=': Invalid assignment target.",
+            ]
+        );
+    }
+
+    #[test]
+    fn visitor_reaches_nested_nodes() {
+        struct Names(Vec<String>);
+        impl Visitor for Names {
+            fn visit_expr(&mut self, expr: &Expr) {
+                if let Expr::Variable(name) = expr {
+                    self.0.push(name.clone());
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        let program =
+            parse("fun f() { var g = fun () { return m[a]; }; while (b) c(d); }").unwrap();
+        let mut names = Names(Vec::new());
+        program.iter().for_each(|s| names.visit_stmt(s));
+        assert_eq!(names.0, ["m", "a", "b", "c", "d"]);
+    }
+}
@@ -1,13 +1,14 @@
 use crate::memory::allocator::Allocator;
-use crate::memory::VMHeapVec;
+use crate::memory::{MemoryManager, Object, VMHeapVec};
 use crate::value::Value;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use std::fmt::Write;
 use std::fmt::{Debug, Formatter};
 use std::ops::Deref;
 use std::sync::Arc;
+use thiserror::Error;
 
-#[derive(Debug, Copy, Clone, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum Opcode {
     Constant,
@@ -24,7 +25,6 @@ pub enum Opcode {
     Equal,
     Greater,
     Less,
-    Print,
     Pop,
     DefineGlobal,
     GetGlobal,
@@ -34,12 +34,186 @@ pub enum Opcode {
     JumpIfFalse,
     Jump,
     Loop,
+    PopN,
+    Modulo,
+    Call,
+    DefineGlobalLong,
+    GetGlobalLong,
+    SetGlobalLong,
+    TailCall,
+    Dup,
+    BuildMap,
+    GetIndex,
+    SetIndex,
+    Len,
+    SmallInt,
+    JumpIfTrue,
+    NotEqual,
 }
 
 impl Opcode {
     pub fn as_byte(self) -> u8 {
         self as u8
     }
+
+    /// # Safety
+    /// `byte` must be the byte representation of a valid opcode.
+    #[cfg(feature = "fast_dispatch")]
+    pub unsafe fn from_byte_unchecked(byte: u8) -> Self {
+        debug_assert!(Opcode::try_from(byte).is_ok());
+        std::mem::transmute::<u8, Opcode>(byte)
+    }
+
+    /// Stable name of the opcode, as used by [`Chunk::disassemble_text`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Opcode::Constant => "CONSTANT",
+            Opcode::Add => "ADD",
+            Opcode::Subtract => "SUBTRACT",
+            Opcode::Multiply => "MULTIPLY",
+            Opcode::Divide => "DIVIDE",
+            Opcode::Negate => "NEGATE",
+            Opcode::Return => "RETURN",
+            Opcode::True => "TRUE",
+            Opcode::False => "FALSE",
+            Opcode::Nil => "NIL",
+            Opcode::Not => "NOT",
+            Opcode::Equal => "EQUAL",
+            Opcode::Greater => "GREATER",
+            Opcode::Less => "LESS",
+            Opcode::Pop => "POP",
+            Opcode::DefineGlobal => "DEFINE_GLOBAL",
+            Opcode::GetGlobal => "GET_GLOBAL",
+            Opcode::SetGlobal => "SET_GLOBAL",
+            Opcode::GetLocal => "GET_LOCAL",
+            Opcode::SetLocal => "SET_LOCAL",
+            Opcode::JumpIfFalse => "JUMP_IF_FALSE",
+            Opcode::Jump => "JUMP",
+            Opcode::Loop => "LOOP",
+            Opcode::PopN => "POP_N",
+            Opcode::Modulo => "MODULO",
+            Opcode::Call => "CALL",
+            Opcode::DefineGlobalLong => "DEFINE_GLOBAL_LONG",
+            Opcode::GetGlobalLong => "GET_GLOBAL_LONG",
+            Opcode::SetGlobalLong => "SET_GLOBAL_LONG",
+            Opcode::TailCall => "TAIL_CALL",
+            Opcode::Dup => "DUP",
+            Opcode::BuildMap => "BUILD_MAP",
+            Opcode::GetIndex => "GET_INDEX",
+            Opcode::SetIndex => "SET_INDEX",
+            Opcode::Len => "LEN",
+            Opcode::SmallInt => "SMALL_INT",
+            Opcode::JumpIfTrue => "JUMP_IF_TRUE",
+            Opcode::NotEqual => "NOT_EQUAL",
+        }
+    }
+
+    /// The opcode with the given [`Opcode::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..=u8::MAX)
+            .filter_map(|byte| Opcode::try_from(byte).ok())
+            .find(|opcode| opcode.name() == name)
+    }
+
+    /// Number of operand bytes following the opcode.
+    pub fn operand_len(self) -> usize {
+        match self {
+            Opcode::Constant
+            | Opcode::DefineGlobal
+            | Opcode::GetGlobal
+            | Opcode::SetGlobal
+            | Opcode::GetLocal
+            | Opcode::SetLocal
+            | Opcode::PopN
+            | Opcode::Call
+            | Opcode::TailCall
+            | Opcode::BuildMap
+            | Opcode::SmallInt => 1,
+            Opcode::JumpIfFalse | Opcode::JumpIfTrue | Opcode::Jump | Opcode::Loop => 2,
+            Opcode::DefineGlobalLong | Opcode::GetGlobalLong | Opcode::SetGlobalLong => 3,
+            _ => 0,
+        }
+    }
+
+    /// The variant of a global variable instruction that takes a three byte slot.
+    fn long_global(self) -> Opcode {
+        match self {
+            Opcode::DefineGlobal => Opcode::DefineGlobalLong,
+            Opcode::GetGlobal => Opcode::GetGlobalLong,
+            Opcode::SetGlobal => Opcode::SetGlobalLong,
+            _ => unreachable!("{self:?} is not a global instruction"),
+        }
+    }
+
+    /// Net change in stack depth after executing this instruction. `PopN`, `BuildMap` and the calls
+    /// pop as many values as their operand says, which isn't known here, so they report 0.
+    pub fn stack_effect(self) -> isize {
+        match self {
+            Opcode::Constant
+            | Opcode::SmallInt
+            | Opcode::True
+            | Opcode::False
+            | Opcode::Nil
+            | Opcode::GetGlobal
+            | Opcode::GetGlobalLong
+            | Opcode::GetLocal
+            | Opcode::Dup => 1,
+            Opcode::Add
+            | Opcode::Subtract
+            | Opcode::Multiply
+            | Opcode::Divide
+            | Opcode::Modulo
+            | Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::Greater
+            | Opcode::Less
+            | Opcode::Pop
+            | Opcode::DefineGlobal
+            | Opcode::DefineGlobalLong
+            | Opcode::GetIndex => -1,
+            Opcode::SetIndex => -2,
+            Opcode::Negate
+            | Opcode::Not
+            | Opcode::Return
+            | Opcode::SetGlobal
+            | Opcode::SetGlobalLong
+            | Opcode::SetLocal
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfTrue
+            | Opcode::Jump
+            | Opcode::Loop
+            | Opcode::PopN
+            | Opcode::Call
+            | Opcode::TailCall
+            | Opcode::BuildMap
+            | Opcode::Len => 0,
+        }
+    }
+}
+
+/// An instruction with its operand decoded, grouped by the size of the operand.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Simple(Opcode),
+    Byte(Opcode, u8),
+    Short(Opcode, u16),
+    Long(Opcode, u32),
+}
+
+impl Instruction {
+    pub fn opcode(self) -> Opcode {
+        match self {
+            Instruction::Simple(opcode)
+            | Instruction::Byte(opcode, _)
+            | Instruction::Short(opcode, _)
+            | Instruction::Long(opcode, _) => opcode,
+        }
+    }
+
+    /// Size of the encoded instruction in bytes, including the opcode.
+    pub fn encoded_len(self) -> usize {
+        1 + self.opcode().operand_len()
+    }
 }
 
 pub struct Chunk {
@@ -77,6 +251,30 @@ impl Chunk {
         self.add_byte(operand, line);
     }
 
+    /// Emits a global variable instruction, switching to its long variant when the slot doesn't fit
+    /// in a single byte.
+    pub fn add_global_opcode(&mut self, opcode: Opcode, slot: u32, line: usize) {
+        match u8::try_from(slot) {
+            Ok(slot) => self.add_opcode_and_operand(opcode, slot, line),
+            Err(_) => self.add_instruction(Instruction::Long(opcode.long_global(), slot), line),
+        }
+    }
+
+    /// Emits the opcode and its operand bytes, big-endian.
+    fn add_instruction(&mut self, instruction: Instruction, line: usize) {
+        let opcode = instruction.opcode();
+        let operand = match instruction {
+            Instruction::Simple(_) => 0,
+            Instruction::Byte(_, operand) => operand as u32,
+            Instruction::Short(_, operand) => operand as u32,
+            Instruction::Long(_, operand) => operand,
+        };
+        self.add_opcode(opcode, line);
+        for byte in &operand.to_be_bytes()[4 - opcode.operand_len()..] {
+            self.add_byte(*byte, line);
+        }
+    }
+
     pub fn add_dummy_jump(&mut self, opcode: Opcode, line: usize) -> usize {
         self.add_opcode(opcode, line);
         let target = self.code.len();
@@ -106,6 +304,36 @@ impl Chunk {
         Ok(())
     }
 
+    /// Turns the `Call` at `offset` into a `TailCall`.
+    pub fn make_tail_call(&mut self, offset: usize) {
+        debug_assert_eq!(self.code[offset], Opcode::Call.as_byte());
+        self.code[offset] = Opcode::TailCall.as_byte();
+    }
+
+    pub fn constants_len(&self) -> usize {
+        self.constants.len()
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the chunk was built with `alloc`, and so belongs to the memory manager using it.
+    pub(crate) fn uses_allocator(&self, alloc: &Arc<Allocator>) -> bool {
+        Arc::ptr_eq(self.code.alloc(), alloc)
+    }
+
+    /// Drops the code from `len` on and the constants from `constants_len` on.
+    pub fn truncate(&mut self, len: usize, constants_len: usize) {
+        self.code.truncate(len);
+        self.lines.truncate(len);
+        self.constants.truncate(constants_len);
+    }
+
     pub fn get_loop_start(&self) -> usize {
         self.code.len()
     }
@@ -134,11 +362,14 @@ impl Chunk {
     pub fn add_constant(&mut self, value: Value) -> Option<u8> {
         if self.constants.len() < 256 {
             // Maybe use some set for this? HashTable maybe?
-            let existing_index = self
-                .constants
-                .iter()
-                .enumerate()
-                .find_map(|(idx, c)| (*c == value).then_some(idx));
+            let existing_index = self.constants.iter().enumerate().find_map(|(idx, c)| {
+                // `0` and `-0` are equal but print differently, so numbers must match exactly
+                let same = match (c, &value) {
+                    (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+                    _ => *c == value,
+                };
+                same.then_some(idx)
+            });
             if let Some(idx) = existing_index {
                 Some(idx as u8)
             } else {
@@ -154,147 +385,737 @@ impl Chunk {
         self.constants.get(index as usize)
     }
 
-    fn code_line_iter(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
-        self.code.iter().copied().zip(self.lines.iter().copied())
+    /// Decodes the instruction starting at `offset`.
+    pub fn decode_at(&self, offset: usize) -> Result<Instruction, ChunkError> {
+        let opcode = self
+            .code
+            .get(offset)
+            .and_then(|&b| Opcode::try_from(b).ok())
+            .ok_or(ChunkError::InvalidOpcode { offset })?;
+        let operand = self
+            .code
+            .get(offset + 1..offset + 1 + opcode.operand_len())
+            .ok_or(ChunkError::MissingOperand { offset })?;
+        Ok(match *operand {
+            [] => Instruction::Simple(opcode),
+            [b] => Instruction::Byte(opcode, b),
+            [h, l] => Instruction::Short(opcode, u16::from_be_bytes([h, l])),
+            [h, m, l] => Instruction::Long(opcode, u32::from_be_bytes([0, h, m, l])),
+            _ => unreachable!("No opcode has more than three operand bytes"),
+        })
     }
 
-    pub fn disassemble(&self) -> String {
-        let mut iter = self.code_line_iter().enumerate();
+    /// Decoded instructions with their offset and line. Stops early at the first instruction that
+    /// can't be decoded, [`Chunk::validate`] reports those.
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, Instruction, usize)> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset >= self.code.len() {
+                return None;
+            }
+            let instruction = self.decode_at(offset).ok()?;
+            let item = (offset, instruction, self.lines[offset]);
+            offset += instruction.encoded_len();
+            Some(item)
+        })
+    }
 
-        let mut result = String::new();
+    /// Checks that every instruction has a valid opcode and all its operands, that every constant
+    /// operand refers to an existing constant, and that every jump lands on an instruction boundary
+    /// inside the chunk. The code must end in a `Return`, so execution can't run off its end. The
+    /// chunks of functions among the constants are checked as well. Returns the first problem found.
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        let mut boundaries = vec![false; self.code.len()];
+        let mut jumps = Vec::new();
+        let mut last = None;
+        let mut offset = 0;
+        while offset < self.code.len() {
+            boundaries[offset] = true;
+            let instruction = self.decode_at(offset)?;
+            last = Some(instruction.opcode());
+            let next = offset + instruction.encoded_len();
+            match instruction {
+                Instruction::Byte(Opcode::Constant, index)
+                    if self.get_constant(index).is_none() =>
+                {
+                    return Err(ChunkError::InvalidConstant { offset, index });
+                }
+                Instruction::Short(opcode, jump) => {
+                    let target = if let Opcode::Loop = opcode {
+                        next.checked_sub(jump as usize)
+                    } else {
+                        Some(next + jump as usize)
+                    };
+                    jumps.push((offset, target));
+                }
+                _ => {}
+            }
+            offset = next;
+        }
+        if last != Some(Opcode::Return) {
+            return Err(ChunkError::MissingReturn);
+        }
+        for (offset, target) in jumps {
+            match target {
+                Some(target) if boundaries.get(target).copied().unwrap_or(false) => {}
+                _ => return Err(ChunkError::InvalidJumpTarget { offset }),
+            }
+        }
+        for constant in self.constants.iter() {
+            if let Value::Obj(Object::Function(function)) = constant {
+                function.chunk().validate()?;
+            }
+        }
+        Ok(())
+    }
 
-        let mut previous_line: Option<usize> = None;
+    /// Abstractly interprets the chunk, following every jump, and verifies that the stack depth
+    /// never goes negative, agrees wherever control flow merges, and is one, just the script's
+    /// result, at every `Return`.
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    pub fn check_stack_balance(&self) -> Result<(), StackBalanceError> {
+        self.check_balance(false)
+    }
 
-        writeln!(result, "== {} ==", self.name).unwrap();
+    /// Like [`Chunk::check_stack_balance`], for the chunk of a function. Its `Return` takes the
+    /// return value from the top of the stack and discards the rest of the frame, so any depth of at
+    /// least one is fine there.
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    pub fn check_function_stack_balance(&self) -> Result<(), StackBalanceError> {
+        self.check_balance(true)
+    }
 
-        while let Some((offset, (opcode, line))) = iter.next() {
-            write!(result, "0x{offset:04x} ").unwrap();
-            match previous_line {
-                None => {
-                    write!(result, "{line:04} ").unwrap();
-                    previous_line = Some(line);
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    fn check_balance(&self, is_function: bool) -> Result<(), StackBalanceError> {
+        let mut depths: Vec<Option<isize>> = vec![None; self.code.len()];
+        let mut worklist = vec![(0usize, 0isize)];
+
+        while let Some((offset, depth)) = worklist.pop() {
+            match depths.get(offset) {
+                None => return Err(StackBalanceError::OutOfBounds { offset }),
+                Some(Some(known)) if *known != depth => {
+                    return Err(StackBalanceError::Mismatch {
+                        offset,
+                        expected: *known,
+                        found: depth,
+                    });
                 }
-                Some(prev_line) => {
-                    if prev_line < line {
-                        write!(result, "{line:04} ").unwrap();
-                        previous_line = Some(line);
-                    } else {
-                        write!(result, "   | ").unwrap();
+                Some(Some(_)) => continue,
+                Some(None) => depths[offset] = Some(depth),
+            }
+            let opcode = Opcode::try_from(self.code[offset])
+                .map_err(|_| StackBalanceError::InvalidOpcode { offset })?;
+            let operand = || {
+                self.code
+                    .get(offset + 1)
+                    .map(|&b| b as isize)
+                    .ok_or(StackBalanceError::OutOfBounds { offset })
+            };
+            let effect = match opcode {
+                Opcode::PopN => -operand()?,
+                // The callee and its arguments are replaced by the result
+                Opcode::Call | Opcode::TailCall => -operand()?,
+                // The keys and values are replaced by the map
+                Opcode::BuildMap => 1 - 2 * operand()?,
+                _ => opcode.stack_effect(),
+            };
+            let new_depth = depth + effect;
+            if new_depth < 0 {
+                return Err(StackBalanceError::Underflow { offset });
+            }
+            let next = offset + 1 + opcode.operand_len();
+            let jump = || -> Result<usize, StackBalanceError> {
+                let h = *self
+                    .code
+                    .get(offset + 1)
+                    .ok_or(StackBalanceError::OutOfBounds { offset })?;
+                let l = *self
+                    .code
+                    .get(offset + 2)
+                    .ok_or(StackBalanceError::OutOfBounds { offset })?;
+                Ok((((h as u16) << 8) | (l as u16)) as usize)
+            };
+            match opcode {
+                Opcode::Return if is_function => {
+                    if new_depth < 1 {
+                        return Err(StackBalanceError::Underflow { offset });
+                    }
+                }
+                Opcode::Return => {
+                    if new_depth != 1 {
+                        return Err(StackBalanceError::Unbalanced {
+                            offset,
+                            depth: new_depth,
+                        });
                     }
                 }
+                Opcode::Jump => worklist.push((next + jump()?, new_depth)),
+                Opcode::JumpIfFalse | Opcode::JumpIfTrue => {
+                    worklist.push((next + jump()?, new_depth));
+                    worklist.push((next, new_depth));
+                }
+                Opcode::Loop => {
+                    let target = next
+                        .checked_sub(jump()?)
+                        .ok_or(StackBalanceError::OutOfBounds { offset })?;
+                    worklist.push((target, new_depth));
+                }
+                _ => worklist.push((next, new_depth)),
             }
-            self.write_single_instruction(&mut iter, &mut result, opcode);
-            writeln!(result).unwrap();
         }
 
-        result
+        Ok(())
+    }
+
+    /// Serializes the chunk, including the names of all globals known to `memory_manager` so their
+    /// slots can be remapped when loading.
+    pub fn to_bytes(&self, memory_manager: &MemoryManager) -> Result<Vec<u8>, ChunkError> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BYTECODE_MAGIC);
+        bytes.push(BYTECODE_VERSION);
+        write_str(&mut bytes, &self.name);
+        let global_names = memory_manager.global_names();
+        write_len(&mut bytes, global_names.len());
+        for name in global_names.iter() {
+            write_str(&mut bytes, &name.to_string());
+        }
+        self.write_body(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Writes the constants, code and lines. Functions among the constants are written with their
+    /// own chunk nested inside.
+    fn write_body(&self, bytes: &mut Vec<u8>) -> Result<(), ChunkError> {
+        write_len(bytes, self.constants.len());
+        for (index, constant) in self.constants.iter().enumerate() {
+            match constant {
+                Value::Number(num) => {
+                    bytes.push(CONSTANT_NUMBER);
+                    bytes.extend_from_slice(&num.to_le_bytes());
+                }
+                Value::Boolean(b) => {
+                    bytes.push(CONSTANT_BOOLEAN);
+                    bytes.push(*b as u8);
+                }
+                Value::Nil => bytes.push(CONSTANT_NIL),
+                Value::Obj(Object::String(s)) => {
+                    bytes.push(CONSTANT_STRING);
+                    write_str(bytes, &s.to_string());
+                }
+                Value::Obj(Object::Function(function)) => {
+                    bytes.push(CONSTANT_FUNCTION);
+                    // Identifiers are never empty, so an empty name means an anonymous function
+                    let name = function.name().map(|n| n.to_string()).unwrap_or_default();
+                    write_str(bytes, &name);
+                    bytes.push(function.arity());
+                    let chunk = function.chunk();
+                    write_str(bytes, &chunk.name);
+                    chunk.write_body(bytes)?;
+                }
+                Value::Obj(Object::Native(_) | Object::Map(_)) => {
+                    return Err(ChunkError::UnserializableConstant { index });
+                }
+            }
+        }
+        write_len(bytes, self.code.len());
+        bytes.extend_from_slice(&self.code);
+        for line in self.lines.iter() {
+            bytes.extend_from_slice(&(*line as u64).to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Loads a chunk written by [`Chunk::to_bytes`], re-interning its strings and global names in
+    /// `memory_manager`. The result is validated before it is returned.
+    pub fn from_bytes(
+        bytes: &[u8],
+        memory_manager: &mut MemoryManager,
+    ) -> Result<Self, ChunkError> {
+        let mut reader = ByteReader { bytes };
+        if reader.take(BYTECODE_MAGIC.len())? != BYTECODE_MAGIC {
+            return Err(ChunkError::InvalidMagic);
+        }
+        let version = reader.take(1)?[0];
+        if version != BYTECODE_VERSION {
+            return Err(ChunkError::UnsupportedVersion(version));
+        }
+        let name = reader.read_str()?.to_string();
+
+        // Counts come from the file, so nothing is allocated up front for them
+        let global_count = reader.read_len()?;
+        let mut global_slots = Vec::new();
+        for _ in 0..global_count {
+            let name = reader.read_str()?;
+            let slot = memory_manager
+                .global_slot(name)
+                .ok_or(ChunkError::TooManyGlobals)?;
+            global_slots.push(slot);
+        }
+
+        let chunk = Self::read_body(&mut reader, name, memory_manager, &global_slots, 0)?;
+        if !reader.bytes.is_empty() {
+            return Err(ChunkError::TrailingBytes);
+        }
+        Ok(chunk)
     }
 
-    fn write_single_instruction(
-        &self,
-        iter: &mut impl Iterator<Item = (usize, (u8, usize))>,
-        result: &mut String,
-        opcode: u8,
-    ) {
-        write!(
-            result,
-            "{}",
-            if let Ok(opcode) = Opcode::try_from(opcode) {
-                match opcode {
-                    Opcode::Return
-                    | Opcode::Negate
-                    | Opcode::Add
-                    | Opcode::Subtract
-                    | Opcode::Multiply
-                    | Opcode::Divide
-                    | Opcode::True
-                    | Opcode::False
-                    | Opcode::Nil
-                    | Opcode::Not
-                    | Opcode::Equal
-                    | Opcode::Greater
-                    | Opcode::Less
-                    | Opcode::Print
-                    | Opcode::Pop => simple_instruction(opcode),
-                    Opcode::Constant
-                    | Opcode::DefineGlobal
-                    | Opcode::GetGlobal
-                    | Opcode::SetGlobal => self.constant_instruction(opcode, iter.next().map(code)),
-                    Opcode::GetLocal | Opcode::SetLocal => {
-                        self.byte_instruction(opcode, iter.next().map(code))
+    /// Reads the constants, code and lines. `depth` counts the functions this chunk is nested in,
+    /// so a crafted file can't recurse deep enough to overflow the stack.
+    fn read_body(
+        reader: &mut ByteReader,
+        name: String,
+        memory_manager: &mut MemoryManager,
+        global_slots: &[u32],
+        depth: usize,
+    ) -> Result<Self, ChunkError> {
+        let mut chunk = Chunk::new(name, memory_manager.alloc());
+        let constant_count = reader.read_len()?;
+        for _ in 0..constant_count {
+            let constant = match reader.take(1)?[0] {
+                CONSTANT_NUMBER => {
+                    Value::Number(f64::from_le_bytes(reader.take(8)?.try_into().unwrap()))
+                }
+                CONSTANT_BOOLEAN => Value::Boolean(reader.take(1)?[0] != 0),
+                CONSTANT_NIL => Value::Nil,
+                CONSTANT_STRING => {
+                    Value::Obj(Object::String(memory_manager.intern(reader.read_str()?)))
+                }
+                CONSTANT_FUNCTION => {
+                    if depth >= MAX_FUNCTION_DEPTH {
+                        return Err(ChunkError::TooDeeplyNested);
                     }
-                    Opcode::JumpIfFalse | Opcode::Jump | Opcode::Loop => {
-                        self.short_instruction(opcode, iter.next().map(code), iter.next().map(code))
+                    let name = reader.read_str()?;
+                    let name = (!name.is_empty()).then_some(name);
+                    let arity = reader.take(1)?[0];
+                    let chunk_name = reader.read_str()?.to_string();
+                    let function_chunk = Self::read_body(
+                        reader,
+                        chunk_name,
+                        memory_manager,
+                        global_slots,
+                        depth + 1,
+                    )?;
+                    let function = memory_manager.new_function(name, arity, function_chunk);
+                    Value::Obj(Object::Function(function))
+                }
+                tag => return Err(ChunkError::InvalidConstantTag(tag)),
+            };
+            chunk.constants.push(constant);
+        }
+
+        let code_len = reader.read_len()?;
+        let code = reader.take(code_len)?;
+        for &byte in code {
+            let line = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+            chunk.add_byte(byte, line as usize);
+        }
+
+        chunk.validate()?;
+        chunk.remap_globals(global_slots)?;
+        Ok(chunk)
+    }
+
+    /// Replaces the global slots in the instructions with `global_slots[slot]`, widening an
+    /// instruction to its long variant when its new slot doesn't fit in a byte. Jumps are adjusted
+    /// for the code that moved. The chunk must be valid.
+    fn remap_globals(&mut self, global_slots: &[u32]) -> Result<(), ChunkError> {
+        // Where each instruction starts after remapping, with one more entry for the end of the code
+        let mut new_offsets = vec![0; self.code.len() + 1];
+        let mut new_len = 0;
+        let mut instructions = Vec::new();
+        for (offset, instruction, line) in self.instructions() {
+            let remap = |slot: u32| {
+                global_slots
+                    .get(slot as usize)
+                    .copied()
+                    .ok_or(ChunkError::InvalidGlobalSlot { offset, slot })
+            };
+            let remapped = match instruction {
+                Instruction::Byte(
+                    opcode @ (Opcode::DefineGlobal | Opcode::GetGlobal | Opcode::SetGlobal),
+                    slot,
+                ) => {
+                    let slot = remap(slot as u32)?;
+                    match u8::try_from(slot) {
+                        Ok(slot) => Instruction::Byte(opcode, slot),
+                        Err(_) => Instruction::Long(opcode.long_global(), slot),
                     }
                 }
-            } else {
-                format!("Unknown opcode 0x{opcode:02x}")
+                Instruction::Long(
+                    opcode @ (Opcode::DefineGlobalLong
+                    | Opcode::GetGlobalLong
+                    | Opcode::SetGlobalLong),
+                    slot,
+                ) => Instruction::Long(opcode, remap(slot)?),
+                other => other,
+            };
+            new_offsets[offset] = new_len;
+            new_len += remapped.encoded_len();
+            instructions.push((offset, instruction, remapped, line));
+        }
+        new_offsets[self.code.len()] = new_len;
+
+        self.code.truncate(0);
+        self.lines.truncate(0);
+        for (offset, instruction, remapped, line) in instructions {
+            let remapped = match remapped {
+                Instruction::Short(opcode, jump) => {
+                    let next = offset + instruction.encoded_len();
+                    let jump = if let Opcode::Loop = opcode {
+                        new_offsets[next] - new_offsets[next - jump as usize]
+                    } else {
+                        new_offsets[next + jump as usize] - new_offsets[next]
+                    };
+                    let jump = u16::try_from(jump).map_err(|_| ChunkError::JumpTooLong {
+                        offset: new_offsets[offset],
+                    })?;
+                    Instruction::Short(opcode, jump)
+                }
+                other => other,
+            };
+            self.add_instruction(remapped, line);
+        }
+        Ok(())
+    }
+
+    /// Writes the chunk in a line-based text format that [`Chunk::assemble_text`] reads back. The
+    /// names of all globals known to `memory_manager` come first, as `.global "name"` lines. Then
+    /// each chunk is a `.chunk "name"` line, its constants, one instruction per line as the opcode
+    /// name, the operand if it has one and the source line (e.g. `CONSTANT 0 3`), and `.end`.
+    /// Function constants are followed by their own chunk. Fails like [`Chunk::to_bytes`] on
+    /// constants that can't be serialized.
+    pub fn disassemble_text(&self, memory_manager: &MemoryManager) -> Result<String, ChunkError> {
+        let mut result = String::new();
+        for name in memory_manager.global_names() {
+            writeln!(result, ".global {}", quote_text(&name.to_string())).unwrap();
+        }
+        self.write_text(&mut result)?;
+        Ok(result)
+    }
+
+    fn write_text(&self, result: &mut String) -> Result<(), ChunkError> {
+        writeln!(result, ".chunk {}", quote_text(&self.name)).unwrap();
+        for (index, constant) in self.constants.iter().enumerate() {
+            match constant {
+                // `Debug` keeps enough digits to parse back to the same number
+                Value::Number(num) => writeln!(result, ".constant number {num:?}").unwrap(),
+                Value::Boolean(b) => writeln!(result, ".constant bool {b}").unwrap(),
+                Value::Nil => writeln!(result, ".constant nil").unwrap(),
+                Value::Obj(Object::String(s)) => {
+                    writeln!(result, ".constant string {}", quote_text(&s.to_string())).unwrap()
+                }
+                Value::Obj(Object::Function(function)) => {
+                    let name = function.name().map(|n| n.to_string()).unwrap_or_default();
+                    writeln!(
+                        result,
+                        ".constant function {} {}",
+                        quote_text(&name),
+                        function.arity()
+                    )
+                    .unwrap();
+                    function.chunk().write_text(result)?;
+                }
+                Value::Obj(Object::Native(_) | Object::Map(_)) => {
+                    return Err(ChunkError::UnserializableConstant { index });
+                }
             }
-        )
-        .unwrap();
+        }
+        for (_, instruction, line) in self.instructions() {
+            let name = instruction.opcode().name();
+            match instruction {
+                Instruction::Simple(_) => writeln!(result, "{name} {line}"),
+                Instruction::Byte(_, operand) => writeln!(result, "{name} {operand} {line}"),
+                Instruction::Short(_, operand) => writeln!(result, "{name} {operand} {line}"),
+                Instruction::Long(_, operand) => writeln!(result, "{name} {operand} {line}"),
+            }
+            .unwrap();
+        }
+        writeln!(result, ".end").unwrap();
+        Ok(())
     }
 
-    pub fn disassemble_instruction_at(&self, idx: usize) -> Option<String> {
-        let mut iter = self.code_line_iter().enumerate().skip(idx);
+    /// Reads a chunk written by [`Chunk::disassemble_text`], re-interning its strings and global
+    /// names in `memory_manager`. Blank lines are ignored. The result is validated before it is
+    /// returned.
+    pub fn assemble_text(
+        text: &str,
+        memory_manager: &mut MemoryManager,
+    ) -> Result<Self, ChunkError> {
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| {
+                let words = split_text_words(line).map_err(|message| ChunkError::InvalidText {
+                    line: idx + 1,
+                    message,
+                })?;
+                Ok((idx + 1, words))
+            })
+            .filter(|line| !matches!(line, Ok((_, words)) if words.is_empty()))
+            .collect::<Result<Vec<_>, ChunkError>>()?;
+        let mut lines = lines.iter().peekable();
+
+        let mut global_slots = Vec::new();
+        while let Some((line, words)) = lines.next_if(|(_, words)| words[0] == ".global") {
+            let [_, name] = words.as_slice() else {
+                return Err(invalid_text(*line, "expected a global name"));
+            };
+            let slot = memory_manager
+                .global_slot(name)
+                .ok_or(ChunkError::TooManyGlobals)?;
+            global_slots.push(slot);
+        }
+
+        let chunk = Self::assemble_body(&mut lines, memory_manager, &global_slots)?;
+        if let Some((line, _)) = lines.next() {
+            return Err(invalid_text(*line, "text after the end of the chunk"));
+        }
+        Ok(chunk)
+    }
+
+    fn assemble_body<'t, I: Iterator<Item = &'t (usize, Vec<String>)>>(
+        lines: &mut I,
+        memory_manager: &mut MemoryManager,
+        global_slots: &[u32],
+    ) -> Result<Self, ChunkError> {
+        let (line, words) = lines.next().ok_or(ChunkError::UnexpectedEof)?;
+        let name = match words.as_slice() {
+            [keyword, name] if keyword == ".chunk" => name.clone(),
+            _ => return Err(invalid_text(*line, "expected a chunk name")),
+        };
+        let mut chunk = Chunk::new(name, memory_manager.alloc());
+
+        loop {
+            let (line, words) = lines.next().ok_or(ChunkError::UnexpectedEof)?;
+            let line = *line;
+            let words: Vec<&str> = words.iter().map(String::as_str).collect();
+            let constant = match words.as_slice() {
+                [".end"] => break,
+                [".constant", "number", num] => Value::Number(
+                    num.parse()
+                        .map_err(|_| invalid_text(line, "invalid number"))?,
+                ),
+                [".constant", "bool", b] => Value::Boolean(
+                    b.parse()
+                        .map_err(|_| invalid_text(line, "invalid boolean"))?,
+                ),
+                [".constant", "nil"] => Value::Nil,
+                [".constant", "string", s] => Value::Obj(Object::String(memory_manager.intern(s))),
+                [".constant", "function", name, arity] => {
+                    let arity = arity
+                        .parse()
+                        .map_err(|_| invalid_text(line, "invalid arity"))?;
+                    let function_chunk = Self::assemble_body(lines, memory_manager, global_slots)?;
+                    // Identifiers are never empty, so an empty name means an anonymous function
+                    let name = (!name.is_empty()).then_some(*name);
+                    let function = memory_manager.new_function(name, arity, function_chunk);
+                    Value::Obj(Object::Function(function))
+                }
+                [".constant", ..] => return Err(invalid_text(line, "invalid constant")),
+                [name, rest @ ..] => {
+                    let opcode = Opcode::from_name(name)
+                        .ok_or_else(|| invalid_text(line, "unknown opcode"))?;
+                    let operand_len = opcode.operand_len();
+                    let (operand, source_line) = match rest {
+                        [source_line] if operand_len == 0 => (None, source_line),
+                        [operand, source_line] if operand_len > 0 => (Some(operand), source_line),
+                        _ => return Err(invalid_text(line, "wrong number of operands")),
+                    };
+                    let source_line = source_line
+                        .parse()
+                        .map_err(|_| invalid_text(line, "invalid line number"))?;
+                    chunk.add_opcode(opcode, source_line);
+                    if let Some(operand) = operand {
+                        let operand: u32 = operand
+                            .parse()
+                            .map_err(|_| invalid_text(line, "invalid operand"))?;
+                        let bytes = operand.to_be_bytes();
+                        let (high, operand) = bytes.split_at(bytes.len() - operand_len);
+                        if high.iter().any(|&b| b != 0) {
+                            return Err(invalid_text(line, "operand out of range"));
+                        }
+                        for &byte in operand {
+                            chunk.add_byte(byte, source_line);
+                        }
+                    }
+                    continue;
+                }
+                [] => unreachable!("Blank lines are skipped"),
+            };
+            chunk.constants.push(constant);
+        }
 
+        chunk.validate()?;
+        chunk.remap_globals(global_slots)?;
+        Ok(chunk)
+    }
+
+    /// Human-readable listing of the code, one instruction per line. Code after the first
+    /// instruction that can't be decoded is reported as the decoding error.
+    pub fn disassemble(&self) -> String {
         let mut result = String::new();
 
-        if let Some((offset, (opcode, line))) = iter.next() {
+        let mut previous_line: Option<usize> = None;
+
+        writeln!(result, "== {} ==", self.name).unwrap();
+
+        let mut end = 0;
+        for (offset, instruction, line) in self.instructions() {
             write!(result, "0x{offset:04x} ").unwrap();
-            write!(result, "{line:04} ").unwrap();
-            self.write_single_instruction(&mut iter, &mut result, opcode);
-            Some(result)
-        } else {
-            None
+            match previous_line {
+                Some(prev_line) if prev_line >= line => write!(result, "   | ").unwrap(),
+                _ => {
+                    write!(result, "{line:04} ").unwrap();
+                    previous_line = Some(line);
+                }
+            }
+            writeln!(result, "{}", self.format_instruction(offset, instruction)).unwrap();
+            end = offset + instruction.encoded_len();
         }
+        if end < self.code.len() {
+            if let Err(err) = self.decode_at(end) {
+                writeln!(result, "0x{end:04x} {err}").unwrap();
+            }
+        }
+
+        result
     }
 
-    fn constant_instruction(&self, opcode: Opcode, operand: Option<u8>) -> String {
-        let value = if let Some(idx) = operand {
-            let value = self.get_constant(idx);
-            if let Some(value) = value {
-                format!("{} {}", idx, value)
-            } else {
-                format!("(index 0x{idx:02x} unknown)")
+    /// The instruction starting at `idx` with its line, or `None` if no instruction can be decoded
+    /// there.
+    pub fn disassemble_instruction_at(&self, idx: usize) -> Option<String> {
+        let instruction = self.decode_at(idx).ok()?;
+        Some(format!(
+            "0x{idx:04x} {:04} {}",
+            self.lines[idx],
+            self.format_instruction(idx, instruction)
+        ))
+    }
+
+    fn format_instruction(&self, offset: usize, instruction: Instruction) -> String {
+        match instruction {
+            Instruction::Simple(opcode) => format!("{opcode:?}"),
+            Instruction::Byte(opcode @ Opcode::Constant, idx) => match self.get_constant(idx) {
+                Some(value) => format!("{opcode:?} {idx} {value}"),
+                None => format!("{opcode:?} (index 0x{idx:02x} unknown)"),
+            },
+            Instruction::Byte(opcode, operand) => format!("{opcode:?} {operand}"),
+            Instruction::Long(opcode, operand) => format!("{opcode:?} {operand}"),
+            Instruction::Short(opcode, jump) => {
+                // Jumps are relative to the instruction after the operands
+                let next = offset + instruction.encoded_len();
+                let target = if let Opcode::Loop = opcode {
+                    next.checked_sub(jump as usize)
+                } else {
+                    Some(next + jump as usize)
+                };
+                match target {
+                    Some(target) => format!("{opcode:?} 0x{jump:04x} -> 0x{target:04x}"),
+                    None => format!("{opcode:?} 0x{jump:04x} -> (out of bounds)"),
+                }
             }
-        } else {
-            "(unknown)".to_string()
-        };
-        format!("{opcode:?} {value}")
+        }
     }
+}
 
-    fn byte_instruction(&self, opcode: Opcode, operand: Option<u8>) -> String {
-        let value = if let Some(idx) = operand {
-            format!("{}", idx)
-        } else {
-            "(unknown)".to_string()
-        };
-        format!("{opcode:?} {value}")
-    }
-
-    fn short_instruction(
-        &self,
-        opcode: Opcode,
-        operand_high: Option<u8>,
-        operand_low: Option<u8>,
-    ) -> String {
-        let value = if let Some((h, l)) = operand_high.zip(operand_low) {
-            let full = ((h as u16) << 8) | (l as u16);
-            format!("0x{full:04x}")
-        } else {
-            "(unknown)".to_string()
-        };
+const BYTECODE_MAGIC: &[u8] = b"LOXC";
+const BYTECODE_VERSION: u8 = 3;
+
+const CONSTANT_NUMBER: u8 = 0;
+const CONSTANT_BOOLEAN: u8 = 1;
+const CONSTANT_NIL: u8 = 2;
+const CONSTANT_STRING: u8 = 3;
+const CONSTANT_FUNCTION: u8 = 4;
 
-        format!("{opcode:?} {value}")
+/// Maximum number of functions a loaded chunk can be nested in.
+const MAX_FUNCTION_DEPTH: usize = 256;
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    write_len(bytes, s.len());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ChunkError> {
+        if self.bytes.len() < len {
+            return Err(ChunkError::UnexpectedEof);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn read_len(&mut self) -> Result<usize, ChunkError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn read_str(&mut self) -> Result<&'a str, ChunkError> {
+        let len = self.read_len()?;
+        std::str::from_utf8(self.take(len)?).map_err(|_| ChunkError::InvalidUtf8)
     }
 }
 
-fn simple_instruction(opcode: Opcode) -> String {
-    format!("{opcode:?}")
+fn invalid_text(line: usize, message: &'static str) -> ChunkError {
+    ChunkError::InvalidText { line, message }
 }
 
-fn code(a: (usize, (u8, usize))) -> u8 {
-    a.1 .0
+/// Quotes `s` for the text format, escaping backslashes, quotes and line breaks.
+fn quote_text(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Splits a line of the text format into words, unquoting the words written by [`quote_text`].
+fn split_text_words(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            loop {
+                match chars.next() {
+                    None => return Err("unterminated string"),
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => word.push('\n'),
+                        Some('r') => word.push('\r'),
+                        Some(c @ ('\\' | '"')) => word.push(c),
+                        _ => return Err("invalid escape"),
+                    },
+                    Some(c) => word.push(c),
+                }
+            }
+        } else {
+            word.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
 }
 
 impl Debug for Chunk {
@@ -302,7 +1123,7 @@ impl Debug for Chunk {
         writeln!(f, "{}", self.disassemble())?;
         writeln!(f, "Constants:")?;
         for (i, c) in self.constants.iter().enumerate() {
-            writeln!(f, "{i:04}: {c}")?;
+            writeln!(f, "{i:04}: {c:?}")?;
         }
         Ok(())
     }
@@ -315,3 +1136,601 @@ impl Deref for Chunk {
         &self.code
     }
 }
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ChunkError {
+    #[error("invalid opcode at 0x{offset:04x}")]
+    InvalidOpcode { offset: usize },
+    #[error("missing operand for instruction at 0x{offset:04x}")]
+    MissingOperand { offset: usize },
+    #[error("jump at 0x{offset:04x} does not land on an instruction")]
+    InvalidJumpTarget { offset: usize },
+    #[error("constant at 0x{offset:04x} refers to unknown constant {index}")]
+    InvalidConstant { offset: usize, index: u8 },
+    #[error("global at 0x{offset:04x} refers to unknown global {slot}")]
+    InvalidGlobalSlot { offset: usize, slot: u32 },
+    #[error("not a Lox bytecode file")]
+    InvalidMagic,
+    #[error("unsupported bytecode version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unexpected end of bytecode")]
+    UnexpectedEof,
+    #[error("code doesn't end in a return")]
+    MissingReturn,
+    #[error("unexpected bytes after the end of the chunk")]
+    TrailingBytes,
+    #[error("invalid UTF-8 in bytecode string")]
+    InvalidUtf8,
+    #[error("invalid constant tag {0}")]
+    InvalidConstantTag(u8),
+    #[error("too many global variables")]
+    TooManyGlobals,
+    #[error("functions are nested more than {MAX_FUNCTION_DEPTH} deep")]
+    TooDeeplyNested,
+    #[error("jump at 0x{offset:04x} is too long after widening global instructions")]
+    JumpTooLong { offset: usize },
+    #[error("line {line}: {message}")]
+    InvalidText { line: usize, message: &'static str },
+    #[error("constant {index} is a native or a map, which can't be serialized")]
+    UnserializableConstant { index: usize },
+}
+
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum StackBalanceError {
+    #[error("invalid opcode at 0x{offset:04x}")]
+    InvalidOpcode { offset: usize },
+    #[error("control flow leaves the chunk at 0x{offset:04x}")]
+    OutOfBounds { offset: usize },
+    #[error("stack underflow at 0x{offset:04x}")]
+    Underflow { offset: usize },
+    #[error("stack depth at 0x{offset:04x} is {found}, but was {expected} on another path")]
+    Mismatch {
+        offset: usize,
+        expected: isize,
+        found: isize,
+    },
+    #[error("{depth} value(s) on the stack at return at 0x{offset:04x}, expected just the result")]
+    Unbalanced { offset: usize, depth: isize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::memory::hash_table::HashTable;
+    use crate::memory::MemoryManager;
+    use crate::scanner::Scanner;
+
+    fn compile_source(source: &str) -> Chunk {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap()
+    }
+
+    #[test]
+    fn compiled_program_is_balanced() {
+        let chunk = compile_source(
+            r#"
+var a = 1;
+{
+  var b = a + 2;
+  if (b > 2 and a < 2 or false) print b; else print a;
+  for (var i = 0; i < 3; i = i + 1) {
+    var c = i;
+    a = a + c;
+  }
+}
+while (a > 0) a = a - 1;
+"#,
+        );
+        assert_eq!(chunk.check_stack_balance(), Ok(()));
+    }
+
+    #[test]
+    fn compiled_program_is_valid() {
+        let chunk = compile_source(
+            "var a = 0; while (a < 10) { if (a > 5 or a == 2) print a; a = a + 1; }",
+        );
+        assert_eq!(chunk.validate(), Ok(()));
+    }
+
+    #[test]
+    fn constants_are_exposed() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        // The strings among the constants live as long as the memory manager
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let source = r#"var a = "hi"; print a + "!"; print 1.5; print 2;"#;
+        let chunk = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+        assert_eq!(chunk.name(), "main");
+        let constants: Vec<String> = chunk.constants().iter().map(|c| c.to_string()).collect();
+        // Globals live in slots and small integers are immediate, so neither takes a constant
+        assert_eq!(constants, ["hi", "!", "1.5"]);
+        for (_, instruction, _) in chunk.instructions() {
+            if let Instruction::Byte(Opcode::Constant, index) = instruction {
+                assert!(chunk.constants().get(index as usize).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_chunk_is_rejected() {
+        let mut chunk = compile_source("var a = 0; while (a < 10) a = a + 1;");
+        let last = chunk.len() - 1;
+        chunk.code[last] = 0xFF;
+        assert_eq!(
+            chunk.validate(),
+            Err(ChunkError::InvalidOpcode { offset: last })
+        );
+    }
+
+    #[test]
+    fn hand_built_chunk_is_valid() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        let constant = chunk.add_constant(Value::Number(1.0)).unwrap();
+        chunk.add_opcode_and_operand(Opcode::Constant, constant, 1);
+        let jump = chunk.add_dummy_jump(Opcode::JumpIfFalse, 1);
+        chunk.add_opcode(Opcode::Pop, 1);
+        chunk.patch_jump(jump).unwrap();
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(chunk.validate(), Ok(()));
+    }
+
+    #[test]
+    fn code_must_end_in_return() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        assert_eq!(chunk.validate(), Err(ChunkError::MissingReturn));
+        chunk.add_opcode(Opcode::Return, 1);
+        chunk.add_opcode(Opcode::Nil, 1);
+        assert_eq!(chunk.validate(), Err(ChunkError::MissingReturn));
+    }
+
+    #[test]
+    fn unknown_constant_is_rejected() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode_and_operand(Opcode::Constant, 3, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(
+            chunk.validate(),
+            Err(ChunkError::InvalidConstant {
+                offset: 0,
+                index: 3
+            })
+        );
+    }
+
+    #[test]
+    fn missing_operand_is_rejected() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode(Opcode::Return, 1);
+        chunk.add_opcode(Opcode::Jump, 1);
+        chunk.add_byte(0, 1);
+        assert_eq!(
+            chunk.validate(),
+            Err(ChunkError::MissingOperand { offset: 1 })
+        );
+    }
+
+    #[test]
+    fn jump_into_operand_is_rejected() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode(Opcode::Jump, 1);
+        chunk.add_byte(0, 1);
+        chunk.add_byte(1, 1);
+        chunk.add_opcode_and_operand(Opcode::GetLocal, 0, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(
+            chunk.validate(),
+            Err(ChunkError::InvalidJumpTarget { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn loop_before_start_is_rejected() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode(Opcode::Loop, 1);
+        chunk.add_byte(0, 1);
+        chunk.add_byte(10, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(
+            chunk.validate(),
+            Err(ChunkError::InvalidJumpTarget { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let chunk = compile(
+            &mut Scanner::new(r#"var a = "hi"; var b = 1.5; print a; print b == 1.5 or nil;"#)
+                .iter(),
+            &mut memory_manager,
+        )
+        .unwrap();
+        let bytes = chunk.to_bytes(&memory_manager).unwrap();
+
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        // Occupy slot 0 so the loaded globals have to be remapped
+        memory_manager.global_slot("other").unwrap();
+        let loaded = Chunk::from_bytes(&bytes, &mut memory_manager).unwrap();
+        assert_eq!(loaded.name, chunk.name);
+        assert_eq!(&*loaded.lines, &*chunk.lines);
+        assert_eq!(loaded.constants.len(), chunk.constants.len());
+        // Written again, the bytes only grow by the name of the extra global
+        let mut other = Vec::new();
+        write_str(&mut other, "other");
+        assert_eq!(
+            loaded.to_bytes(&memory_manager).unwrap().len(),
+            bytes.len() + other.len()
+        );
+    }
+
+    #[test]
+    fn corrupted_counts_are_errors() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let chunk = compile(
+            &mut Scanner::new("var a = 1.5; print a;").iter(),
+            &mut memory_manager,
+        )
+        .unwrap();
+        let bytes = chunk.to_bytes(&memory_manager).unwrap();
+        let mut reader = ByteReader { bytes: &bytes };
+        reader.take(BYTECODE_MAGIC.len() + 1).unwrap();
+        reader.read_str().unwrap();
+        let global_count_at = bytes.len() - reader.bytes.len();
+        for _ in 0..reader.read_len().unwrap() {
+            reader.read_str().unwrap();
+        }
+        let constant_count_at = bytes.len() - reader.bytes.len();
+        // The code is followed by a line for every byte of it
+        let code_len_at = bytes.len() - chunk.len() * 9 - 4;
+
+        for offset in [global_count_at, constant_count_at, code_len_at] {
+            let mut corrupted = bytes.clone();
+            corrupted[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            let alloc = Allocator::new();
+            let strings = HashTable::new(alloc.clone());
+            let mut memory_manager = MemoryManager::new(alloc, strings);
+            let err = Chunk::from_bytes(&corrupted, &mut memory_manager).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    ChunkError::UnexpectedEof | ChunkError::InvalidConstantTag(_)
+                ),
+                "count at {offset}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn deeply_nested_functions_are_errors() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BYTECODE_MAGIC);
+        bytes.push(BYTECODE_VERSION);
+        write_str(&mut bytes, "script");
+        write_len(&mut bytes, 0);
+        for _ in 0..200_000 {
+            write_len(&mut bytes, 1);
+            bytes.push(CONSTANT_FUNCTION);
+            write_str(&mut bytes, "f");
+            bytes.push(0);
+            write_str(&mut bytes, "f");
+        }
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        assert_eq!(
+            Chunk::from_bytes(&bytes, &mut memory_manager).unwrap_err(),
+            ChunkError::TooDeeplyNested
+        );
+    }
+
+    #[test]
+    fn maps_and_natives_are_not_serializable() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let mut chunk = Chunk::new("test".to_string(), alloc);
+        chunk.add_constant(Value::Nil);
+        let map = memory_manager.new_map();
+        chunk.add_constant(Value::Obj(Object::Map(map)));
+        assert_eq!(
+            chunk.to_bytes(&memory_manager),
+            Err(ChunkError::UnserializableConstant { index: 1 })
+        );
+        assert_eq!(
+            chunk.disassemble_text(&memory_manager),
+            Err(ChunkError::UnserializableConstant { index: 1 })
+        );
+    }
+
+    #[test]
+    fn remapped_globals_widen_to_long_opcodes() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let source = "var a = 0;\nwhile (a < 3) a = a + 1;\nprint a;";
+        let chunk = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+        let bytes = chunk.to_bytes(&memory_manager).unwrap();
+
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        for i in 0..300 {
+            memory_manager.global_slot(&format!("other{i}")).unwrap();
+        }
+        let loaded = Chunk::from_bytes(&bytes, &mut memory_manager).unwrap();
+        assert_eq!(loaded.validate(), Ok(()));
+        assert!(loaded
+            .instructions()
+            .any(|(_, instruction, _)| instruction.opcode() == Opcode::SetGlobalLong));
+        assert_eq!(run_chunk(&loaded, memory_manager), "3\n");
+    }
+
+    fn run_chunk(chunk: &Chunk, memory_manager: MemoryManager) -> String {
+        let alloc = memory_manager.alloc();
+        let mut out = Vec::new();
+        crate::vm::VM::new(&mut out, memory_manager, alloc)
+            .run(chunk)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let source = r#"
+var greeting = "back\slash
+and newline";
+fun count(n) { var i = 0; while (i < n) i = i + 1; return i; }
+var apply = fun (f, x) { return f(x); };
+print greeting;
+print apply(count, 3) + 0.1;
+print -0 == 0 and !nil;"#;
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let chunk = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+        let text = chunk.disassemble_text(&memory_manager).unwrap();
+        let expected = run_chunk(&chunk, memory_manager);
+
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let assembled = Chunk::assemble_text(&text, &mut memory_manager).unwrap();
+        assert_eq!(assembled.disassemble_text(&memory_manager).unwrap(), text);
+        assert_eq!(run_chunk(&assembled, memory_manager), expected);
+    }
+
+    #[test]
+    fn assembled_text_behaves_like_compiled_source() {
+        let source = "var a = 1.5; if (a > 1) print a * 2; else print nil;";
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let compiled = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+        let expected = run_chunk(&compiled, memory_manager);
+
+        // Written by hand, with the globals in a different order than the compiler uses
+        let text = r#"
+.global "print"
+.global "a"
+.chunk "main"
+.constant number 1.5
+
+CONSTANT 0 1
+DEFINE_GLOBAL 1 1
+GET_GLOBAL 1 1
+SMALL_INT 1 1
+GREATER 1
+JUMP_IF_FALSE 14 1
+POP 1
+GET_GLOBAL 0 1
+GET_GLOBAL 1 1
+SMALL_INT 2 1
+MULTIPLY 1
+CALL 1 1
+POP 1
+JUMP 7 1
+POP 1
+GET_GLOBAL 0 1
+NIL 1
+CALL 1 1
+POP 1
+NIL 1
+RETURN 1
+.end
+"#;
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let assembled = Chunk::assemble_text(text, &mut memory_manager).unwrap();
+        assert_eq!(run_chunk(&assembled, memory_manager), expected);
+        assert_eq!(expected, "3\n");
+    }
+
+    #[test]
+    fn bad_text_is_rejected() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let mut assemble = |text| Chunk::assemble_text(text, &mut memory_manager).unwrap_err();
+        assert_eq!(
+            assemble(".chunk \"main\"\nFROB 1\n.end"),
+            invalid_text(2, "unknown opcode")
+        );
+        assert_eq!(
+            assemble(".chunk \"main\"\nCONSTANT 1\n.end"),
+            invalid_text(2, "wrong number of operands")
+        );
+        assert_eq!(
+            assemble(".chunk \"main\"\nPOP_N 256 1\n.end"),
+            invalid_text(2, "operand out of range")
+        );
+        assert_eq!(
+            assemble(".chunk \"main\"\n.constant string \"open\n.end"),
+            invalid_text(2, "unterminated string")
+        );
+        assert_eq!(
+            assemble(".chunk \"main\"\nRETURN 1"),
+            ChunkError::UnexpectedEof
+        );
+        assert_eq!(
+            assemble(".chunk \"main\"\nCONSTANT 0 1\nRETURN 1\n.end"),
+            ChunkError::InvalidConstant {
+                offset: 0,
+                index: 0
+            }
+        );
+    }
+
+    #[test]
+    fn opcode_names_are_unique() {
+        let opcodes: Vec<_> = (0..=u8::MAX)
+            .filter_map(|byte| Opcode::try_from(byte).ok())
+            .collect();
+        for opcode in &opcodes {
+            assert_eq!(Opcode::from_name(opcode.name()), Some(*opcode));
+        }
+        assert_eq!(Opcode::from_name("Constant"), None);
+    }
+
+    #[test]
+    fn bad_bytes_are_rejected() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        assert_eq!(
+            Chunk::from_bytes(b"NOPE", &mut memory_manager).unwrap_err(),
+            ChunkError::InvalidMagic
+        );
+        assert_eq!(
+            Chunk::from_bytes(b"LOXC\x03", &mut memory_manager).unwrap_err(),
+            ChunkError::UnexpectedEof
+        );
+        assert_eq!(
+            Chunk::from_bytes(b"LOXC\x09", &mut memory_manager).unwrap_err(),
+            ChunkError::UnsupportedVersion(9)
+        );
+    }
+
+    #[test]
+    fn extra_push_is_flagged() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode(Opcode::True, 1);
+        chunk.add_opcode(Opcode::Nil, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(
+            chunk.check_stack_balance(),
+            Err(StackBalanceError::Unbalanced {
+                offset: 2,
+                depth: 2
+            })
+        );
+    }
+
+    #[test]
+    fn underflow_is_flagged() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode(Opcode::Pop, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(
+            chunk.check_stack_balance(),
+            Err(StackBalanceError::Underflow { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn mismatched_branches_are_flagged() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode(Opcode::True, 1);
+        let jump = chunk.add_dummy_jump(Opcode::JumpIfFalse, 1);
+        chunk.add_opcode(Opcode::Pop, 1);
+        chunk.patch_jump(jump).unwrap();
+        chunk.add_opcode(Opcode::Nil, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert!(matches!(
+            chunk.check_stack_balance(),
+            Err(StackBalanceError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn wide_global_slots_use_long_opcodes() {
+        let alloc = Allocator::new();
+        let mut chunk = Chunk::new("test".to_string(), alloc);
+        chunk.add_global_opcode(Opcode::GetGlobal, 255, 1);
+        chunk.add_global_opcode(Opcode::SetGlobal, 0x012345, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(chunk.validate(), Ok(()));
+        let disassembly = chunk.disassemble();
+        assert!(disassembly.contains("GetGlobal 255\n"), "{disassembly}");
+        assert!(
+            disassembly.contains("SetGlobalLong 74565\n"),
+            "{disassembly}"
+        );
+    }
+
+    #[test]
+    fn instructions_decodes_operands() {
+        let chunk = compile_source("var a = 1;\nwhile (a < 2) a = a + 1;");
+        let instructions: Vec<_> = chunk.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                (0x00, Instruction::Byte(Opcode::SmallInt, 1), 1),
+                (0x02, Instruction::Byte(Opcode::DefineGlobal, 0), 1),
+                (0x04, Instruction::Byte(Opcode::GetGlobal, 0), 2),
+                (0x06, Instruction::Byte(Opcode::SmallInt, 2), 2),
+                (0x08, Instruction::Simple(Opcode::Less), 2),
+                (0x09, Instruction::Short(Opcode::JumpIfFalse, 12), 2),
+                (0x0c, Instruction::Simple(Opcode::Pop), 2),
+                (0x0d, Instruction::Byte(Opcode::GetGlobal, 0), 2),
+                (0x0f, Instruction::Byte(Opcode::SmallInt, 1), 2),
+                (0x11, Instruction::Simple(Opcode::Add), 2),
+                (0x12, Instruction::Byte(Opcode::SetGlobal, 0), 2),
+                (0x14, Instruction::Simple(Opcode::Pop), 2),
+                (0x15, Instruction::Short(Opcode::Loop, 20), 2),
+                (0x18, Instruction::Simple(Opcode::Pop), 2),
+                (0x19, Instruction::Simple(Opcode::Nil), 2),
+                (0x1a, Instruction::Simple(Opcode::Return), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembly_resolves_loop_target() {
+        let chunk = compile_source("var i = 0;\nwhile (i < 3) i = i + 1;");
+        let disassembly = chunk.disassemble();
+        let loop_line = disassembly
+            .lines()
+            .find(|l| l.contains("Loop"))
+            .expect("no Loop instruction");
+        assert!(loop_line.ends_with("Loop 0x0014 -> 0x0004"), "{loop_line}");
+        assert!(disassembly.contains("JumpIfFalse 0x000c -> 0x0018"));
+    }
+
+    #[test]
+    fn disassembly_reports_undecodable_code() {
+        let mut chunk = Chunk::new("test".to_string(), Allocator::new());
+        chunk.add_opcode(Opcode::Nil, 1);
+        chunk.add_opcode(Opcode::PopN, 2);
+        let disassembly = chunk.disassemble();
+        assert_eq!(
+            disassembly,
+            "== test ==\n0x0000 0001 Nil\n0x0001 missing operand for instruction at 0x0001\n"
+        );
+        assert_eq!(
+            chunk.disassemble_instruction_at(0),
+            Some("0x0000 0001 Nil".to_string())
+        );
+        assert_eq!(chunk.disassemble_instruction_at(1), None);
+    }
+}
@@ -1,9 +1,9 @@
 use crate::chunk::{Chunk, Opcode};
 use crate::memory::{MemoryManager, Object};
-use crate::scanner::{ScanError, ScanResult, Token, TokenContents};
+use crate::scanner::{number_value, ScanError, ScanResult, Token, TokenContents};
 use crate::value::Value;
 use arrayvec::ArrayVec;
-use log::trace;
+use log::{trace, warn};
 use std::fmt::{Display, Formatter};
 use std::iter::Peekable;
 use std::num::NonZeroUsize;
@@ -12,10 +12,13 @@ use thiserror::Error;
 type CompileResult<A> = Result<A, CompileErrors>;
 
 const MAX_LOCALS: usize = 256;
+// Local slots are encoded in a single byte operand
+const _: () = assert!(MAX_LOCALS <= u8::MAX as usize + 1);
+pub const DEFAULT_MAX_ERRORS: usize = 25;
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Ord, Eq)]
-enum BindingPower {
+pub(crate) enum BindingPower {
     None,
     Assignment,
     Or,
@@ -26,77 +29,276 @@ enum BindingPower {
     Factor,
     Unary,
     Call,
-    Primary,
 }
 
-impl BindingPower {
-    fn previous(&self) -> Self {
-        use BindingPower::*;
-        match self {
-            None => unreachable!(),
-            Assignment => None,
-            Or => Assignment,
-            And => Or,
-            Equality => And,
-            Comparison => Equality,
-            Term => Comparison,
-            Factor => Term,
-            Unary => Factor,
-            Call => Unary,
-            Primary => Call,
+pub fn compile<'a, 'b>(
+    iter: &'b mut impl Iterator<Item = ScanResult<Token<'a>>>,
+    memory_manager: &'b mut MemoryManager,
+) -> CompileResult<Chunk> {
+    compile_with_max_errors(iter, memory_manager, DEFAULT_MAX_ERRORS)
+}
+
+/// Compiles, stopping once `max_errors` errors have been collected.
+pub fn compile_with_max_errors<'a, 'b>(
+    iter: &'b mut impl Iterator<Item = ScanResult<Token<'a>>>,
+    memory_manager: &'b mut MemoryManager,
+    max_errors: usize,
+) -> CompileResult<Chunk> {
+    let mut compiler = Compiler::new(memory_manager).with_max_errors(max_errors);
+    let result = compiler.feed(iter);
+    log_warnings(compiler.warnings());
+    result?;
+    Ok(compiler.finish())
+}
+
+/// Like [`compile`], but hands each lint warning to `on_warning` instead of logging it. Warnings
+/// are reported even if compilation fails.
+pub fn compile_with_warnings<'a, 'b>(
+    iter: &'b mut impl Iterator<Item = ScanResult<Token<'a>>>,
+    memory_manager: &'b mut MemoryManager,
+    on_warning: impl FnMut(&CompileError),
+) -> CompileResult<Chunk> {
+    let mut compiler = Compiler::new(memory_manager);
+    let result = compiler.feed(iter);
+    compiler.warnings().iter().for_each(on_warning);
+    result?;
+    Ok(compiler.finish())
+}
+
+pub(crate) fn log_warnings(warnings: &[CompileError]) {
+    for warning in warnings {
+        warn!("{warning}");
+    }
+}
+
+/// Compiles source handed to it piece by piece into one chunk, for a REPL that runs what it has
+/// seen so far. Globals declared by earlier pieces stay known to later ones. Embedders get one from
+/// [`Session::compiler`](crate::Session::compiler).
+pub struct Compiler<'m> {
+    chunk: Chunk,
+    memory_manager: &'m mut MemoryManager,
+    max_errors: usize,
+    warnings: Vec<CompileError>,
+}
+
+impl<'m> Compiler<'m> {
+    pub(crate) fn new(memory_manager: &'m mut MemoryManager) -> Self {
+        let chunk = Chunk::new("main".to_string(), memory_manager.alloc());
+        Self {
+            chunk,
+            memory_manager,
+            max_errors: DEFAULT_MAX_ERRORS,
+            warnings: Vec::new(),
         }
     }
+
+    pub fn with_max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Compiles a run of complete declarations onto the end of the chunk. On error nothing is
+    /// added, so the pieces fed before can still be finished and run.
+    pub fn feed<'a>(
+        &mut self,
+        iter: &mut impl Iterator<Item = ScanResult<Token<'a>>>,
+    ) -> CompileResult<()> {
+        let (code_len, constants_len) = (self.chunk.len(), self.chunk.constants_len());
+        let chunk = std::mem::replace(
+            &mut self.chunk,
+            Chunk::new("main".to_string(), self.memory_manager.alloc()),
+        );
+        let mut parser = Parser::new(iter, chunk, self.memory_manager, self.max_errors);
+        let result = parser.compile();
+        let Parser {
+            mut chunk,
+            warnings,
+            ..
+        } = parser;
+        self.warnings.extend(warnings);
+        if result.is_err() {
+            chunk.truncate(code_len, constants_len);
+        }
+        self.chunk = chunk;
+        result
+    }
+
+    /// Lints found in everything fed so far, such as unused locals. They don't stop compilation.
+    pub fn warnings(&self) -> &[CompileError] {
+        &self.warnings
+    }
+
+    pub fn finish(self) -> Chunk {
+        finish_chunk(self.chunk)
+    }
 }
 
-pub fn compile<'a, 'b>(
+/// Like [`compile`], but lint warnings such as an unused local or a possibly infinite `for` loop
+/// are reported as errors instead of only being logged.
+pub fn compile_strict<'a, 'b>(
     iter: &'b mut impl Iterator<Item = ScanResult<Token<'a>>>,
     memory_manager: &'b mut MemoryManager,
 ) -> CompileResult<Chunk> {
     let chunk = Chunk::new("main".to_string(), memory_manager.alloc());
-    let mut compiler = Compiler::new(iter, chunk, memory_manager);
-    compiler.compile()?;
-    let Compiler { mut chunk, .. } = compiler;
+    let mut parser = Parser::new(iter, chunk, memory_manager, DEFAULT_MAX_ERRORS);
+    parser.strict = true;
+    parser.compile()?;
+    let Parser { chunk, .. } = parser;
 
-    // TODO
-    chunk.add_opcode(Opcode::Return, 0);
+    Ok(finish_chunk(chunk))
+}
+
+/// Compiles a single expression, without trailing semicolon, into a chunk that prints its value.
+pub fn compile_expression<'a, 'b>(
+    iter: &'b mut impl Iterator<Item = ScanResult<Token<'a>>>,
+    memory_manager: &'b mut MemoryManager,
+) -> CompileResult<Chunk> {
+    let chunk = Chunk::new("main".to_string(), memory_manager.alloc());
+    let mut parser = Parser::new(iter, chunk, memory_manager, DEFAULT_MAX_ERRORS);
+    let line = parser.peek_token()?.line;
+    parser.emit_print_callee(line)?;
+    parser.expression()?;
+    if let Some(token) = parser.iter.next() {
+        let token = token?;
+        return Err(ParseError::UnexpectedToken(token.line, token.contents.to_string()).into());
+    }
+    parser.emit_print_call(line);
+    let Parser {
+        chunk, warnings, ..
+    } = parser;
+    log_warnings(&warnings);
+
+    Ok(finish_chunk(chunk))
+}
+
+/// Ends the script with an implicit `return nil;` on its last line, so running it has a result.
+fn finish_chunk(mut chunk: Chunk) -> Chunk {
+    let line = match chunk.len() {
+        0 => 1,
+        len => chunk.line_for(len - 1),
+    };
+    chunk.add_opcode(Opcode::Nil, line);
+    chunk.add_opcode(Opcode::Return, line);
 
     trace!("Emitting chunk:\n{:?}", &chunk);
-    Ok(chunk)
+    #[cfg(debug_assertions)]
+    {
+        if let Err(e) = chunk.validate() {
+            panic!("Compiler emitted an invalid chunk: {e}\n{chunk:?}");
+        }
+        if let Err(e) = chunk.check_stack_balance() {
+            panic!("Compiler emitted an unbalanced chunk: {e}\n{chunk:?}");
+        }
+    }
+    chunk
 }
 
-struct Compiler<'a, 'b> {
+fn finish_function_chunk(chunk: &Chunk) {
+    trace!("Emitting function chunk:\n{:?}", chunk);
+    #[cfg(debug_assertions)]
+    {
+        if let Err(e) = chunk.validate() {
+            panic!("Compiler emitted an invalid chunk: {e}\n{chunk:?}");
+        }
+        if let Err(e) = chunk.check_function_stack_balance() {
+            panic!("Compiler emitted an unbalanced chunk: {e}\n{chunk:?}");
+        }
+    }
+}
+
+struct Parser<'a, 'b> {
     iter: Peekable<&'b mut dyn Iterator<Item = ScanResult<Token<'a>>>>,
     chunk: Chunk,
     memory_manager: &'b mut MemoryManager,
     errors: CompileErrors,
+    /// Lints that aren't errors, unless `strict` is set.
+    warnings: Vec<CompileError>,
     locals: ArrayVec<Local<'a>, MAX_LOCALS>,
     scope_depth: usize,
+    strict: bool,
+    /// Loops enclosing the code being compiled, innermost last.
+    loops: Vec<LoopContext>,
+    /// Identifiers compiled while inside a `for` loop, and whether they were assigned to.
+    identifier_log: Vec<(String, bool)>,
+    log_identifiers: usize,
+    /// Offset of the last `Call` emitted, to spot calls in tail position.
+    last_call: Option<usize>,
+    /// Code and constant lengths from before the left operand of the infix operator being
+    /// compiled, so constant operands can be folded.
+    operand_start: (usize, usize),
+    /// Functions whose compilation is suspended while a nested function is compiled, innermost
+    /// last. Empty at the top level.
+    enclosing: Vec<FunctionState<'a>>,
+}
+
+/// The parts of the compiler that belong to a single function.
+struct FunctionState<'a> {
+    chunk: Chunk,
+    locals: ArrayVec<Local<'a>, MAX_LOCALS>,
+    scope_depth: usize,
+    loops: Vec<LoopContext>,
+    last_call: Option<usize>,
+}
+
+#[derive(Debug)]
+struct LoopContext {
+    /// Where `continue` jumps to.
+    continue_target: usize,
+    /// Scope depth of the loop itself, locals deeper than this are popped on `break`/`continue`.
+    scope_depth: usize,
+    break_jumps: Vec<usize>,
 }
 
 #[derive(Debug)]
 struct Local<'a> {
     name: &'a str,
     depth: Option<NonZeroUsize>,
+    /// Line of the `var` declaring it, until the local is first referenced. Always `None` for
+    /// parameters and hidden locals, which aren't reported when unused.
+    unreferenced: Option<usize>,
 }
 
-impl<'a, 'b> Compiler<'a, 'b> {
+impl<'a, 'b> Parser<'a, 'b> {
     fn new(
         iter: &'b mut impl Iterator<Item = ScanResult<Token<'a>>>,
         chunk: Chunk,
         memory_manager: &'b mut MemoryManager,
+        max_errors: usize,
     ) -> Self {
         let iter: &mut dyn Iterator<Item = ScanResult<Token<'a>>> = iter;
         Self {
             iter: iter.peekable(),
             chunk,
             memory_manager,
-            errors: CompileErrors::default(),
+            errors: CompileErrors::with_limit(max_errors),
+            warnings: Vec::new(),
             locals: ArrayVec::new(),
             scope_depth: 0,
+            strict: false,
+            loops: Vec::new(),
+            identifier_log: Vec::new(),
+            log_identifiers: 0,
+            last_call: None,
+            operand_start: (0, 0),
+            enclosing: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, warning: ParseError) {
+        if self.strict {
+            self.errors.push(warning.into());
+        } else {
+            self.warnings.push(warning.into());
+        }
+    }
+
+    fn warn_if_unreferenced(&mut self, local: &Local) {
+        if let Some(line) = local.unreferenced {
+            self.warn(ParseError::UnusedLocal(line, local.name.to_string()));
         }
     }
 
-    fn next_token(&mut self) -> CompileResult<Token> {
+    fn next_token(&mut self) -> CompileResult<Token<'a>> {
         match self.iter.next() {
             Some(token) => match token {
                 Ok(token) => Ok(token),
@@ -106,7 +308,7 @@ impl<'a, 'b> Compiler<'a, 'b> {
         }
     }
 
-    fn peek_token(&mut self) -> CompileResult<&Token> {
+    fn peek_token(&mut self) -> CompileResult<&Token<'a>> {
         match self.iter.peek() {
             Some(token) => match token {
                 Ok(token) => Ok(token),
@@ -118,11 +320,15 @@ impl<'a, 'b> Compiler<'a, 'b> {
 
     fn compile(&mut self) -> CompileResult<()> {
         while let Some(peeked) = self.iter.peek() {
+            if self.errors.is_full() {
+                break;
+            }
             match peeked {
                 Ok(_) => self.declaration()?,
                 Err(e) => {
+                    // Skip the bad token and carry on with whatever follows it
                     self.errors.push(e.clone().into());
-                    break;
+                    let _ = self.iter.next();
                 }
             }
         }
@@ -135,12 +341,17 @@ impl<'a, 'b> Compiler<'a, 'b> {
     }
 
     fn declaration(&mut self) -> CompileResult<()> {
-        let contents = &self.iter.peek().unwrap().as_ref().unwrap().contents;
-        let result = if *contents == TokenContents::Var {
-            let _ = self.iter.next();
-            self.var_declaration()
-        } else {
-            self.statement()
+        let result = match self.peek_token().map(|token| token.contents.clone()) {
+            Ok(TokenContents::Var) => {
+                let _ = self.iter.next();
+                self.var_declaration()
+            }
+            Ok(TokenContents::Fun) => {
+                let _ = self.iter.next();
+                self.fun_declaration()
+            }
+            Ok(_) => self.statement(),
+            Err(e) => Err(e),
         };
         if let Err(e) = result {
             self.synchronize(e);
@@ -171,8 +382,23 @@ impl<'a, 'b> Compiler<'a, 'b> {
     }
 
     fn var_declaration(&mut self) -> CompileResult<()> {
-        let mut errors = CompileErrors::new();
+        let line = match self.iter.peek() {
+            Some(Ok(token)) => token.line,
+            _ => 0,
+        };
         let constant_index = self.parse_variable()?;
+        if constant_index.is_none() {
+            let local = self.locals.last_mut().expect("Local was just declared");
+            // A leading underscore marks a local as deliberately unused
+            if !local.name.starts_with('_') {
+                local.unreferenced = Some(line);
+            }
+        }
+        self.var_initializer(constant_index)
+    }
+
+    fn var_initializer(&mut self, constant_index: Option<u32>) -> CompileResult<()> {
+        let mut errors = CompileErrors::new();
         if let Some(Ok(token)) = self.iter.peek() {
             match token.contents {
                 TokenContents::Equal => {
@@ -199,30 +425,141 @@ impl<'a, 'b> Compiler<'a, 'b> {
         }
     }
 
-    fn parse_variable(&mut self) -> CompileResult<Option<u8>> {
+    fn fun_declaration(&mut self) -> CompileResult<()> {
+        let token = self.peek_token()?;
+        let line = token.line;
+        let name = match token.contents {
+            TokenContents::Identifier(id) => Some(id),
+            _ => None,
+        };
+        let global = self.parse_variable()?;
+        if global.is_none() {
+            self.define_variable(None, line)?;
+        }
+        // Capturing isn't supported, so a local function reaches itself through slot 0 instead of
+        // the local it is stored in. A global one is looked up by name, as it may be reassigned.
+        self.function(name, global.is_none(), line)?;
+        if global.is_some() {
+            self.define_variable(global, line)?;
+        }
+        Ok(())
+    }
+
+    /// Compiles the parameters and body of a function, after `fun` and its name, and emits the
+    /// function as a constant. If `recursive`, `name` refers to the function itself in its body.
+    fn function(
+        &mut self,
+        name: Option<&'a str>,
+        recursive: bool,
+        line: usize,
+    ) -> CompileResult<()> {
+        let chunk = Chunk::new(
+            name.unwrap_or("lambda").to_string(),
+            self.memory_manager.alloc(),
+        );
+        self.enclosing.push(FunctionState {
+            chunk: std::mem::replace(&mut self.chunk, chunk),
+            locals: std::mem::take(&mut self.locals),
+            scope_depth: std::mem::replace(&mut self.scope_depth, 1),
+            loops: std::mem::take(&mut self.loops),
+            last_call: self.last_call.take(),
+        });
+        // Slot 0 holds the function being called
+        self.locals.push(Local {
+            name: if recursive { name.unwrap_or("") } else { "" },
+            depth: NonZeroUsize::new(1),
+            unreferenced: None,
+        });
+
+        let res = self.function_body(line);
+
+        let enclosing = self.enclosing.pop().unwrap();
+        let chunk = std::mem::replace(&mut self.chunk, enclosing.chunk);
+        for local in std::mem::replace(&mut self.locals, enclosing.locals) {
+            self.warn_if_unreferenced(&local);
+        }
+        self.scope_depth = enclosing.scope_depth;
+        self.loops = enclosing.loops;
+        self.last_call = enclosing.last_call;
+        let arity = res?;
+
+        // The body may have been skipped partially after an error
+        if self.errors.errors.is_empty() {
+            finish_function_chunk(&chunk);
+        }
+        let function = self.memory_manager.new_function(name, arity, chunk);
+        let constant = self
+            .chunk
+            .add_constant(Value::Obj(Object::Function(function)))
+            .ok_or_else(|| {
+                CompileErrors::from(ParseError::TooManyConstants(line, "fun".to_string()))
+            })?;
+        self.chunk
+            .add_opcode_and_operand(Opcode::Constant, constant, line);
+        Ok(())
+    }
+
+    /// Returns the arity.
+    fn function_body(&mut self, line: usize) -> CompileResult<u8> {
+        match self.next_token()? {
+            token if token.contents == TokenContents::LeftParen => {}
+            token => {
+                return Err(ParseError::MissingParameterList(
+                    token.line,
+                    token.contents.to_string(),
+                )
+                .into());
+            }
+        }
+        let mut arity: u8 = 0;
+        if self.peek_token()?.contents != TokenContents::RightParen {
+            loop {
+                let token = self.peek_token()?;
+                let (param_line, param) = (token.line, token.contents.to_string());
+                arity = arity.checked_add(1).ok_or_else(|| {
+                    CompileErrors::from(ParseError::TooManyParameters(param_line, param))
+                })?;
+                let idx = self.parse_variable()?;
+                self.define_variable(idx, param_line)?;
+                match self.peek_token()?.contents {
+                    TokenContents::Comma => {
+                        let _ = self.next_token();
+                    }
+                    _ => break,
+                }
+            }
+        }
+        match self.next_token()? {
+            token if token.contents == TokenContents::RightParen => {}
+            token => {
+                return Err(ParseError::UnclosedParameterList(
+                    token.line,
+                    token.contents.to_string(),
+                )
+                .into());
+            }
+        }
+        match self.next_token()? {
+            token if token.contents == TokenContents::LeftBrace => {}
+            token => {
+                return Err(ParseError::MissingFunctionBody(
+                    token.line,
+                    token.contents.to_string(),
+                )
+                .into());
+            }
+        }
+        self.block()?;
+        self.chunk.add_opcode(Opcode::Nil, line);
+        self.chunk.add_opcode(Opcode::Return, line);
+        Ok(arity)
+    }
+
+    fn parse_variable(&mut self) -> CompileResult<Option<u32>> {
         let mut errors = CompileErrors::new();
         match self.iter.next() {
             Some(token) => match token {
-                Ok(token) => {
-                    let line = token.line;
-                    match token.contents {
-                        TokenContents::Identifier(id) => {
-                            self.declare_variable(id, line)?;
-                            if self.scope_depth > 0 {
-                                Ok(None)
-                            } else {
-                                self.identifier_constant(id).map(Some)
-                            }
-                        }
-                        _ => {
-                            errors.push(
-                                ParseError::NotAVariableName(line, token.contents.to_string())
-                                    .into(),
-                            );
-                            Err(errors)
-                        }
-                    }
-                }
+                Ok(token) => self.declare_named(&token),
                 Err(e) => {
                     errors.push(e.into());
                     Err(errors)
@@ -240,12 +577,54 @@ impl<'a, 'b> Compiler<'a, 'b> {
         }
     }
 
-    fn identifier_constant(&mut self, id: &str) -> CompileResult<u8> {
-        self.chunk
-            .add_constant(Value::Obj(Object::String(
-                self.memory_manager.new_str_copied(id),
-            )))
-            .ok_or_else(|| CompileErrors::from(ParseError::TooManyConstants))
+    /// Declares the variable named by `token`, returning its global slot outside of any scope.
+    fn declare_named(&mut self, token: &Token<'a>) -> CompileResult<Option<u32>> {
+        match token.contents {
+            TokenContents::Identifier(id) => {
+                self.declare_variable(id, token.line)?;
+                if self.scope_depth > 0 {
+                    Ok(None)
+                } else {
+                    self.global_slot(id).map(Some)
+                }
+            }
+            _ => Err(ParseError::NotAVariableName(token.line, token.contents.to_string()).into()),
+        }
+    }
+
+    /// `print` is a native function, the statement is just a nicer way to call it.
+    fn emit_print_callee(&mut self, line: usize) -> CompileResult<()> {
+        let slot = self.global_slot("print")?;
+        self.emit_variable(Opcode::GetGlobal, slot, line);
+        Ok(())
+    }
+
+    /// Calls the `print` native pushed by [`Parser::emit_print_callee`] and drops its result.
+    fn emit_print_call(&mut self, line: usize) {
+        self.chunk.add_opcode_and_operand(Opcode::Call, 1, line);
+        self.chunk.add_opcode(Opcode::Pop, line);
+    }
+
+    /// `print x` calls the `print` native with `x`, `print` without an operand is the native
+    /// itself, like in `var p = print;`.
+    fn parse_print(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        self.emit_print_callee(token.line)?;
+        let has_operand = matches!(
+            self.iter.peek(),
+            Some(Ok(next)) if get_parser(next, OperatorType::Prefix).is_some()
+        );
+        if has_operand {
+            self.expression()?;
+            self.chunk
+                .add_opcode_and_operand(Opcode::Call, 1, token.line);
+        }
+        Ok(())
+    }
+
+    fn global_slot(&mut self, id: &str) -> CompileResult<u32> {
+        self.memory_manager
+            .global_slot(id)
+            .ok_or_else(|| CompileErrors::from(ParseError::TooManyGlobals))
     }
 
     fn declare_variable(&mut self, name: &'a str, line: usize) -> CompileResult<()> {
@@ -260,22 +639,26 @@ impl<'a, 'b> Compiler<'a, 'b> {
                     return Err(ParseError::DuplicateLocal(line, name.to_string()).into());
                 }
             }
-            self.add_local(name)
+            self.add_local(name, line)
         } else {
             Ok(())
         }
     }
 
-    fn add_local(&mut self, name: &'a str) -> CompileResult<()> {
+    fn add_local(&mut self, name: &'a str, line: usize) -> CompileResult<()> {
         self.locals
-            .try_push(Local { name, depth: None })
-            .map_err(|_| ParseError::GeneralError("Too many locals".to_string()).into())
+            .try_push(Local {
+                name,
+                depth: None,
+                unreferenced: None,
+            })
+            .map_err(|_| ParseError::TooManyLocals(line, name.to_string()).into())
     }
 
-    fn define_variable(&mut self, idx: Option<u8>, line: usize) -> CompileResult<()> {
+    fn define_variable(&mut self, idx: Option<u32>, line: usize) -> CompileResult<()> {
         if let Some(idx) = idx {
             self.chunk
-                .add_opcode_and_operand(Opcode::DefineGlobal, idx, line);
+                .add_global_opcode(Opcode::DefineGlobal, idx, line);
         } else if let Some(local_depth) = NonZeroUsize::new(self.scope_depth) {
             if let Some(local) = self.locals.last_mut() {
                 local.depth = Some(local_depth);
@@ -289,40 +672,9 @@ impl<'a, 'b> Compiler<'a, 'b> {
     }
 
     fn statement(&mut self) -> CompileResult<()> {
-        let mut errors = CompileErrors::new();
         let token = self.peek_token()?;
         let line = token.line;
         match token.contents {
-            TokenContents::Print => {
-                let _ = self.next_token();
-                self.expression()?;
-                match self.iter.next() {
-                    Some(Ok(Token {
-                        contents: TokenContents::Semicolon,
-                        line,
-                    })) => {
-                        self.chunk.add_opcode(Opcode::Print, line);
-                        Ok(())
-                    }
-                    Some(Ok(token)) => {
-                        errors.push(
-                            ParseError::MissingSemicolon(token.line, token.contents.to_string())
-                                .into(),
-                        );
-                        Err(errors)
-                    }
-                    _ => {
-                        errors.push(
-                            ParseError::GeneralError(format!(
-                                "Missing semicolon around line {}",
-                                line
-                            ))
-                            .into(),
-                        );
-                        Err(errors)
-                    }
-                }
-            }
             TokenContents::LeftBrace => {
                 let _ = self.next_token()?;
                 self.scoped(|s| s.block())?;
@@ -330,15 +682,23 @@ impl<'a, 'b> Compiler<'a, 'b> {
             }
             TokenContents::If => {
                 let _ = self.next_token()?;
-                self.if_statement()
+                self.if_statement(line)
             }
             TokenContents::While => {
                 let _ = self.next_token()?;
-                self.while_statement()
+                self.while_statement(line)
             }
             TokenContents::For => {
                 let _ = self.next_token()?;
-                self.for_statement()
+                self.for_statement(line)
+            }
+            TokenContents::Break | TokenContents::Continue => {
+                let token = self.next_token()?;
+                self.loop_control_statement(&token)
+            }
+            TokenContents::Return => {
+                let _ = self.next_token()?;
+                self.return_statement(line)
             }
             _ => self.expression_statement(line),
         }
@@ -348,11 +708,17 @@ impl<'a, 'b> Compiler<'a, 'b> {
         self.scope_depth += 1;
         let res = f(self);
         self.scope_depth -= 1;
+        let mut count: u8 = 0;
         while let Some(last) = self.locals.last() {
             if let Some(local_depth) = last.depth {
                 if local_depth.get() > self.scope_depth {
-                    self.chunk.add_opcode(Opcode::Pop, 0);
-                    let _ = self.locals.pop();
+                    if count == u8::MAX {
+                        self.emit_pops(count);
+                        count = 0;
+                    }
+                    count += 1;
+                    let local = self.locals.pop().unwrap();
+                    self.warn_if_unreferenced(&local);
                 } else {
                     break;
                 }
@@ -360,16 +726,59 @@ impl<'a, 'b> Compiler<'a, 'b> {
                 break;
             }
         }
+        self.emit_pops(count);
         res
     }
 
+    /// Pops the locals declared deeper than `depth` without forgetting them, for jumps out of a
+    /// scope that keeps being compiled afterwards.
+    fn emit_pops_to_depth(&mut self, depth: usize) {
+        let count = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|l| l.depth.is_some_and(|d| d.get() > depth))
+            .count();
+        for _ in 0..count / u8::MAX as usize {
+            self.emit_pops(u8::MAX);
+        }
+        self.emit_pops((count % u8::MAX as usize) as u8);
+    }
+
+    fn emit_pops(&mut self, count: u8) {
+        match count {
+            0 => {}
+            1 => self.chunk.add_opcode(Opcode::Pop, 0),
+            n => self.chunk.add_opcode_and_operand(Opcode::PopN, n, 0),
+        }
+    }
+
     fn block(&mut self) -> CompileResult<()> {
+        // Whatever follows a `return`, `break` or `continue` is still compiled to report errors,
+        // but the code is thrown away at the end of the block
+        let mut unreachable_from = None;
         while let Ok(next) = self.peek_token() {
             match next.contents {
                 TokenContents::RightBrace => break,
+                TokenContents::Return | TokenContents::Break | TokenContents::Continue
+                    if unreachable_from.is_none() =>
+                {
+                    self.declaration()?;
+                    unreachable_from = Some((self.chunk.len(), self.chunk.constants_len()));
+                    match self.peek_token() {
+                        Ok(next) if next.contents != TokenContents::RightBrace => {
+                            let line = next.line;
+                            self.warn(ParseError::UnreachableCode(line));
+                        }
+                        _ => {}
+                    }
+                }
                 _ => self.declaration()?,
             }
         }
+        if let Some(start) = unreachable_from {
+            self.discard_code(start);
+        }
         match self.next_token() {
             Ok(token) if token.contents == TokenContents::RightBrace => Ok(()),
             _ => Err(
@@ -378,40 +787,141 @@ impl<'a, 'b> Compiler<'a, 'b> {
         }
     }
 
-    fn if_statement(&mut self) -> CompileResult<()> {
-        match self.next_token() {
-            Ok(token) if token.contents == TokenContents::LeftParen => (),
-            _ => {
-                return Err(ParseError::GeneralError("Expected '(' after 'if'".to_string()).into());
+    /// Drops the code and constants from `start` on, and forgets jumps and calls in there.
+    fn discard_code(&mut self, (len, constants_len): (usize, usize)) {
+        self.chunk.truncate(len, constants_len);
+        for context in self.loops.iter_mut() {
+            context.break_jumps.retain(|jump| *jump < len);
+        }
+        if self.last_call.is_some_and(|offset| offset >= len) {
+            self.last_call = None;
+        }
+    }
+
+    /// The jumps and pops of a branch get the line of its `if`, the branches keep their own.
+    fn if_statement(&mut self, mut line: usize) -> CompileResult<()> {
+        // `else if` branches are compiled in this loop instead of recursively, so every branch
+        // jumps straight to the end of the whole chain
+        let mut end_jumps = Vec::new();
+        loop {
+            match self.next_token() {
+                Ok(token) if token.contents == TokenContents::LeftParen => (),
+                _ => {
+                    return Err(
+                        ParseError::GeneralError("Expected '(' after 'if'".to_string()).into(),
+                    );
+                }
+            }
+            self.expression()?;
+            match self.next_token() {
+                Ok(token) if token.contents == TokenContents::RightParen => (),
+                _ => {
+                    return Err(ParseError::GeneralError(
+                        "Expected ')' after condition".to_string(),
+                    )
+                    .into());
+                }
+            };
+            let then_jump = self.emit_jump(Opcode::JumpIfFalse, line)?;
+            self.chunk.add_opcode(Opcode::Pop, line);
+            self.statement()?;
+            end_jumps.push((self.emit_jump(Opcode::Jump, line)?, line));
+            self.patch_jump(then_jump, line, "if")?;
+            self.chunk.add_opcode(Opcode::Pop, line);
+            if let Some(Ok(t)) = self.iter.peek() {
+                if t.contents == TokenContents::Else {
+                    let _ = self.next_token()?;
+                    if let Some(Ok(t)) = self.iter.peek() {
+                        if t.contents == TokenContents::If {
+                            line = self.next_token()?.line;
+                            continue;
+                        }
+                    }
+                    self.statement()?;
+                }
             }
+            break;
         }
-        self.expression()?;
-        let token = match self.next_token() {
-            Ok(token) if token.contents == TokenContents::RightParen => token,
-            _ => {
-                return Err(
-                    ParseError::GeneralError("Expected ')' after condition".to_string()).into(),
-                );
+        for (end_jump, line) in end_jumps {
+            self.patch_jump(end_jump, line, "if")?;
+        }
+        Ok(())
+    }
+
+    fn return_statement(&mut self, line: usize) -> CompileResult<()> {
+        if self.enclosing.is_empty() {
+            return Err(ParseError::ReturnAtTopLevel(line).into());
+        }
+        if self.peek_token()?.contents == TokenContents::Semicolon {
+            let _ = self.next_token()?;
+            self.chunk.add_opcode(Opcode::Nil, line);
+        } else {
+            self.expression()?;
+            match self.next_token()? {
+                token if token.contents == TokenContents::Semicolon => {}
+                token => {
+                    return Err(ParseError::MissingSemicolon(
+                        token.line,
+                        token.contents.to_string(),
+                    )
+                    .into());
+                }
             }
+            // Nothing is left to do in this frame after a call that ends the returned expression,
+            // so the callee can take the frame over. The `Return` is still needed for jumps that
+            // skip the call, like in `return a or f();`.
+            if let Some(offset) = self.last_call.filter(|o| o + 2 == self.chunk.len()) {
+                self.chunk.make_tail_call(offset);
+            }
+        }
+        self.chunk.add_opcode(Opcode::Return, line);
+        Ok(())
+    }
+
+    fn loop_control_statement(&mut self, token: &Token) -> CompileResult<()> {
+        let Some(context) = self.loops.last() else {
+            return Err(
+                ParseError::LoopControlOutsideLoop(token.line, token.contents.to_string()).into(),
+            );
         };
-        let line = token.line;
-        // TODO fix the line numbers here
-        let then_jump = self.emit_jump(Opcode::JumpIfFalse, line)?;
-        self.chunk.add_opcode(Opcode::Pop, line);
-        self.statement()?;
-        let else_jump = self.emit_jump(Opcode::Jump, line)?;
-        self.patch_jump(then_jump)?;
-        self.chunk.add_opcode(Opcode::Pop, line);
-        if let Some(Ok(t)) = self.iter.peek() {
-            if t.contents == TokenContents::Else {
-                let _ = self.next_token()?;
-                self.statement()?;
+        let (continue_target, depth) = (context.continue_target, context.scope_depth);
+        match self.next_token() {
+            Ok(t) if t.contents == TokenContents::Semicolon => {}
+            Ok(t) => {
+                return Err(ParseError::MissingSemicolon(t.line, t.contents.to_string()).into())
             }
+            Err(e) => return Err(e),
         }
-        self.patch_jump(else_jump)
+        self.emit_pops_to_depth(depth);
+        if token.contents == TokenContents::Break {
+            let jump = self.emit_jump(Opcode::Jump, token.line)?;
+            self.loops.last_mut().unwrap().break_jumps.push(jump);
+        } else {
+            self.emit_loop(continue_target, token.line, "continue")?;
+        }
+        Ok(())
+    }
+
+    /// Compiles a loop body that `break` and `continue` can leave.
+    fn loop_body(&mut self, continue_target: usize) -> CompileResult<Vec<usize>> {
+        self.loops.push(LoopContext {
+            continue_target,
+            scope_depth: self.scope_depth,
+            break_jumps: Vec::new(),
+        });
+        let res = self.statement();
+        let context = self.loops.pop().unwrap();
+        res.map(|_| context.break_jumps)
+    }
+
+    fn patch_breaks(&mut self, break_jumps: Vec<usize>, line: usize) -> CompileResult<()> {
+        for jump in break_jumps {
+            self.patch_jump(jump, line, "break")?;
+        }
+        Ok(())
     }
 
-    fn while_statement(&mut self) -> CompileResult<()> {
+    fn while_statement(&mut self, line: usize) -> CompileResult<()> {
         let loop_start = self.chunk.get_loop_start();
         match self.next_token() {
             Ok(token) if token.contents == TokenContents::LeftParen => (),
@@ -422,28 +932,28 @@ impl<'a, 'b> Compiler<'a, 'b> {
             }
         }
         self.expression()?;
-        let token = match self.next_token() {
-            Ok(token) if token.contents == TokenContents::RightParen => token,
+        match self.next_token() {
+            Ok(token) if token.contents == TokenContents::RightParen => (),
             _ => {
                 return Err(
                     ParseError::GeneralError("Expected ')' after condition".to_string()).into(),
                 );
             }
         };
-        let line = token.line;
         let exit_jump = self.emit_jump(Opcode::JumpIfFalse, line)?;
         self.chunk.add_opcode(Opcode::Pop, line);
-        self.statement()?;
+        let break_jumps = self.loop_body(loop_start)?;
 
-        self.emit_loop(loop_start, line)?;
+        self.emit_loop(loop_start, line, "while")?;
 
-        self.patch_jump(exit_jump)?;
+        self.patch_jump(exit_jump, line, "while")?;
         self.chunk.add_opcode(Opcode::Pop, line);
+        self.patch_breaks(break_jumps, line)?;
 
         Ok(())
     }
 
-    fn for_statement(&mut self) -> CompileResult<()> {
+    fn for_statement(&mut self, line: usize) -> CompileResult<()> {
         self.scoped(|s| {
             match s.next_token() {
                 Ok(token) if token.contents == TokenContents::LeftParen => (),
@@ -459,7 +969,12 @@ impl<'a, 'b> Compiler<'a, 'b> {
                 }
                 Ok(token) if token.contents == TokenContents::Var => {
                     s.next_token()?;
-                    s.var_declaration()?;
+                    let name = s.next_token()?;
+                    if s.peek_token()?.contents == TokenContents::In {
+                        return s.foreach(name, line);
+                    }
+                    let constant_index = s.declare_named(&name)?;
+                    s.var_initializer(constant_index)?;
                 }
                 Ok(token) => {
                     let line = token.line;
@@ -470,13 +985,15 @@ impl<'a, 'b> Compiler<'a, 'b> {
 
             let loop_start = s.chunk.get_loop_start();
 
+            s.log_identifiers += 1;
+            let condition_start = s.identifier_log.len();
+            let condition_line = s.peek_token().map(|t| t.line).unwrap_or(0);
             let exit_jump = match s.peek_token() {
                 Ok(token) if token.contents == TokenContents::Semicolon => {
                     let _ = s.next_token()?;
                     None
                 }
-                Ok(token) => {
-                    let line = token.line;
+                Ok(_) => {
                     s.expression()?;
                     match s.next_token() {
                         Ok(token) if token.contents == TokenContents::Semicolon => (),
@@ -490,13 +1007,17 @@ impl<'a, 'b> Compiler<'a, 'b> {
                 }
                 _ => return Err(ParseError::GeneralError("Expected ';'".to_string()).into()),
             };
-            let (line, loop_start) = match s.peek_token() {
+            let condition_end = s.identifier_log.len();
+            let has_increment = !matches!(
+                s.peek_token(),
+                Ok(token) if token.contents == TokenContents::RightParen
+            );
+            let loop_start = match s.peek_token() {
                 Ok(token) if token.contents == TokenContents::RightParen => {
-                    let token = s.next_token()?;
-                    (token.line, loop_start)
+                    s.next_token()?;
+                    loop_start
                 }
-                Ok(token) => {
-                    let line = token.line;
+                Ok(_) => {
                     let body_jump = s.emit_jump(Opcode::Jump, line)?;
                     let increment_start = s.chunk.get_loop_start();
                     s.expression()?;
@@ -510,10 +1031,10 @@ impl<'a, 'b> Compiler<'a, 'b> {
                             .into());
                         }
                     };
-                    s.emit_loop(loop_start, line)?;
-                    s.patch_jump(body_jump)?;
+                    s.emit_loop(loop_start, line, "for")?;
+                    s.patch_jump(body_jump, line, "for")?;
 
-                    (line, increment_start)
+                    increment_start
                 }
                 _ => {
                     return Err(ParseError::GeneralError(
@@ -522,41 +1043,251 @@ impl<'a, 'b> Compiler<'a, 'b> {
                     .into());
                 }
             };
-            s.statement()?;
+            let body_start = s.identifier_log.len();
+            let break_jumps = s.loop_body(loop_start)?;
+            s.log_identifiers -= 1;
+
+            if exit_jump.is_some() && !has_increment && break_jumps.is_empty() {
+                let (condition, body) =
+                    s.identifier_log[condition_start..].split_at(body_start - condition_start);
+                let condition = &condition[..condition_end - condition_start];
+                let updated = body
+                    .iter()
+                    .any(|(name, assigned)| *assigned && condition.iter().any(|(c, _)| c == name));
+                if !updated {
+                    s.warn(ParseError::PossibleInfiniteLoop(condition_line));
+                }
+            }
+            if s.log_identifiers == 0 {
+                s.identifier_log.clear();
+            }
 
-            s.emit_loop(loop_start, line)?;
+            s.emit_loop(loop_start, line, "for")?;
 
             if let Some(exit_jump) = exit_jump {
-                s.patch_jump(exit_jump)?;
+                s.patch_jump(exit_jump, line, "for")?;
                 s.chunk.add_opcode(Opcode::Pop, line);
             }
+            s.patch_breaks(break_jumps, line)?;
             Ok(())
         })
     }
 
+    /// The rest of `for (var name in sequence) body`, walking the graphemes of a string with a
+    /// hidden index. Already inside the scope of the `for` statement.
+    fn foreach(&mut self, name: Token<'a>, line: usize) -> CompileResult<()> {
+        let TokenContents::Identifier(id) = name.contents else {
+            return Err(ParseError::NotAVariableName(name.line, name.contents.to_string()).into());
+        };
+        // Skip `in`
+        self.next_token()?;
+        // Names with a space can't be written in a script, so these never clash
+        self.add_local(" sequence", line)?;
+        self.expression()?;
+        self.define_variable(None, line)?;
+        let sequence = (self.locals.len() - 1) as u8;
+        // The length is taken once, measuring a string takes time proportional to its length
+        self.add_local(" length", line)?;
+        self.chunk
+            .add_opcode_and_operand(Opcode::GetLocal, sequence, line);
+        self.chunk.add_opcode(Opcode::Len, line);
+        self.define_variable(None, line)?;
+        let length = (self.locals.len() - 1) as u8;
+        self.add_local(" index", line)?;
+        self.emit_constant(Value::Number(0.0), line)?;
+        self.define_variable(None, line)?;
+        let index = (self.locals.len() - 1) as u8;
+        match self.next_token() {
+            Ok(token) if token.contents == TokenContents::RightParen => (),
+            _ => {
+                return Err(
+                    ParseError::GeneralError("Expected ')' after for clauses".to_string()).into(),
+                );
+            }
+        }
+
+        let loop_start = self.chunk.get_loop_start();
+        self.chunk
+            .add_opcode_and_operand(Opcode::GetLocal, index, line);
+        self.chunk
+            .add_opcode_and_operand(Opcode::GetLocal, length, line);
+        self.chunk.add_opcode(Opcode::Less, line);
+        let exit_jump = self.emit_jump(Opcode::JumpIfFalse, line)?;
+        self.chunk.add_opcode(Opcode::Pop, line);
+        let body_jump = self.emit_jump(Opcode::Jump, line)?;
+
+        let increment_start = self.chunk.get_loop_start();
+        self.chunk
+            .add_opcode_and_operand(Opcode::GetLocal, index, line);
+        self.emit_constant(Value::Number(1.0), line)?;
+        self.chunk.add_opcode(Opcode::Add, line);
+        self.chunk
+            .add_opcode_and_operand(Opcode::SetLocal, index, line);
+        self.chunk.add_opcode(Opcode::Pop, line);
+        self.emit_loop(loop_start, line, "for")?;
+        self.patch_jump(body_jump, line, "for")?;
+
+        // The element lives in a scope of its own, which `continue` has to pop
+        self.loops.push(LoopContext {
+            continue_target: increment_start,
+            scope_depth: self.scope_depth,
+            break_jumps: Vec::new(),
+        });
+        let res = self.scoped(|s| {
+            s.declare_variable(id, name.line)?;
+            s.chunk
+                .add_opcode_and_operand(Opcode::GetLocal, sequence, name.line);
+            s.chunk
+                .add_opcode_and_operand(Opcode::GetLocal, index, name.line);
+            s.chunk.add_opcode(Opcode::GetIndex, name.line);
+            s.define_variable(None, name.line)?;
+            s.statement()
+        });
+        let context = self.loops.pop().unwrap();
+        res?;
+        self.emit_loop(increment_start, line, "for")?;
+
+        self.patch_jump(exit_jump, line, "for")?;
+        self.chunk.add_opcode(Opcode::Pop, line);
+        self.patch_breaks(context.break_jumps, line)
+    }
+
+    fn emit_constant(&mut self, value: Value, line: usize) -> CompileResult<()> {
+        if let Value::Number(n) = value {
+            if let Some(byte) = small_int(n) {
+                self.chunk
+                    .add_opcode_and_operand(Opcode::SmallInt, byte, line);
+                return Ok(());
+            }
+        }
+        let constant = self.chunk.add_constant(value).ok_or_else(|| {
+            CompileErrors::from(ParseError::TooManyConstants(line, value.to_string()))
+        })?;
+        self.chunk
+            .add_opcode_and_operand(Opcode::Constant, constant, line);
+        Ok(())
+    }
+
+    /// The value pushed by the code from `start` to `end`, if that code is a single `Constant` or
+    /// `SmallInt`.
+    fn constant_between(&self, start: usize, end: usize) -> Option<Value> {
+        if end != start + 2 {
+            return None;
+        }
+        match Opcode::try_from(self.chunk[start]) {
+            Ok(Opcode::Constant) => self.chunk.get_constant(self.chunk[start + 1]).copied(),
+            Ok(Opcode::SmallInt) => Some(Value::Number(self.chunk[start + 1] as f64)),
+            _ => None,
+        }
+    }
+
+    /// Replaces the code and constants from `start` on with a single `Constant`.
+    fn fold_into_constant(
+        &mut self,
+        (start, constants_len): (usize, usize),
+        value: Value,
+        line: usize,
+    ) -> CompileResult<()> {
+        self.chunk.truncate(start, constants_len);
+        self.emit_constant(value, line)
+    }
+
+    /// Folds a binary operator whose operands are both number constants. Results that aren't
+    /// finite are left to the VM, which may be asked to report them.
+    fn fold_binary(
+        &mut self,
+        opcode: Opcode,
+        left_start: (usize, usize),
+        right_start: usize,
+        line: usize,
+    ) -> CompileResult<bool> {
+        let (Some(Value::Number(a)), Some(Value::Number(b))) = (
+            self.constant_between(left_start.0, right_start),
+            self.constant_between(right_start, self.chunk.len()),
+        ) else {
+            return Ok(false);
+        };
+        let result = match opcode {
+            Opcode::Add => a + b,
+            Opcode::Subtract => a - b,
+            Opcode::Multiply => a * b,
+            Opcode::Divide => a / b,
+            _ => return Ok(false),
+        };
+        if !result.is_finite() {
+            return Ok(false);
+        }
+        self.fold_into_constant(left_start, Value::Number(result), line)?;
+        Ok(true)
+    }
+
+    /// Folds `-` of a number constant and `!` of a literal.
+    fn fold_unary(&mut self, token: &Token, start: (usize, usize)) -> CompileResult<bool> {
+        let end = self.chunk.len();
+        match (&token.contents, self.constant_between(start.0, end)) {
+            (TokenContents::Minus, Some(Value::Number(n))) => {
+                self.fold_into_constant(start, Value::Number(-n), token.line)?;
+                Ok(true)
+            }
+            (TokenContents::Bang, _) if end == start.0 + 1 => {
+                let negated = match Opcode::try_from(self.chunk[start.0]) {
+                    Ok(Opcode::True) => Opcode::False,
+                    Ok(Opcode::False | Opcode::Nil) => Opcode::True,
+                    _ => return Ok(false),
+                };
+                self.chunk.truncate(start.0, start.1);
+                self.chunk.add_opcode(negated, token.line);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     fn emit_jump(&mut self, opcode: Opcode, line: usize) -> CompileResult<usize> {
         Ok(self.chunk.add_dummy_jump(opcode, line))
     }
 
-    fn patch_jump(&mut self, target: usize) -> CompileResult<()> {
+    /// `construct` names the statement or operator the jump belongs to, for the error message.
+    fn patch_jump(
+        &mut self,
+        target: usize,
+        line: usize,
+        construct: &'static str,
+    ) -> CompileResult<()> {
         self.chunk
             .patch_jump(target)
-            .map_err(|e| ParseError::GeneralError(e).into())
+            .map_err(|_| ParseError::JumpTooFar { line, construct }.into())
     }
 
-    fn emit_loop(&mut self, loop_start: usize, line: usize) -> CompileResult<()> {
+    fn emit_loop(
+        &mut self,
+        loop_start: usize,
+        line: usize,
+        construct: &'static str,
+    ) -> CompileResult<()> {
         self.chunk
             .emit_loop(loop_start, line)
-            .map_err(|e| ParseError::GeneralError(e).into())
+            .map_err(|_| ParseError::JumpTooFar { line, construct }.into())
     }
 
     fn expression_statement(&mut self, estimated_line: usize) -> CompileResult<()> {
+        let starts_with_print = self.peek_token()?.contents == TokenContents::Print;
+        let start = self.chunk.len();
         self.expression()?;
         match self.next_token() {
             Ok(Token {
                 contents: TokenContents::Semicolon,
                 line,
             }) => {
+                // A lone `print` only pushes the native, a statement of just that is a mistake
+                let lone_print = starts_with_print
+                    && self
+                        .chunk
+                        .decode_at(start)
+                        .is_ok_and(|callee| start + callee.encoded_len() == self.chunk.len());
+                if lone_print {
+                    return Err(ParseError::ExpectExpressionAfterPrint(line).into());
+                }
                 self.chunk.add_opcode(Opcode::Pop, line);
                 Ok(())
             }
@@ -577,6 +1308,7 @@ impl<'a, 'b> Compiler<'a, 'b> {
 
     fn expression_bp(&mut self, min_bp: BindingPower) -> CompileResult<()> {
         let mut errors = CompileErrors::new();
+        let start = (self.chunk.len(), self.chunk.constants_len());
 
         if let Some(token) = self.iter.next() {
             match token {
@@ -608,16 +1340,29 @@ impl<'a, 'b> Compiler<'a, 'b> {
                         if infix_bp < min_bp {
                             break;
                         }
-                        let token = self.iter.next().unwrap().unwrap();
+                        let token = self.next_token()?;
 
+                        self.operand_start = start;
                         if let Err(e) = infix_rule(self, &token, can_assign) {
                             errors.extend(e);
                         }
                     } else {
                         let peek = self.peek_token()?;
-                        if can_assign && peek.contents == TokenContents::Equal {
+                        if can_assign
+                            && (peek.contents == TokenContents::Equal
+                                || compound_assignment_op(&peek.contents).is_some())
+                        {
                             errors.push(ParseError::InvalidAssignmentTarget(peek.line).into());
                         }
+                        if postfix_op(&peek.contents).is_some() {
+                            errors.push(
+                                ParseError::InvalidIncrementTarget(
+                                    peek.line,
+                                    peek.contents.to_string(),
+                                )
+                                .into(),
+                            );
+                        }
                         break;
                     }
                 }
@@ -636,7 +1381,11 @@ impl<'a, 'b> Compiler<'a, 'b> {
     }
 
     fn parse_unary(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        let start = (self.chunk.len(), self.chunk.constants_len());
         self.expression_bp(BindingPower::Unary)?;
+        if self.fold_unary(token, start)? {
+            return Ok(());
+        }
         match token.contents {
             TokenContents::Minus => self.chunk.add_opcode(Opcode::Negate, token.line),
             TokenContents::Bang => self.chunk.add_opcode(Opcode::Not, token.line),
@@ -647,34 +1396,55 @@ impl<'a, 'b> Compiler<'a, 'b> {
 
     fn parse_number(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
         let number: f64 = match &token.contents {
-            TokenContents::Number(number) => number.parse().expect("Could not parse number"),
+            TokenContents::Number(number) => number_value(number),
             _ => unreachable!("Expected number, got token {token:?}"),
         };
+        if let Some(byte) = small_int(number) {
+            self.chunk
+                .add_opcode_and_operand(Opcode::SmallInt, byte, token.line);
+            return Ok(());
+        }
         let constant = self
             .chunk
             .add_constant(Value::Number(number))
-            .ok_or_else(|| CompileErrors::from(ParseError::TooManyConstants))?;
+            .ok_or_else(|| {
+                CompileErrors::from(ParseError::TooManyConstants(
+                    token.line,
+                    token.contents.to_string(),
+                ))
+            })?;
         self.chunk
             .add_opcode_and_operand(Opcode::Constant, constant, token.line);
         Ok(())
     }
 
     fn parse_term(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        let left_start = self.operand_start;
+        let right_start = self.chunk.len();
         self.expression_bp(BindingPower::Term)?;
-        match token.contents {
-            TokenContents::Plus => self.chunk.add_opcode(Opcode::Add, token.line),
-            TokenContents::Minus => self.chunk.add_opcode(Opcode::Subtract, token.line),
+        let opcode = match token.contents {
+            TokenContents::Plus => Opcode::Add,
+            TokenContents::Minus => Opcode::Subtract,
             _ => unreachable!("Unexpected term token, got {token:?}"),
+        };
+        if !self.fold_binary(opcode, left_start, right_start, token.line)? {
+            self.chunk.add_opcode(opcode, token.line);
         }
         Ok(())
     }
 
     fn parse_factor(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        let left_start = self.operand_start;
+        let right_start = self.chunk.len();
         self.expression_bp(BindingPower::Factor)?;
-        match token.contents {
-            TokenContents::Asterisk => self.chunk.add_opcode(Opcode::Multiply, token.line),
-            TokenContents::Slash => self.chunk.add_opcode(Opcode::Divide, token.line),
+        let opcode = match token.contents {
+            TokenContents::Asterisk => Opcode::Multiply,
+            TokenContents::Slash => Opcode::Divide,
+            TokenContents::Percent => Opcode::Modulo,
             _ => unreachable!("Unexpected term token, got {token:?}"),
+        };
+        if !self.fold_binary(opcode, left_start, right_start, token.line)? {
+            self.chunk.add_opcode(opcode, token.line);
         }
         Ok(())
     }
@@ -700,6 +1470,119 @@ impl<'a, 'b> Compiler<'a, 'b> {
         Ok(())
     }
 
+    fn parse_call(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        let mut arg_count: u8 = 0;
+        if !matches!(self.peek_token()?.contents, TokenContents::RightParen) {
+            loop {
+                self.expression()?;
+                arg_count = arg_count
+                    .checked_add(1)
+                    .ok_or_else(|| CompileErrors::from(ParseError::TooManyArguments(token.line)))?;
+                match self.peek_token()?.contents {
+                    TokenContents::Comma => {
+                        let _ = self.next_token();
+                    }
+                    _ => break,
+                }
+            }
+        }
+        match self.next_token() {
+            Ok(t) if t.contents == TokenContents::RightParen => {
+                self.last_call = Some(self.chunk.len());
+                self.chunk
+                    .add_opcode_and_operand(Opcode::Call, arg_count, token.line);
+                Ok(())
+            }
+            _ => Err(ParseError::GeneralError("Expect ')' after arguments".to_string()).into()),
+        }
+    }
+
+    /// A map literal, with `{:}` for the empty map. At the start of a statement `{` always opens a
+    /// block instead, so only maps in expression position need parsing here.
+    fn parse_map(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        let mut entry_count: u8 = 0;
+        match self.peek_token()?.contents {
+            // `{}` is an empty block, never an expression; the empty map is spelled `{:}`
+            TokenContents::RightBrace => {
+                return Err(
+                    ParseError::NoPrefixParser(token.line, token.contents.to_string()).into(),
+                );
+            }
+            TokenContents::Colon => {
+                self.next_token()?;
+            }
+            _ => loop {
+                self.expression()?;
+                let colon = self.next_token()?;
+                if colon.contents != TokenContents::Colon {
+                    return Err(ParseError::MissingMapColon(
+                        colon.line,
+                        colon.contents.to_string(),
+                    )
+                    .into());
+                }
+                self.expression()?;
+                entry_count = entry_count.checked_add(1).ok_or_else(|| {
+                    CompileErrors::from(ParseError::TooManyMapEntries(token.line))
+                })?;
+                if self.peek_token()?.contents != TokenContents::Comma {
+                    break;
+                }
+                self.next_token()?;
+                if self.peek_token()?.contents == TokenContents::RightBrace {
+                    break;
+                }
+            },
+        }
+        let close = self.next_token()?;
+        if close.contents != TokenContents::RightBrace {
+            return Err(ParseError::UnclosedMap(close.line, close.contents.to_string()).into());
+        }
+        self.chunk
+            .add_opcode_and_operand(Opcode::BuildMap, entry_count, token.line);
+        Ok(())
+    }
+
+    fn parse_index(&mut self, token: &Token, can_assign: bool) -> CompileResult<()> {
+        self.expression()?;
+        let close = self.next_token()?;
+        if close.contents != TokenContents::RightBracket {
+            return Err(ParseError::UnclosedIndex(close.line, close.contents.to_string()).into());
+        }
+        if can_assign && self.peek_token()?.contents == TokenContents::Equal {
+            self.next_token()?;
+            self.expression()?;
+            self.chunk.add_opcode(Opcode::SetIndex, token.line);
+        } else {
+            self.chunk.add_opcode(Opcode::GetIndex, token.line);
+        }
+        Ok(())
+    }
+
+    fn parse_dot(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        // There are no properties yet, but `123.` should still get clox's error
+        match self.iter.next() {
+            Some(Ok(name)) if matches!(name.contents, TokenContents::Identifier(_)) => {
+                Err(ParseError::NoInfixParser(token.line, token.contents.to_string()).into())
+            }
+            Some(Ok(name)) => Err(ParseError::MissingPropertyName(
+                name.line,
+                format!("'{}'", name.contents),
+            )
+            .into()),
+            Some(Err(e)) => Err(CompileError::ScanError(e).into()),
+            None => Err(ParseError::MissingPropertyName(token.line, "end".to_string()).into()),
+        }
+    }
+
+    fn parse_lambda(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
+        // A named function is a declaration, which can't appear where an expression is expected
+        if self.peek_token()?.contents != TokenContents::LeftParen {
+            return Err(ParseError::NoPrefixParser(token.line, token.contents.to_string()).into());
+        }
+        self.function(None, false, token.line)
+    }
+
     fn parse_literal(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
         match token.contents {
             TokenContents::True => self.chunk.add_opcode(Opcode::True, token.line),
@@ -714,10 +1597,7 @@ impl<'a, 'b> Compiler<'a, 'b> {
         self.expression_bp(BindingPower::Equality)?;
         match token.contents {
             TokenContents::EqualEqual => self.chunk.add_opcode(Opcode::Equal, token.line),
-            TokenContents::BangEqual => {
-                self.chunk.add_opcode(Opcode::Equal, token.line);
-                self.chunk.add_opcode(Opcode::Not, token.line);
-            }
+            TokenContents::BangEqual => self.chunk.add_opcode(Opcode::NotEqual, token.line),
             _ => unreachable!("Unexpected equality token, got {token:?}"),
         }
         Ok(())
@@ -744,11 +1624,14 @@ impl<'a, 'b> Compiler<'a, 'b> {
     fn parse_string(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
         match token.contents {
             TokenContents::String(s) => {
-                let value = Value::Obj(Object::String(self.memory_manager.new_str_copied(s)));
-                let constant = self
-                    .chunk
-                    .add_constant(value)
-                    .ok_or_else(|| CompileErrors::from(ParseError::TooManyConstants))?;
+                // Interning makes every chunk that uses this literal share one string object
+                let value = Value::Obj(Object::String(self.memory_manager.intern(s)));
+                let constant = self.chunk.add_constant(value).ok_or_else(|| {
+                    CompileErrors::from(ParseError::TooManyConstants(
+                        token.line,
+                        format!("\"{s}\""),
+                    ))
+                })?;
                 self.chunk
                     .add_opcode_and_operand(Opcode::Constant, constant, token.line)
             }
@@ -761,17 +1644,46 @@ impl<'a, 'b> Compiler<'a, 'b> {
         match token.contents {
             TokenContents::Identifier(id) => {
                 let (get_op, set_op, idx) = if let Some(idx) = self.resolve_local(id, token.line)? {
-                    (Opcode::GetLocal, Opcode::SetLocal, idx)
+                    (Opcode::GetLocal, Opcode::SetLocal, idx as u32)
+                } else if self
+                    .enclosing
+                    .iter()
+                    .any(|f| f.locals.iter().any(|l| l.name == id))
+                {
+                    return Err(ParseError::CannotCapture(token.line, id.to_string()).into());
                 } else {
-                    let idx = self.identifier_constant(id)?;
+                    let idx = self.global_slot(id)?;
                     (Opcode::GetGlobal, Opcode::SetGlobal, idx)
                 };
-                if self.peek_token()?.contents == TokenContents::Equal && can_assign {
+                let is_plain_assignment = self.peek_token()?.contents == TokenContents::Equal;
+                let compound_op = compound_assignment_op(&self.peek_token()?.contents);
+                let postfix_op = postfix_op(&self.peek_token()?.contents);
+                if self.log_identifiers > 0 {
+                    let assigned = postfix_op.is_some()
+                        || can_assign && (is_plain_assignment || compound_op.is_some());
+                    self.identifier_log.push((id.to_string(), assigned));
+                }
+                if is_plain_assignment && can_assign {
                     self.next_token()?;
                     self.expression()?;
-                    self.chunk.add_opcode_and_operand(set_op, idx, token.line);
+                    self.emit_variable(set_op, idx, token.line);
+                } else if let Some(op) = compound_op.filter(|_| can_assign) {
+                    let op_line = self.next_token()?.line;
+                    self.emit_variable(get_op, idx, token.line);
+                    self.expression()?;
+                    self.chunk.add_opcode(op, op_line);
+                    self.emit_variable(set_op, idx, token.line);
+                } else if let Some(op) = postfix_op {
+                    // Leaves the old value on the stack, below the updated one that is stored
+                    let op_token = self.next_token()?;
+                    self.emit_variable(get_op, idx, token.line);
+                    self.chunk.add_opcode(Opcode::Dup, op_token.line);
+                    self.emit_constant(Value::Number(1.0), op_token.line)?;
+                    self.chunk.add_opcode(op, op_token.line);
+                    self.emit_variable(set_op, idx, token.line);
+                    self.chunk.add_opcode(Opcode::Pop, op_token.line);
                 } else {
-                    self.chunk.add_opcode_and_operand(get_op, idx, token.line);
+                    self.emit_variable(get_op, idx, token.line);
                 }
             }
             _ => unreachable!("Unexpected identifier token, got {token:?}"),
@@ -779,13 +1691,24 @@ impl<'a, 'b> Compiler<'a, 'b> {
         Ok(())
     }
 
+    fn emit_variable(&mut self, opcode: Opcode, idx: u32, line: usize) {
+        match opcode {
+            // Locals are limited to MAX_LOCALS, so their slot always fits in a byte
+            Opcode::GetLocal | Opcode::SetLocal => {
+                self.chunk.add_opcode_and_operand(opcode, idx as u8, line)
+            }
+            _ => self.chunk.add_global_opcode(opcode, idx, line),
+        }
+    }
+
     fn parse_and(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
         match token.contents {
             TokenContents::And => {
+                // `JumpIfFalse` only peeks, so a falsey left operand stays as the result
                 let end_jump = self.emit_jump(Opcode::JumpIfFalse, token.line)?;
                 self.chunk.add_opcode(Opcode::Pop, token.line);
                 self.expression_bp(BindingPower::And)?;
-                self.patch_jump(end_jump)?;
+                self.patch_jump(end_jump, token.line, "and")?;
             }
             _ => unreachable!("Unexpected 'and' token, got {token:?}"),
         }
@@ -795,12 +1718,11 @@ impl<'a, 'b> Compiler<'a, 'b> {
     fn parse_or(&mut self, token: &Token, _can_assign: bool) -> CompileResult<()> {
         match token.contents {
             TokenContents::Or => {
-                let else_jump = self.emit_jump(Opcode::JumpIfFalse, token.line)?;
-                let end_jump = self.emit_jump(Opcode::Jump, token.line)?;
-                self.patch_jump(else_jump)?;
+                // `JumpIfTrue` only peeks, so a truthy left operand stays as the result
+                let end_jump = self.emit_jump(Opcode::JumpIfTrue, token.line)?;
                 self.chunk.add_opcode(Opcode::Pop, token.line);
                 self.expression_bp(BindingPower::Or)?;
-                self.patch_jump(end_jump)?;
+                self.patch_jump(end_jump, token.line, "or")?;
             }
             _ => unreachable!("Unexpected 'or' token, got {token:?}"),
         }
@@ -819,6 +1741,7 @@ impl<'a, 'b> Compiler<'a, 'b> {
                 if local.depth.is_none() {
                     return Err(ParseError::LocalInOwnInitializer(line, name.to_string()).into());
                 }
+                self.locals[idx].unreferenced = None;
                 return Ok(Some(idx as u8));
             }
         }
@@ -826,31 +1749,68 @@ impl<'a, 'b> Compiler<'a, 'b> {
     }
 }
 
+/// The operand for `SmallInt` if `n` is a whole number it can push.
+fn small_int(n: f64) -> Option<u8> {
+    (n.fract() == 0.0 && n.is_sign_positive() && n <= u8::MAX as f64).then_some(n as u8)
+}
+
+/// The binary operator a compound assignment like `+=` applies before storing.
+fn compound_assignment_op(contents: &TokenContents) -> Option<Opcode> {
+    match contents {
+        TokenContents::PlusEqual => Some(Opcode::Add),
+        TokenContents::MinusEqual => Some(Opcode::Subtract),
+        TokenContents::AsteriskEqual => Some(Opcode::Multiply),
+        TokenContents::SlashEqual => Some(Opcode::Divide),
+        TokenContents::PercentEqual => Some(Opcode::Modulo),
+        _ => None,
+    }
+}
+
+/// The operator a postfix `++` or `--` applies to its variable.
+fn postfix_op(contents: &TokenContents) -> Option<Opcode> {
+    match contents {
+        TokenContents::PlusPlus => Some(Opcode::Add),
+        TokenContents::MinusMinus => Some(Opcode::Subtract),
+        _ => None,
+    }
+}
+
 fn get_parser<'a, 'b, 'c>(
     token: &'c Token,
     operator_type: OperatorType,
-) -> Option<(Parser<'a, 'b, 'c>, BindingPower)> {
+) -> Option<(ParseFn<'a, 'b, 'c>, BindingPower)> {
     match (&token.contents, operator_type) {
         (TokenContents::Minus | TokenContents::Bang, OperatorType::Prefix) => {
-            Some((Compiler::parse_unary, BindingPower::Unary))
+            Some((Parser::parse_unary, BindingPower::Unary))
         }
         (TokenContents::Number(_), OperatorType::Prefix) => {
-            Some((Compiler::parse_number, BindingPower::None))
+            Some((Parser::parse_number, BindingPower::None))
         }
         (TokenContents::Plus | TokenContents::Minus, OperatorType::Infix) => {
-            Some((Compiler::parse_term, BindingPower::Term))
+            Some((Parser::parse_term, BindingPower::Term))
         }
-        (TokenContents::Asterisk | TokenContents::Slash, OperatorType::Infix) => {
-            Some((Compiler::parse_factor, BindingPower::Factor))
+        (
+            TokenContents::Asterisk | TokenContents::Slash | TokenContents::Percent,
+            OperatorType::Infix,
+        ) => Some((Parser::parse_factor, BindingPower::Factor)),
+        (TokenContents::LeftParen, OperatorType::Infix) => {
+            Some((Parser::parse_call, BindingPower::Call))
         }
         (TokenContents::LeftParen, OperatorType::Prefix) => {
-            Some((Compiler::parse_grouping, BindingPower::None))
+            Some((Parser::parse_grouping, BindingPower::None))
         }
+        (TokenContents::LeftBrace, OperatorType::Prefix) => {
+            Some((Parser::parse_map, BindingPower::None))
+        }
+        (TokenContents::LeftBracket, OperatorType::Infix) => {
+            Some((Parser::parse_index, BindingPower::Call))
+        }
+        (TokenContents::Dot, OperatorType::Infix) => Some((Parser::parse_dot, BindingPower::Call)),
         (TokenContents::True | TokenContents::False | TokenContents::Nil, OperatorType::Prefix) => {
-            Some((Compiler::parse_literal, BindingPower::None))
+            Some((Parser::parse_literal, BindingPower::None))
         }
         (TokenContents::EqualEqual | TokenContents::BangEqual, OperatorType::Infix) => {
-            Some((Compiler::parse_equality, BindingPower::Equality))
+            Some((Parser::parse_equality, BindingPower::Equality))
         }
         (
             TokenContents::Greater
@@ -858,15 +1818,21 @@ fn get_parser<'a, 'b, 'c>(
             | TokenContents::Less
             | TokenContents::LessEqual,
             OperatorType::Infix,
-        ) => Some((Compiler::parse_comparison, BindingPower::Comparison)),
+        ) => Some((Parser::parse_comparison, BindingPower::Comparison)),
         (TokenContents::String(_), OperatorType::Prefix) => {
-            Some((Compiler::parse_string, BindingPower::None))
+            Some((Parser::parse_string, BindingPower::None))
         }
         (TokenContents::Identifier(_), OperatorType::Prefix) => {
-            Some((Compiler::parse_identifier, BindingPower::None))
+            Some((Parser::parse_identifier, BindingPower::None))
+        }
+        (TokenContents::Print, OperatorType::Prefix) => {
+            Some((Parser::parse_print, BindingPower::None))
         }
-        (TokenContents::And, OperatorType::Infix) => Some((Compiler::parse_and, BindingPower::And)),
-        (TokenContents::Or, OperatorType::Infix) => Some((Compiler::parse_or, BindingPower::Or)),
+        (TokenContents::Fun, OperatorType::Prefix) => {
+            Some((Parser::parse_lambda, BindingPower::None))
+        }
+        (TokenContents::And, OperatorType::Infix) => Some((Parser::parse_and, BindingPower::And)),
+        (TokenContents::Or, OperatorType::Infix) => Some((Parser::parse_or, BindingPower::Or)),
         _ => None,
     }
 }
@@ -877,11 +1843,13 @@ enum OperatorType {
     Infix,
 }
 
-type Parser<'a, 'b, 'c> = fn(&'c mut Compiler<'a, 'b>, &'c Token<'b>, bool) -> CompileResult<()>;
+type ParseFn<'a, 'b, 'c> = fn(&'c mut Parser<'a, 'b>, &'c Token<'b>, bool) -> CompileResult<()>;
 
 #[derive(Error, Debug, Clone)]
 pub struct CompileErrors {
     errors: Vec<CompileError>,
+    limit: usize,
+    suppressed: usize,
 }
 
 impl Display for CompileErrors {
@@ -895,28 +1863,56 @@ impl Display for CompileErrors {
         for e in self.errors.iter() {
             writeln!(f, "{e}")?;
         }
+        if self.suppressed > 0 {
+            writeln!(f, "{} further errors suppressed", self.suppressed)?;
+        }
         Ok(())
     }
 }
 
 impl CompileErrors {
     pub fn new() -> Self {
+        Self::with_limit(usize::MAX)
+    }
+
+    /// Keeps at most `limit` errors, counting any further ones as suppressed.
+    pub fn with_limit(limit: usize) -> Self {
         Self {
             errors: Vec::with_capacity(4),
+            limit,
+            suppressed: 0,
         }
     }
 
-    fn push(&mut self, e: CompileError) {
-        self.errors.push(e)
+    pub(crate) fn push(&mut self, e: CompileError) {
+        if self.errors.contains(&e) {
+            return;
+        }
+        if self.is_full() {
+            self.suppressed += 1;
+        } else {
+            self.errors.push(e)
+        }
     }
 
-    fn extend(&mut self, other: CompileErrors) {
-        self.errors.extend(other.errors.into_iter())
+    pub(crate) fn extend(&mut self, other: CompileErrors) {
+        for e in other.errors {
+            self.push(e);
+        }
+        self.suppressed += other.suppressed;
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.errors.len() >= self.limit
     }
 
     pub fn errors(&self) -> &[CompileError] {
         &self.errors
     }
+
+    pub fn suppressed(&self) -> usize {
+        self.suppressed
+    }
 }
 
 impl Default for CompileErrors {
@@ -949,7 +1945,7 @@ impl From<ScanError> for CompileErrors {
     }
 }
 
-#[derive(Error, Debug, Clone)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum CompileError {
     #[error(transparent)]
     ScanError(#[from] ScanError),
@@ -957,24 +1953,460 @@ pub enum CompileError {
     ParseError(#[from] ParseError),
 }
 
-#[derive(Error, Debug, Clone)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum ParseError {
-    #[error("Too many constants in one chunk.")]
-    TooManyConstants,
+    #[error("[line {0}] Error at '{1}': Too many constants in one chunk.")]
+    TooManyConstants(usize, String),
+    #[error("Too many global variables.")]
+    TooManyGlobals,
     #[error("[line {0}] Error at '=': Invalid assignment target.")]
     InvalidAssignmentTarget(usize),
+    #[error("[line {0}] Error at '{1}': Invalid increment target.")]
+    InvalidIncrementTarget(usize, String),
     #[error("[line {0}] Error at '{1}': Expect expression. (prefix)")]
     NoPrefixParser(usize, String),
     #[error("[line {0}] Error at '{1}': Expect expression. (infix)")]
     NoInfixParser(usize, String),
+    #[error("[line {0}] Error: Can't have more than 255 arguments.")]
+    TooManyArguments(usize),
+    #[error("[line {0}] Error: Can't have more than 255 entries in a map literal.")]
+    TooManyMapEntries(usize),
+    #[error("[line {0}] Error at '{1}': Expect ':' after map key.")]
+    MissingMapColon(usize, String),
+    #[error("[line {0}] Error at '{1}': Expect '}}' after map entries.")]
+    UnclosedMap(usize, String),
+    #[error("[line {0}] Error at '{1}': Expect ']' after index.")]
+    UnclosedIndex(usize, String),
+    #[error("[line {0}] Error at {1}: Expect property name after '.'.")]
+    MissingPropertyName(usize, String),
+    #[error("[line {line}] Error in '{construct}': Too much code to jump over.")]
+    JumpTooFar {
+        line: usize,
+        construct: &'static str,
+    },
+    #[error("[line {0}] Error at '{1}': Can't have more than 255 parameters.")]
+    TooManyParameters(usize, String),
+    #[error("[line {0}] Error at '{1}': Expect '(' after function name.")]
+    MissingParameterList(usize, String),
+    #[error("[line {0}] Error at '{1}': Expect ')' after parameters.")]
+    UnclosedParameterList(usize, String),
+    #[error("[line {0}] Error at '{1}': Expect '{{' before function body.")]
+    MissingFunctionBody(usize, String),
+    #[error("[line {0}] Error at 'return': Can't return from top-level code.")]
+    ReturnAtTopLevel(usize),
+    #[error("[line {0}] Error at '{1}': Can't capture local variables of an enclosing function.")]
+    CannotCapture(usize, String),
+    #[error("[line {0}] Error at '{1}': Can't use '{1}' outside of a loop.")]
+    LoopControlOutsideLoop(usize, String),
+    #[error("[line {0}] Error at ';': Expect expression.")]
+    ExpectExpressionAfterPrint(usize),
     #[error("[line {0}] Error at '{1}': Can't read local variable in its own initializer.")]
     LocalInOwnInitializer(usize, String),
     #[error("[line {0}] Error at '{1}': Expect variable name.")]
     NotAVariableName(usize, String),
+    #[error("[line {0}] Error at '{1}': Too many local variables in function.")]
+    TooManyLocals(usize, String),
     #[error("[line {0}] Error at '{1}': Already a variable with this name in this scope.")]
     DuplicateLocal(usize, String),
     #[error("[line {0}] Error at '{1}': Expect ';' after expression.")]
     MissingSemicolon(usize, String),
+    #[error("[line {0}] Error at '{1}': Expect end of expression.")]
+    UnexpectedToken(usize, String),
+    #[error("[line {0}] Warning: Possible infinite loop, the condition is never updated.")]
+    PossibleInfiniteLoop(usize),
+    #[error("[line {0}] Warning: Local variable '{1}' is never used.")]
+    UnusedLocal(usize, String),
+    #[error("[line {0}] Warning: Unreachable code.")]
+    UnreachableCode(usize),
     #[error("Compile error: {0}.")]
     GeneralError(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::allocator::Allocator;
+    use crate::memory::hash_table::HashTable;
+    use crate::scanner::Scanner;
+
+    fn compile_source(source: &str) -> Chunk {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap()
+    }
+
+    #[test]
+    fn logical_operators_are_balanced() {
+        for source in [
+            "false and 1 / 0;",
+            "nil or \"default\";",
+            "print true and nil or \"x\" and false;",
+            "{ var a = 1; a = (a or 2) and (nil or a); print a; }",
+            "if (1 and nil or 2) print 1; else print 2;",
+            "while (false or nil) print 1;",
+        ] {
+            let chunk = compile_source(source);
+            assert_eq!(chunk.check_stack_balance(), Ok(()), "{source}");
+        }
+    }
+
+    #[test]
+    fn else_if_chain_is_flat() {
+        let chunk = compile_source(
+            r#"
+var a = 3;
+if (a == 1) print 1;
+else if (a == 2) print 2;
+else if (a == 3) print 3;
+else if (a == 4) print 4;
+else if (a == 5) print 5;
+else print 6;
+"#,
+        );
+        let mut conditional_jumps = 0;
+        let mut end_targets = Vec::new();
+        let mut offset = 0;
+        while offset < chunk.len() {
+            let opcode = Opcode::try_from(chunk[offset]).unwrap();
+            let next = offset + 1 + opcode.operand_len();
+            match opcode {
+                Opcode::JumpIfFalse => conditional_jumps += 1,
+                Opcode::Jump => {
+                    let jump = ((chunk[offset + 1] as usize) << 8) | chunk[offset + 2] as usize;
+                    end_targets.push(next + jump);
+                }
+                _ => {}
+            }
+            offset = next;
+        }
+        assert_eq!(conditional_jumps, 5);
+        assert_eq!(end_targets.len(), 5);
+        // Every branch jumps to the end of the chain, which is the implicit `return nil`
+        assert!(end_targets.iter().all(|t| *t == chunk.len() - 2));
+    }
+
+    #[test]
+    fn jumps_have_the_line_of_their_statement() {
+        let chunk = compile_source(
+            "var a;\nif (a\n)\nprint 1;\nelse if\n(a) print 2;\nwhile (a\n)\na = nil;\nfor (;a;\n) {}",
+        );
+        let lines: Vec<_> = chunk
+            .instructions()
+            .filter(|(_, instruction, _)| {
+                matches!(
+                    instruction.opcode(),
+                    Opcode::JumpIfFalse | Opcode::Jump | Opcode::Loop
+                )
+            })
+            .map(|(_, instruction, line)| (instruction.opcode(), line))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (Opcode::JumpIfFalse, 2),
+                (Opcode::Jump, 2),
+                (Opcode::JumpIfFalse, 5),
+                (Opcode::Jump, 5),
+                (Opcode::JumpIfFalse, 7),
+                (Opcode::Loop, 7),
+                (Opcode::JumpIfFalse, 10),
+                (Opcode::Loop, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn constant_arithmetic_is_folded() {
+        let chunk = compile_source("print 1 + 2;");
+        let disassembled = chunk.disassemble();
+        assert!(!disassembled.contains("Constant"), "{disassembled}");
+        assert!(disassembled.contains("SmallInt 3"), "{disassembled}");
+        assert!(!disassembled.contains("Add"), "{disassembled}");
+        assert_eq!(chunk.constants_len(), 0, "{chunk:?}");
+
+        let chunk = compile_source("print -(1 + 2 * 3) / 7; print !true;");
+        let disassembled = chunk.disassemble();
+        assert!(disassembled.contains("Constant 0 -1"), "{disassembled}");
+        assert!(disassembled.contains("False"), "{disassembled}");
+        assert!(!disassembled.contains("Not"), "{disassembled}");
+        assert!(!disassembled.contains("Negate"), "{disassembled}");
+        assert_eq!(chunk.constants_len(), 1, "{chunk:?}");
+    }
+
+    #[test]
+    fn non_constant_operands_are_not_folded() {
+        let chunk = compile_source("var a = 1; print a + 2; print 1 + a; print 1 / 0; print !a;");
+        let disassembled = chunk.disassemble();
+        assert_eq!(disassembled.matches("Add").count(), 2, "{disassembled}");
+        assert_eq!(disassembled.matches("Divide").count(), 1, "{disassembled}");
+        assert_eq!(disassembled.matches("Not").count(), 1, "{disassembled}");
+    }
+
+    #[test]
+    fn block_locals_popped_at_once() {
+        let chunk = compile_source("{ var a = 1; var b = 2; { var c = 3; } var d = 4; }");
+        let disassembled = chunk.disassemble();
+        assert_eq!(disassembled.matches("PopN 3").count(), 1, "{disassembled}");
+        assert_eq!(disassembled.matches("Pop\n").count(), 1, "{disassembled}");
+    }
+
+    #[test]
+    fn empty_block_emits_nothing() {
+        let chunk = compile_source("{} { { } }");
+        assert_eq!(
+            chunk.disassemble().lines().count(),
+            3,
+            "{}",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn only_calls_in_tail_position_are_tail_calls() {
+        // The function lives in the memory manager, so it has to outlive the assertions
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let source =
+            "fun f(n) { if (n) return f(n - 1); if (n) return (nil or f(n)); return 1 + f(n); }";
+        let chunk = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+        let Some(Value::Obj(Object::Function(f))) = chunk.get_constant(0) else {
+            panic!("expected a function constant\n{chunk:?}");
+        };
+        let disassembled = f.chunk().disassemble();
+        assert_eq!(
+            disassembled.matches("TailCall 1").count(),
+            2,
+            "{disassembled}"
+        );
+        assert_eq!(disassembled.matches(" Call 1").count(), 1, "{disassembled}");
+    }
+
+    #[test]
+    fn code_after_return_is_not_emitted() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let source = "fun f() { print 1; return 2; print 3; var a = 4; { print a; } }";
+        let chunk = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+        let Some(Value::Obj(Object::Function(f))) = chunk.get_constant(0) else {
+            panic!("expected a function constant\n{chunk:?}");
+        };
+        let disassembled = f.chunk().disassemble();
+        assert_eq!(disassembled.matches("Call 1").count(), 1, "{disassembled}");
+        assert_eq!(f.chunk().constants_len(), 0, "{:?}", f.chunk());
+    }
+
+    #[test]
+    fn small_integers_are_immediate() {
+        let chunk = compile_source("print 5;");
+        let disassembled = chunk.disassemble();
+        assert!(disassembled.contains("SmallInt 5"), "{disassembled}");
+        assert_eq!(chunk.constants_len(), 0, "{chunk:?}");
+
+        let chunk = compile_source("print 255; print 256; print 256; print 1.5; print -1;");
+        let disassembled = chunk.disassemble();
+        assert!(disassembled.contains("SmallInt 255"), "{disassembled}");
+        assert_eq!(
+            disassembled.matches("Constant 0 256").count(),
+            2,
+            "{disassembled}"
+        );
+        assert!(disassembled.contains("Constant 1 1.5"), "{disassembled}");
+        assert!(disassembled.contains("Constant 2 -1"), "{disassembled}");
+        assert_eq!(chunk.constants_len(), 3, "{chunk:?}");
+    }
+
+    #[test]
+    fn or_uses_one_conditional_jump() {
+        let chunk = compile_source("var a; print a or 2;");
+        let opcodes: Vec<_> = chunk
+            .instructions()
+            .map(|(_, instruction, _)| instruction.opcode())
+            .collect();
+        assert_eq!(
+            opcodes,
+            [
+                Opcode::Nil,
+                Opcode::DefineGlobal,
+                Opcode::GetGlobal,
+                Opcode::GetGlobal,
+                Opcode::JumpIfTrue,
+                Opcode::Pop,
+                Opcode::SmallInt,
+                Opcode::Call,
+                Opcode::Pop,
+                Opcode::Nil,
+                Opcode::Return,
+            ],
+            "{}",
+            chunk.disassemble()
+        );
+        let disassembled = chunk.disassemble();
+        assert!(
+            disassembled.contains("JumpIfTrue 0x0003 -> 0x000d"),
+            "{disassembled}"
+        );
+        assert_eq!(chunk.check_stack_balance(), Ok(()), "{disassembled}");
+    }
+
+    #[test]
+    fn not_equal_is_one_opcode() {
+        let chunk = compile_source("var a; a != 1;");
+        let opcodes: Vec<_> = chunk
+            .instructions()
+            .map(|(_, instruction, _)| instruction.opcode())
+            .collect();
+        assert_eq!(
+            opcodes,
+            [
+                Opcode::Nil,
+                Opcode::DefineGlobal,
+                Opcode::GetGlobal,
+                Opcode::SmallInt,
+                Opcode::NotEqual,
+                Opcode::Pop,
+                Opcode::Nil,
+                Opcode::Return,
+            ],
+            "{}",
+            chunk.disassemble()
+        );
+    }
+
+    #[test]
+    fn code_after_break_is_not_emitted() {
+        let chunk = compile_source("while (true) { { break; print 1; } continue; print 2; }");
+        let disassembled = chunk.disassemble();
+        assert!(!disassembled.contains("Call"), "{disassembled}");
+        assert_eq!(chunk.check_stack_balance(), Ok(()), "{disassembled}");
+    }
+
+    #[test]
+    fn string_constants_are_shared_between_functions() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let source = r#"fun a() { return "shared"; } fun b() { print "shared"; }"#;
+        let chunk = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+        let shared = Value::Obj(Object::String(
+            memory_manager.get_interned("shared").unwrap(),
+        ));
+        for index in 0..2 {
+            let Some(Value::Obj(Object::Function(f))) = chunk.get_constant(index) else {
+                panic!("expected a function constant\n{chunk:?}");
+            };
+            assert_eq!(f.chunk().get_constant(0), Some(&shared), "{chunk:?}");
+        }
+    }
+
+    #[test]
+    fn errors_limited_and_deduplicated() {
+        let mut errors = CompileErrors::with_limit(10);
+        for line in 0..100 {
+            errors.push(ParseError::InvalidAssignmentTarget(line).into());
+            errors.push(ParseError::InvalidAssignmentTarget(0).into());
+        }
+        assert_eq!(errors.errors().len(), 10);
+        assert_eq!(errors.suppressed(), 90);
+        assert!(errors
+            .to_string()
+            .ends_with("90 further errors suppressed\n"));
+    }
+
+    #[test]
+    fn truncated_input_reports_errors() {
+        for (source, expected) in [
+            ("var", "Unexpected end of stream after 'var' declaration"),
+            ("{", "Didn't find matching closing brace"),
+            ("{ var a = 1;", "Didn't find matching closing brace"),
+            ("fun f() {", "Didn't find matching closing brace"),
+            ("while (true) {", "Didn't find matching closing brace"),
+        ] {
+            let alloc = Allocator::new();
+            let strings = HashTable::new(alloc.clone());
+            let mut memory_manager = MemoryManager::new(alloc, strings);
+            let errors = compile(&mut Scanner::new(source).iter(), &mut memory_manager)
+                .unwrap_err()
+                .to_string();
+            assert!(errors.contains(expected), "{source:?}: {errors}");
+        }
+    }
+
+    #[test]
+    fn extend_respects_limit() {
+        let mut errors = CompileErrors::with_limit(2);
+        let mut other = CompileErrors::new();
+        other.push(ParseError::TooManyConstants(1, "1".to_string()).into());
+        other.push(ParseError::TooManyGlobals.into());
+        other.push(ParseError::TooManyConstants(1, "1".to_string()).into());
+        other.push(ParseError::InvalidAssignmentTarget(1).into());
+        assert_eq!(other.errors().len(), 3);
+        errors.extend(other);
+        assert_eq!(errors.errors().len(), 2);
+        assert_eq!(errors.suppressed(), 1);
+    }
+
+    #[test]
+    fn compiler_can_be_fed_incrementally() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let mut compiler = Compiler::new(&mut memory_manager);
+        compiler
+            .feed(&mut Scanner::new("var a = 1;").iter())
+            .unwrap();
+        assert!(compiler
+            .feed(&mut Scanner::new("print a +;").iter())
+            .is_err());
+        compiler
+            .feed(&mut Scanner::new("print a + 1;").iter())
+            .unwrap();
+        let chunk = compiler.finish();
+
+        let mut out = Vec::new();
+        crate::vm::VM::new(&mut out, memory_manager, alloc)
+            .run(&chunk)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "2\n");
+    }
+
+    #[test]
+    fn unused_local_is_a_warning() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let mut compiler = Compiler::new(&mut memory_manager);
+        let source = "
+var global;
+fun f(unused_parameter) {
+  var used = 1;
+  var _ignored;
+  {
+    var unused = 2;
+  }
+  return used;
+}";
+        compiler.feed(&mut Scanner::new(source).iter()).unwrap();
+        assert_eq!(
+            compiler.warnings(),
+            [ParseError::UnusedLocal(7, "unused".to_string()).into()]
+        );
+    }
+
+    #[test]
+    fn unused_local_is_an_error_when_strict() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let source = "fun f() { var unused; }";
+        let errors = compile_strict(&mut Scanner::new(source).iter(), &mut memory_manager)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            errors.contains("[line 1] Warning: Local variable 'unused' is never used."),
+            "{errors}"
+        );
+    }
+}
@@ -1,21 +1,36 @@
-use crate::compiler::{compile, CompileErrors};
+use crate::chunk::ChunkError;
+use crate::compiler::{
+    compile, compile_expression, compile_strict, compile_with_warnings, log_warnings,
+};
 use crate::memory::allocator::Allocator;
 use crate::memory::hash_table::HashTable;
 use crate::memory::MemoryManager;
-use crate::scanner::Scanner;
-use crate::vm::{VMError, VM};
+use crate::vm::VM;
 use log::trace;
-use std::io::Write;
+use std::fmt::{Display, Formatter};
+use std::io::BufRead;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+pub mod ast;
 mod chunk;
 mod compiler;
 mod memory;
+mod native;
 mod scanner;
 mod value;
 mod vm;
 
-pub fn interpret<W: Write>(source: &str, write: &mut W) -> Result<(), InterpretError> {
+pub use ast::parse;
+pub use chunk::{Chunk, Instruction, Opcode};
+pub use compiler::{CompileError, CompileErrors, Compiler, ParseError};
+pub use scanner::{ScanError, ScanResult, Scanner, SourceIterator, Token, TokenContents};
+pub use value::Value;
+pub use vm::{
+    Output, RuntimeError, VMError, VMOptions, VmHook, DEFAULT_MAX_FRAMES, DEFAULT_STACK_SIZE,
+};
+
+pub fn interpret<W: Output>(source: &str, write: &mut W) -> Result<(), InterpretError> {
     trace!("Got input string: {source}");
     let scanner = Scanner::new(source);
     let alloc = Allocator::new();
@@ -27,10 +42,297 @@ pub fn interpret<W: Write>(source: &str, write: &mut W) -> Result<(), InterpretE
     Ok(())
 }
 
+/// Like [`interpret`], but returns everything the script printed.
+pub fn interpret_to_string(source: &str) -> Result<String, InterpretError> {
+    let mut out = Vec::new();
+    interpret(source, &mut out)?;
+    Ok(String::from_utf8(out).expect("Lox only writes valid UTF-8"))
+}
+
+/// Like [`interpret`], but compiler warnings are reported as errors, so a script with an unused
+/// local, unreachable code or a possibly infinite loop fails to compile.
+pub fn interpret_strict<W: Output>(source: &str, write: &mut W) -> Result<(), InterpretError> {
+    let scanner = Scanner::new(source);
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+    let chunk = compile_strict(&mut scanner.iter(), &mut memory_manager)?;
+    let mut vm = VM::new(write, memory_manager, alloc);
+    vm.run(&chunk)?;
+    Ok(())
+}
+
+/// Like [`interpret`], but hands each compiler warning, such as an unused local, to `on_warning`
+/// before running instead of only logging it.
+pub fn interpret_with_warnings<W: Output>(
+    source: &str,
+    write: &mut W,
+    on_warning: impl FnMut(&CompileError),
+) -> Result<(), InterpretError> {
+    let scanner = Scanner::new(source);
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+    let chunk = compile_with_warnings(&mut scanner.iter(), &mut memory_manager, on_warning)?;
+    let mut vm = VM::new(write, memory_manager, alloc);
+    vm.run(&chunk)?;
+    Ok(())
+}
+
+/// How long [`interpret_timed`] spent in each phase.
+#[derive(Debug, Clone, Copy)]
+pub struct Timings {
+    pub compile: Duration,
+    pub run: Duration,
+    /// The most bytes the interpreter had allocated at once.
+    pub peak_allocated: usize,
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "compile: {:?}", self.compile)?;
+        writeln!(f, "run: {:?}", self.run)?;
+        write!(f, "peak allocated: {} bytes", self.peak_allocated)
+    }
+}
+
+/// Like [`interpret_with_warnings`], but measures how long compiling and running took.
+pub fn interpret_timed<W: Output>(
+    source: &str,
+    write: &mut W,
+    on_warning: impl FnMut(&CompileError),
+) -> Result<Timings, InterpretError> {
+    let scanner = Scanner::new(source);
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+    let start = Instant::now();
+    let chunk = compile_with_warnings(&mut scanner.iter(), &mut memory_manager, on_warning)?;
+    let compile_time = start.elapsed();
+    let mut vm = VM::new(write, memory_manager, alloc.clone());
+    let start = Instant::now();
+    vm.run(&chunk)?;
+    let run_time = start.elapsed();
+    Ok(Timings {
+        compile: compile_time,
+        run: run_time,
+        peak_allocated: alloc.peak_allocated(),
+    })
+}
+
+/// Like [`interpret`], but runs the VM with the given options.
+pub fn interpret_with_options<W: Output>(
+    source: &str,
+    write: &mut W,
+    options: VMOptions,
+) -> Result<(), InterpretError> {
+    let scanner = Scanner::new(source);
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+    let chunk = compile(&mut scanner.iter(), &mut memory_manager)?;
+    let mut vm = VM::new(write, memory_manager, alloc).with_options(options);
+    vm.run(&chunk)?;
+    Ok(())
+}
+
+/// Like [`interpret`], but `read_line` reads from `input` instead of stdin.
+pub fn interpret_with_input<W: Output>(
+    source: &str,
+    write: &mut W,
+    input: impl BufRead,
+) -> Result<(), InterpretError> {
+    let scanner = Scanner::new(source);
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+    let chunk = compile(&mut scanner.iter(), &mut memory_manager)?;
+    let mut vm = VM::new(write, memory_manager, alloc).with_input(input);
+    vm.run(&chunk)?;
+    Ok(())
+}
+
+/// Interprets a line typed into the REPL. A bare expression has its value printed, anything else
+/// is interpreted as statements.
+pub fn interpret_repl_line<W: Output>(source: &str, write: &mut W) -> Result<(), InterpretError> {
+    Session::new(write).interpret_repl_line(source)
+}
+
+/// An interpreter that keeps its globals and interned strings between scripts.
+pub struct Session<'a, W: Output> {
+    vm: VM<'a, W>,
+}
+
+impl<'a, W: Output> Session<'a, W> {
+    pub fn new(write: &'a mut W) -> Self {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let memory_manager = MemoryManager::new(alloc.clone(), strings);
+        Self {
+            vm: VM::new(write, memory_manager, alloc),
+        }
+    }
+
+    pub fn interpret(&mut self, source: &str) -> Result<(), InterpretError> {
+        self.vm.reset();
+        let chunk = compile(
+            &mut Scanner::new(source).iter(),
+            self.vm.memory_manager_mut(),
+        )?;
+        self.vm.run(&chunk)?;
+        Ok(())
+    }
+
+    /// Like [`interpret_repl_line`], but globals defined by earlier lines stay visible.
+    pub fn interpret_repl_line(&mut self, source: &str) -> Result<(), InterpretError> {
+        let memory_manager = self.vm.memory_manager_mut();
+        let chunk = match compile_expression(&mut Scanner::new(source).iter(), memory_manager) {
+            Ok(chunk) => chunk,
+            Err(_) => {
+                let mut compiler = self.compiler();
+                let result = compiler.feed(&mut Scanner::new(source).iter());
+                log_warnings(compiler.warnings());
+                result?;
+                compiler.finish()
+            }
+        };
+        self.run(&chunk)
+    }
+
+    /// A compiler that shares this session's globals and interned strings, for chunks to run with
+    /// [`Session::run`].
+    pub fn compiler(&mut self) -> Compiler<'_> {
+        Compiler::new(self.vm.memory_manager_mut())
+    }
+
+    /// Runs a chunk finished by a compiler from [`Session::compiler`]. Chunks compiled for another
+    /// session are rejected.
+    pub fn run(&mut self, chunk: &Chunk) -> Result<(), InterpretError> {
+        if !chunk.uses_allocator(&self.vm.memory_manager_mut().alloc()) {
+            return Err(InterpretError::ForeignChunk);
+        }
+        self.vm.reset();
+        self.vm.run(chunk)?;
+        Ok(())
+    }
+}
+
+/// Like [`interpret`], but calls `hook` before every instruction instead of trace logging.
+pub fn interpret_with_hook<W: Output>(
+    source: &str,
+    write: &mut W,
+    hook: impl VmHook,
+) -> Result<(), InterpretError> {
+    let scanner = Scanner::new(source);
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+    let chunk = compile(&mut scanner.iter(), &mut memory_manager)?;
+    let mut vm = VM::new(write, memory_manager, alloc).with_hook(hook);
+    vm.run(&chunk)?;
+    Ok(())
+}
+
+/// Scans `source` into tokens, each with the 1-based column it starts at. Scanning continues after
+/// errors.
+pub fn tokenize(source: &str) -> Vec<(ScanResult<Token<'_>>, usize)> {
+    let mut iter = Scanner::new(source).iter();
+    let mut tokens = Vec::new();
+    while let Some(token) = iter.next() {
+        tokens.push((token, iter.last_token_column()));
+    }
+    tokens
+}
+
+/// Compiles `source` to a bytecode file that can be executed with [`run_bytes`].
+pub fn compile_to_bytes(source: &str) -> Result<Vec<u8>, InterpretError> {
+    let scanner = Scanner::new(source);
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc, strings);
+    let chunk = compile(&mut scanner.iter(), &mut memory_manager)?;
+    Ok(chunk.to_bytes(&memory_manager)?)
+}
+
+/// Runs bytecode produced by [`compile_to_bytes`].
+pub fn run_bytes<W: Output>(bytes: &[u8], write: &mut W) -> Result<(), InterpretError> {
+    let alloc = Allocator::new();
+    let strings = HashTable::new(alloc.clone());
+    let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+    let chunk = Chunk::from_bytes(bytes, &mut memory_manager)?;
+    let mut vm = VM::new(write, memory_manager, alloc);
+    vm.run(&chunk)?;
+    Ok(())
+}
+
 #[derive(Error, Debug, Clone)]
 pub enum InterpretError {
     #[error(transparent)]
     CompileErrors(#[from] CompileErrors),
     #[error(transparent)]
     InterpretError(#[from] VMError),
+    #[error("Invalid bytecode: {0}")]
+    InvalidBytecode(#[from] ChunkError),
+    #[error("Chunk was compiled for another session")]
+    ForeignChunk,
+}
+
+impl InterpretError {
+    /// The error message, with the offending line of `source` and a caret under the column for
+    /// errors that know where they are.
+    pub fn render(&self, source: &str) -> String {
+        let InterpretError::CompileErrors(errors) = self else {
+            return format!("{self}\n");
+        };
+        let mut rendered = String::new();
+        for e in errors.errors() {
+            match e {
+                CompileError::ScanError(scan_error) => match scan_error.column(source) {
+                    Some(col) => rendered.push_str(&render_diagnostic(
+                        source,
+                        scan_error.line(),
+                        col,
+                        &e.to_string(),
+                    )),
+                    None => rendered.push_str(&format!("{e}\n")),
+                },
+                CompileError::ParseError(_) => rendered.push_str(&format!("{e}\n")),
+            }
+        }
+        if errors.suppressed() > 0 {
+            rendered.push_str(&format!(
+                "{} further errors suppressed\n",
+                errors.suppressed()
+            ));
+        }
+        rendered
+    }
+}
+
+/// Formats `msg` followed by line `line` of `source` with a caret under the 1-based column `col`.
+pub fn render_diagnostic(source: &str, line: usize, col: usize, msg: &str) -> String {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let gutter = line.to_string();
+    let padding = " ".repeat(gutter.len());
+    format!("{msg}\n{gutter} | {text}\n{padding} | {:>col$}\n", "^")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpret_to_string_captures_output() {
+        assert_eq!(interpret_to_string("print 1+1;").unwrap(), "2\n");
+        assert!(interpret_to_string("print nope;").is_err());
+    }
+
+    #[test]
+    fn diagnostic_points_at_column() {
+        let rendered = render_diagnostic("var a;\nprint a @ 1;", 2, 9, "Unknown token @");
+        assert_eq!(
+            rendered,
+            "Unknown token @\n2 | print a @ 1;\n  |         ^\n"
+        );
+    }
 }
@@ -2,7 +2,10 @@ use anyhow::Result;
 use clap::Parser;
 use env_logger::Builder;
 use log::{error, LevelFilter};
-use lox::interpret;
+use lox::{
+    compile_to_bytes, interpret_strict, interpret_timed, interpret_with_warnings, parse, run_bytes,
+    tokenize, InterpretError, Session,
+};
 use std::io::BufRead;
 use std::io::Write;
 use std::path::PathBuf;
@@ -11,14 +14,42 @@ use std::path::PathBuf;
 struct Args {
     #[arg(short, long)]
     file: Option<PathBuf>,
+    /// Compile the source file to bytecode at this path instead of running it
+    #[arg(short, long, requires = "file")]
+    compile: Option<PathBuf>,
+    /// Run a compiled bytecode file
+    #[arg(short, long, conflicts_with = "file")]
+    run: Option<PathBuf>,
+    /// Treat compiler warnings, such as unused locals, as errors
+    #[arg(short, long, requires = "file")]
+    strict: bool,
+    /// Print how long compiling and running took to stderr
+    #[arg(short, long, requires = "file", conflicts_with_all = ["compile", "strict"])]
+    time: bool,
+    /// Print the tokens of the source file, one per line with its line and column, and exit
+    #[arg(long, requires = "file", conflicts_with_all = ["compile", "strict", "time"])]
+    tokens: bool,
+    /// Print the syntax tree of the source file and exit
+    #[arg(long, requires = "file", conflicts_with_all = ["compile", "strict", "time", "tokens"])]
+    ast: bool,
 }
 
 fn main() -> Result<()> {
     init_logger();
     let args = Args::parse();
 
-    if let Some(path) = args.file {
-        run_file(&path)?;
+    if let Some(path) = args.run {
+        run_bytecode_file(&path)?;
+    } else if let Some(path) = args.file {
+        if let Some(out) = args.compile {
+            compile_file(&path, &out)?;
+        } else if args.tokens {
+            print_tokens(&path)?;
+        } else if args.ast {
+            print_ast(&path)?;
+        } else {
+            run_file(&path, args.strict, args.time)?;
+        }
     } else {
         repl()?
     }
@@ -31,12 +62,14 @@ fn repl() -> Result<()> {
     write!(stdout, ">")?;
     stdout.flush()?;
     let stdin = std::io::stdin();
+    let mut out = std::io::stdout();
+    let mut session = Session::new(&mut out);
     for line in stdin.lock().lines() {
         let line = line?;
         if line.is_empty() {
             break;
         }
-        match interpret(&line, &mut std::io::stdout()) {
+        match session.interpret_repl_line(&line) {
             Ok(_) => {}
             Err(e) => error!("Error: {e}"),
         }
@@ -47,9 +80,74 @@ fn repl() -> Result<()> {
     Ok(())
 }
 
-fn run_file(path: &PathBuf) -> Result<()> {
+fn run_file(path: &PathBuf, strict: bool, time: bool) -> Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let result = if strict {
+        interpret_strict(&contents, &mut std::io::stdout())
+    } else if time {
+        interpret_timed(&contents, &mut std::io::stdout(), |warning| {
+            eprintln!("{warning}")
+        })
+        .map(|timings| eprintln!("{timings}"))
+    } else {
+        interpret_with_warnings(&contents, &mut std::io::stdout(), |warning| {
+            eprintln!("{warning}")
+        })
+    };
+    if let Err(e @ InterpretError::CompileErrors(_)) = result {
+        eprint!("{}", e.render(&contents));
+        std::process::exit(65);
+    }
+    result?;
+    Ok(())
+}
+
+fn compile_file(path: &PathBuf, out: &PathBuf) -> Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let bytes = compile_to_bytes(&contents)?;
+    std::fs::write(out, bytes)?;
+    Ok(())
+}
+
+fn print_tokens(path: &PathBuf) -> Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut stdout = std::io::stdout();
+    let mut failed = false;
+    for (token, column) in tokenize(&contents) {
+        match token {
+            Ok(token) => writeln!(stdout, "{}:{column} {:?}", token.line, token.contents)?,
+            Err(e) => {
+                writeln!(stdout, "{}:{column} error: {e}", e.line())?;
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(65);
+    }
+    Ok(())
+}
+
+fn print_ast(path: &PathBuf) -> Result<()> {
     let contents = std::fs::read_to_string(path)?;
-    interpret(&contents, &mut std::io::stdout())?;
+    match parse(&contents) {
+        Ok(statements) => {
+            let mut stdout = std::io::stdout();
+            for statement in statements {
+                writeln!(stdout, "{statement:#?}")?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprint!("{}", InterpretError::from(e).render(&contents));
+            std::process::exit(65);
+        }
+    }
+}
+
+fn run_bytecode_file(path: &PathBuf) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    run_bytes(&bytes, &mut std::io::stdout())?;
     Ok(())
 }
 
@@ -7,20 +7,36 @@ use std::sync::Arc;
 #[derive(Debug)]
 pub struct Allocator {
     allocated: AtomicUsize,
+    peak: AtomicUsize,
 }
 
 impl Allocator {
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
             allocated: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
         })
     }
 
+    pub fn bytes_allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// The most bytes that were allocated at once.
+    pub fn peak_allocated(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    fn record_growth(&self, size: usize) {
+        let total = self.allocated.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(total, Ordering::Relaxed);
+    }
+
     pub unsafe fn allocate(&self, layout: Layout) -> NonNull<u8> {
         let ptr = alloc(layout);
         match NonNull::new(ptr) {
             Some(ptr) => {
-                self.allocated.fetch_add(layout.size(), Ordering::Relaxed);
+                self.record_growth(layout.size());
                 trace!(
                     "Allocated {} bytes for a new total of {}",
                     layout.size(),
@@ -46,7 +62,7 @@ impl Allocator {
         let ptr = realloc(old_ptr.as_ptr(), old_layout, new_layout.size());
         match NonNull::new(ptr) {
             Some(ptr) => {
-                self.allocated.fetch_add(diff, Ordering::Relaxed);
+                self.record_growth(diff);
                 trace!(
                     "Reallocated {} extra bytes for a new total of {}",
                     diff,
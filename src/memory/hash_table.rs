@@ -1,8 +1,10 @@
 use crate::memory::allocator::Allocator;
-use crate::memory::{ObjString, VMHeap};
+use crate::memory::{ObjString, Object, VMHeap, VMHeapVec};
 use crate::value::Value;
 use std::alloc::Layout;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
 use std::ptr::NonNull;
 use std::sync::Arc;
 
@@ -11,10 +13,13 @@ pub struct HashTable {
     capacity: usize,
     entries: NonNull<Entry>,
     alloc: Arc<Allocator>,
+    max_load: f64,
+    /// Live keys in insertion order, only kept by tables made with [`HashTable::with_insertion_order`].
+    order: Option<VMHeapVec<Value>>,
 }
 
 impl HashTable {
-    const MAX_LOAD: f64 = 0.75;
+    const DEFAULT_MAX_LOAD: f64 = 0.75;
 
     pub fn new(alloc: Arc<Allocator>) -> Self {
         Self {
@@ -22,27 +27,64 @@ impl HashTable {
             capacity: 0,
             entries: NonNull::dangling(),
             alloc,
+            max_load: Self::DEFAULT_MAX_LOAD,
+            order: None,
         }
     }
 
-    pub(in crate::memory) fn get_string(
-        &self,
-        key: NonNull<ObjString>,
-    ) -> Option<VMHeap<ObjString>> {
+    /// A table that holds `count` keys without growing.
+    pub(crate) fn with_room_for(alloc: Arc<Allocator>, count: usize) -> Self {
+        let capacity = (count as f64 / Self::DEFAULT_MAX_LOAD).ceil() as usize;
+        Self::with_config(alloc, capacity, Self::DEFAULT_MAX_LOAD)
+    }
+
+    /// Makes [`HashTable::iter`] return entries in the order their keys were first inserted, at
+    /// the cost of tracking the keys separately. Must be called before anything is inserted. Lox
+    /// maps use it so they print the same way every run; it isn't reachable by embedders.
+    pub(crate) fn with_insertion_order(mut self) -> Self {
+        assert_eq!(self.count, 0, "Table already has entries");
+        self.order = Some(VMHeapVec::new(self.alloc.clone()));
+        self
+    }
+
+    /// A table with `initial_capacity` entries allocated up front, that grows once more than
+    /// `max_load` of its entries would be in use. Only the crate itself tunes tables, embedders
+    /// can't reach them.
+    ///
+    /// # Panics
+    /// If `max_load` isn't strictly between 0 and 1.
+    pub(crate) fn with_config(
+        alloc: Arc<Allocator>,
+        initial_capacity: usize,
+        max_load: f64,
+    ) -> Self {
+        assert!(
+            max_load > 0.0 && max_load < 1.0,
+            "max_load must be between 0 and 1, got {max_load}"
+        );
+        let mut table = Self::new(alloc);
+        table.max_load = max_load;
+        if initial_capacity > 0 {
+            table.adjust_capacity(initial_capacity);
+        }
+        table
+    }
+
+    pub(in crate::memory) fn get_string(&self, key: &str, hash: u32) -> Option<VMHeap<ObjString>> {
         if self.count == 0 {
             return None;
         }
         unsafe {
-            let hash = ObjString::hash(key) as usize;
-            let index = hash % self.capacity;
+            let index = hash as usize % self.capacity;
             for i in 0..self.capacity {
                 let entry = self.entries.as_ptr().add((index + i) % self.capacity);
                 match (*entry).key {
-                    Some(entry_key) => {
-                        if entry_key.as_str() == (*key.as_ptr()).as_str() {
+                    Some(Value::Obj(Object::String(entry_key))) => {
+                        if entry_key.hash == hash && entry_key.as_str() == key {
                             return Some(entry_key);
                         }
                     }
+                    Some(_) => {}
                     None => {
                         if (*entry).value == Value::Nil {
                             return None;
@@ -55,7 +97,7 @@ impl HashTable {
     }
 
     pub unsafe fn clear(&mut self) {
-        if self.count != 0 {
+        if self.capacity != 0 {
             self.alloc.dealloc(
                 self.entries.cast::<u8>(),
                 Layout::array::<Entry>(self.capacity).unwrap(),
@@ -63,13 +105,44 @@ impl HashTable {
         }
         self.count = 0;
         self.capacity = 0;
+        if let Some(order) = &mut self.order {
+            order.truncate(0);
+        }
     }
 
-    pub fn get(&self, key: VMHeap<ObjString>) -> Option<&Value> {
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Live entries, in insertion order if the table keeps it and in no particular order otherwise.
+    pub fn iter(&self) -> impl Iterator<Item = (Value, Value)> + '_ {
+        let ordered = self.order.as_ref().map(|order| {
+            order.iter().map(|&key| {
+                (
+                    key,
+                    *self.get(key).expect("Ordered key should be in the table"),
+                )
+            })
+        });
+        let unordered = match self.order {
+            Some(_) => None,
+            None => Some(
+                self.entries_as_slice()
+                    .iter()
+                    .filter_map(|entry| entry.key.map(|key| (key, entry.value))),
+            ),
+        };
+        ordered
+            .into_iter()
+            .flatten()
+            .chain(unordered.into_iter().flatten())
+    }
+
+    pub fn get(&self, key: Value) -> Option<&Value> {
         if self.count == 0 {
             return None;
         }
-        let entry = Self::find_entry(self.entries, key.0, self.capacity);
+        let entry = Self::find_entry(self.entries, key, self.capacity);
         unsafe {
             if (*entry.as_ptr()).key.is_some() {
                 let entry = &*(entry.as_ptr());
@@ -81,35 +154,21 @@ impl HashTable {
     }
 
     // TODO Option<Value>
-    pub fn delete(&mut self, key: VMHeap<ObjString>) -> bool {
-        if self.count == 0 {
-            return false;
-        }
-
-        unsafe {
-            let entry = Self::find_entry(self.entries, key.0, self.capacity);
-            if (*entry.as_ptr()).key.is_none() {
-                return false;
-            }
-            entry.as_ptr().write(Entry {
-                key: None,
-                value: Value::Boolean(true),
-            });
-            true
-        }
-    }
-
-    // TODO Option<Value>
-    pub fn insert(&mut self, key: VMHeap<ObjString>, value: Value) -> bool {
-        if (self.count + 1) as f64 > (self.capacity as f64) * Self::MAX_LOAD {
+    /// Keys must equal themselves, so `NaN` can't be used, see [`Value::is_valid_key`].
+    pub fn insert(&mut self, key: Value, value: Value) -> bool {
+        debug_assert!(key.is_valid_key(), "{key:?} can't be a key");
+        if (self.count + 1) as f64 > (self.capacity as f64) * self.max_load {
             let new_capacity = self.grow_capacity();
             self.adjust_capacity(new_capacity)
         }
-        let entry = Self::find_entry(self.entries, key.0, self.capacity);
+        let entry = Self::find_entry(self.entries, key, self.capacity);
         unsafe {
             let is_new_key = (*entry.as_ptr()).key.is_none();
             if is_new_key {
                 self.count += 1;
+                if let Some(order) = &mut self.order {
+                    order.push(key);
+                }
             }
 
             (*entry.as_ptr()).key = Some(key);
@@ -144,18 +203,17 @@ impl HashTable {
                 };
                 entries.as_ptr().add(i).write(entry)
             }
-            let prev_count = self.count;
             self.count = 0;
             for i in 0..self.capacity {
                 let source = self.entries.as_ptr().add(i).read();
                 if let Some(key) = source.key {
-                    let dest = Self::find_entry(entries, key.0, new_capacity);
+                    let dest = Self::find_entry(entries, key, new_capacity);
                     dest.as_ptr().write(source);
                     self.count += 1;
                 }
             }
 
-            if prev_count != 0 {
+            if self.capacity != 0 {
                 self.alloc.dealloc(
                     self.entries.cast::<u8>(),
                     Layout::array::<Entry>(self.capacity).unwrap(),
@@ -167,13 +225,21 @@ impl HashTable {
         }
     }
 
-    fn find_entry(
-        entries: NonNull<Entry>,
-        key: NonNull<ObjString>,
-        capacity: usize,
-    ) -> NonNull<Entry> {
+    /// Strings reuse the hash computed when they were created, so interning doesn't hash twice.
+    fn hash(key: Value) -> u32 {
+        match key {
+            Value::Obj(Object::String(s)) => s.hash,
+            key => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                hasher.finish() as u32
+            }
+        }
+    }
+
+    fn find_entry(entries: NonNull<Entry>, key: Value, capacity: usize) -> NonNull<Entry> {
         unsafe {
-            let hash = ObjString::hash(key) as usize;
+            let hash = Self::hash(key) as usize;
             let index = hash % capacity;
             let mut tombstone: Option<NonNull<Entry>> = None;
             for i in 0..capacity {
@@ -191,7 +257,7 @@ impl HashTable {
                         }
                     }
                     Some(entry_key) => {
-                        if entry_key.0 == key {
+                        if entry_key == key {
                             return entry;
                         }
                     }
@@ -223,7 +289,7 @@ impl Debug for HashTable {
 }
 
 struct Entry {
-    key: Option<VMHeap<ObjString>>,
+    key: Option<Value>,
     value: Value,
 }
 
@@ -232,7 +298,6 @@ impl Debug for Entry {
         f.debug_struct("Entry")
             .field("key", &self.key)
             .field("value", &self.value)
-            .field("key_val", &self.key.map(|k| k.as_str().to_string()))
             .finish()
     }
 }
@@ -250,10 +315,10 @@ mod tests {
         let strings = HashTable::new(alloc.clone());
         let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
         let mut table = HashTable::new(alloc);
-        let key = memory_manager.new_str_copied("hi!");
+        let key = memory_manager.intern("hi!");
         let value = Value::Number(1.5);
-        assert!(table.insert(key, value));
-        assert!(!table.insert(key, value));
+        assert!(table.insert(key.into(), value));
+        assert!(!table.insert(key.into(), value));
     }
 
     #[test]
@@ -264,19 +329,19 @@ mod tests {
         let mut table = HashTable::new(alloc);
         let kvs: Vec<_> = (0..MAX)
             .map(|i| {
-                let key = memory_manager.new_str_copied(&format!("hi{i}"));
+                let key = memory_manager.intern(&format!("hi{i}"));
                 let value = Value::Number(i as f64);
                 (key, value)
             })
             .collect();
 
         for (k, v) in kvs.iter() {
-            assert!(table.insert(*k, *v), "{k:?}, {k}, {v}");
-            assert_eq!(table.get(*k).unwrap(), v, "{k:?}, {k}, {v}");
-            assert!(!table.insert(*k, *v), "{k:?}, {k}, {v}");
+            assert!(table.insert((*k).into(), *v), "{k:?}, {k}, {v}");
+            assert_eq!(table.get((*k).into()).unwrap(), v, "{k:?}, {k}, {v}");
+            assert!(!table.insert((*k).into(), *v), "{k:?}, {k}, {v}");
         }
         for (k, v) in kvs.iter() {
-            assert_eq!(table.get(*k).unwrap(), v, "{k:?}, {k}, {v}");
+            assert_eq!(table.get((*k).into()).unwrap(), v, "{k:?}, {k}, {v}");
         }
     }
 
@@ -286,37 +351,107 @@ mod tests {
         let strings = HashTable::new(alloc.clone());
         let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
         let mut table = HashTable::new(alloc);
-        let key = memory_manager.new_str_copied("hi!");
+        let key = memory_manager.intern("hi!");
         let value = Value::Number(1.5);
-        assert_eq!(table.get(key), None);
-        assert!(table.insert(key, value));
-        assert_eq!(table.get(key).unwrap(), &value);
-        assert!(!table.insert(key, value));
+        assert_eq!(table.get(key.into()), None);
+        assert!(table.insert(key.into(), value));
+        assert_eq!(table.get(key.into()).unwrap(), &value);
+        assert!(!table.insert(key.into(), value));
+    }
+
+    fn keys(memory_manager: &mut MemoryManager, count: usize) -> Vec<Value> {
+        (0..count)
+            .map(|i| memory_manager.intern(&format!("key{i}")).into())
+            .collect()
     }
 
     #[test]
-    fn delete() {
+    fn preallocated_table_does_not_resize_early() {
         let alloc = Allocator::new();
         let strings = HashTable::new(alloc.clone());
         let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
-        let mut table = HashTable::new(alloc);
-        let kvs: Vec<_> = (0..MAX)
-            .map(|i| {
-                let key = memory_manager.new_str_copied(&format!("hi{i}"));
-                let value = Value::Number(i as f64);
-                (key, value)
-            })
-            .collect();
+        let keys = keys(&mut memory_manager, 49);
 
-        for (k, v) in kvs.iter() {
-            assert!(table.insert(*k, *v), "{k:?}, {k}, {v}");
-            assert_eq!(table.get(*k).unwrap(), v, "{k:?}, {k}, {v}");
-            assert!(!table.insert(*k, *v), "{k:?}, {k}, {v}");
-            assert!(table.delete(*k));
-            assert_eq!(table.get(*k), None, "{k:?}, {k}, {v}");
+        let before = alloc.bytes_allocated();
+        let mut table = HashTable::with_config(alloc.clone(), 64, 0.75);
+        let preallocated = alloc.bytes_allocated();
+        assert!(preallocated > before);
+        for (i, key) in keys[..48].iter().enumerate() {
+            assert!(table.insert(*key, Value::Number(i as f64)));
+            assert_eq!(alloc.bytes_allocated(), preallocated);
         }
-        for (k, v) in kvs.iter() {
-            assert_eq!(table.get(*k), None, "{k:?}, {k}, {v}");
+        assert_eq!(table.capacity, 64);
+
+        table.insert(keys[48], Value::Nil);
+        assert_eq!(table.capacity, 128);
+        drop(table);
+        assert_eq!(alloc.bytes_allocated(), before);
+    }
+
+    #[test]
+    fn lower_load_factor_grows_earlier() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let keys = keys(&mut memory_manager, 3);
+
+        let mut default = HashTable::with_config(alloc.clone(), 8, 0.75);
+        let mut sparse = HashTable::with_config(alloc.clone(), 8, 0.25);
+        for key in &keys {
+            default.insert(*key, Value::Nil);
+            sparse.insert(*key, Value::Nil);
+        }
+        assert_eq!(default.capacity, 8);
+        assert_eq!(sparse.capacity, 16);
+        for key in &keys {
+            assert_eq!(sparse.get(*key), Some(&Value::Nil));
+        }
+    }
+
+    #[test]
+    fn room_for_count_keys() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        for count in [0, 1, 3, 6, 7, 100] {
+            let keys = keys(&mut memory_manager, count);
+            let mut table = HashTable::with_room_for(alloc.clone(), count);
+            let capacity = table.capacity;
+            for key in keys {
+                table.insert(key, Value::Nil);
+            }
+            assert_eq!(table.capacity, capacity, "{count}");
         }
     }
+
+    #[test]
+    #[should_panic(expected = "max_load must be between 0 and 1")]
+    fn max_load_must_be_a_fraction() {
+        HashTable::with_config(Allocator::new(), 8, 1.0);
+    }
+
+    #[test]
+    fn insertion_order_is_kept() {
+        let mut table = HashTable::new(Allocator::new()).with_insertion_order();
+        let keys: Vec<_> = (0..MAX).map(|i| ((i * 7919) % MAX) as f64).collect();
+        for &key in &keys {
+            table.insert(Value::Number(key), Value::Boolean(true));
+        }
+        table.insert(Value::Number(keys[1]), Value::Nil);
+
+        // Updating keeps a key's place
+        let expected: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, &key)| {
+                let value = if i == 1 {
+                    Value::Nil
+                } else {
+                    Value::Boolean(true)
+                };
+                (Value::Number(key), value)
+            })
+            .collect();
+        assert_eq!(table.iter().collect::<Vec<_>>(), expected);
+    }
 }
@@ -1,13 +1,18 @@
+use crate::chunk::Chunk;
 use crate::memory::allocator::Allocator;
 use crate::memory::hash_table::HashTable;
+use crate::native::NativeFn;
 use crate::value::Value;
-use arrayvec::ArrayVec;
 use std::alloc::Layout;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 use std::ptr::NonNull;
 use std::sync::Arc;
 use std::{ptr, slice};
+use unicode_segmentation::UnicodeSegmentation;
 
 pub mod allocator;
 pub mod hash_table;
@@ -15,71 +20,205 @@ mod vec;
 
 pub use vec::VMHeapVec;
 
-const STACK_SIZE: usize = 256;
+/// Global slots are encoded in at most three bytes.
+const MAX_GLOBALS: usize = 1 << 24;
 
 #[derive(Debug)]
 pub struct MemoryManager {
     known_objects: Option<Object>,
     alloc: Arc<Allocator>,
     strings: HashTable,
-    stack: ArrayVec<Value, STACK_SIZE>,
+    stack: VMHeapVec<Value>,
+    /// Slot of each global's name, as a `Value::Number`.
+    global_slots: HashTable,
+    global_names: VMHeapVec<VMHeap<ObjString>>,
 }
 
 impl MemoryManager {
     pub fn new(alloc: Arc<Allocator>, strings: HashTable) -> Self {
         Self {
             known_objects: None,
-            alloc,
+            alloc: alloc.clone(),
             strings,
-            stack: ArrayVec::new(),
+            stack: VMHeapVec::new(alloc.clone()),
+            global_slots: HashTable::new(alloc.clone()),
+            global_names: VMHeapVec::new(alloc),
+        }
+    }
+
+    /// Returns the stable slot index for the global with this name, assigning a new one if needed.
+    pub fn global_slot(&mut self, name: &str) -> Option<u32> {
+        let name = self.intern(name);
+        if let Some(&Value::Number(slot)) = self.global_slots.get(name.into()) {
+            return Some(slot as u32);
+        }
+        if self.global_names.len() >= MAX_GLOBALS {
+            return None;
         }
+        let slot = self.global_names.len() as u32;
+        self.global_slots
+            .insert(name.into(), Value::Number(slot as f64));
+        self.global_names.push(name);
+        Some(slot)
+    }
+
+    pub fn global_names(&self) -> &[VMHeap<ObjString>] {
+        &self.global_names
+    }
+
+    pub fn global_name(&self, slot: u32) -> Option<VMHeap<ObjString>> {
+        self.global_names.get(slot as usize).copied()
     }
 
     pub fn alloc(&self) -> Arc<Allocator> {
         self.alloc.clone()
     }
 
-    pub fn new_str_copied(&mut self, s: &str) -> VMHeap<ObjString> {
-        let s = ObjString::new_copied(s, self.alloc.clone());
-        if let Some(str) = self.strings.get_string(NonNull::from(&s)) {
+    /// Returns the interned string with these contents, creating it if it doesn't exist yet. Only
+    /// the crate interns strings, embedders can't define natives or globals to hand them to.
+    pub(crate) fn intern(&mut self, s: &str) -> VMHeap<ObjString> {
+        if let Some(str) = self.get_interned(s) {
             str
         } else {
-            let str = VMHeap::new(s, self.alloc.clone());
-            self.strings.insert(str, Value::Nil);
+            let str = VMHeap::new(
+                ObjString::new_copied(s, self.alloc.clone()),
+                self.alloc.clone(),
+            );
+            self.strings.insert(str.into(), Value::Nil);
             self.register_obj(Object::String(str));
             str
         }
     }
 
+    /// Returns the interned string with these contents, if it exists.
+    pub(crate) fn get_interned(&self, s: &str) -> Option<VMHeap<ObjString>> {
+        let hash = ObjString::make_hash(NonNull::from(s.as_bytes()).cast(), s.len());
+        self.strings.get_string(s, hash)
+    }
+
     pub fn new_str_concat(&mut self, a: &ObjString, b: &ObjString) -> VMHeap<ObjString> {
         let s = ObjString::new_concat(a, b);
-        if let Some(str) = self.strings.get_string(NonNull::from(&s)) {
+        if let Some(str) = self.strings.get_string(s.as_str(), s.hash) {
             str
         } else {
             let str = VMHeap::new(s, self.alloc.clone());
-            self.strings.insert(str, Value::Nil);
+            self.strings.insert(str.into(), Value::Nil);
             self.register_obj(Object::String(str));
             str
         }
     }
 
+    pub fn new_native(
+        &mut self,
+        name: &str,
+        arity: Option<u8>,
+        function: NativeFn,
+    ) -> VMHeap<ObjNative> {
+        let name = self.intern(name);
+        let native = VMHeap::new(
+            ObjNative {
+                name,
+                arity,
+                function,
+                next: None,
+            },
+            self.alloc.clone(),
+        );
+        self.register_obj(Object::Native(native));
+        native
+    }
+
+    pub fn new_function(
+        &mut self,
+        name: Option<&str>,
+        arity: u8,
+        chunk: Chunk,
+    ) -> VMHeap<ObjFunction> {
+        let name = name.map(|name| self.intern(name));
+        let function = VMHeap::new(
+            ObjFunction {
+                name,
+                arity,
+                chunk,
+                next: None,
+            },
+            self.alloc.clone(),
+        );
+        self.register_obj(Object::Function(function));
+        function
+    }
+
+    pub fn new_map(&mut self) -> VMHeap<ObjMap> {
+        self.new_map_with_room_for(0)
+    }
+
+    /// A new map that holds `count` entries without growing, for map literals. Its entries iterate
+    /// in insertion order.
+    pub fn new_map_with_room_for(&mut self, count: usize) -> VMHeap<ObjMap> {
+        let map = VMHeap::new(
+            ObjMap {
+                table: HashTable::with_room_for(self.alloc.clone(), count).with_insertion_order(),
+                next: None,
+            },
+            self.alloc.clone(),
+        );
+        self.register_obj(Object::Map(map));
+        map
+    }
+
+    /// A new map with the same entries as `map`. The values themselves aren't copied.
+    pub fn copy_map(&mut self, map: &ObjMap) -> VMHeap<ObjMap> {
+        let mut copy = self.new_map();
+        for (key, value) in map.table.iter() {
+            copy.insert(key, value);
+        }
+        copy
+    }
+
     fn register_obj(&mut self, mut obj: Object) {
+        #[cfg(feature = "gc_log")]
+        log::debug!(
+            "{:p} allocate {} bytes for {}",
+            obj.as_ptr_u8(),
+            obj.layout().size(),
+            obj.kind()
+        );
         *obj.next_obj() = self.known_objects;
         self.known_objects = Some(obj);
     }
 
+    fn objects(&self) -> impl Iterator<Item = Object> {
+        std::iter::successors(self.known_objects, |obj| *obj.clone().next_obj())
+    }
+
+    /// Number of live heap objects.
+    pub fn object_count(&self) -> usize {
+        self.objects().count()
+    }
+
+    /// Number of live heap objects per kind.
+    pub fn object_histogram(&self) -> HashMap<&'static str, usize> {
+        let mut histogram = HashMap::new();
+        for obj in self.objects() {
+            *histogram.entry(obj.kind()).or_default() += 1;
+        }
+        histogram
+    }
+
     unsafe fn drop_object(&mut self, obj: Object) {
         let layout = obj.layout();
         let ptr = obj.as_ptr_u8();
+        #[cfg(feature = "gc_log")]
+        log::debug!("{ptr:p} free {} bytes for {obj:?}", layout.size());
         obj.drop_in_place();
         self.alloc.dealloc(ptr, layout);
     }
 
-    pub fn stack(&self) -> &ArrayVec<Value, STACK_SIZE> {
+    pub fn stack(&self) -> &VMHeapVec<Value> {
         &self.stack
     }
 
-    pub fn stack_mut(&mut self) -> &mut ArrayVec<Value, STACK_SIZE> {
+    pub fn stack_mut(&mut self) -> &mut VMHeapVec<Value> {
         &mut self.stack
     }
 }
@@ -115,7 +254,7 @@ impl<T: GCAble> VMHeap<T> {
 
 impl<T: ?Sized> Clone for VMHeap<T> {
     fn clone(&self) -> Self {
-        Self(self.0)
+        *self
     }
 }
 
@@ -170,36 +309,86 @@ pub unsafe trait GCAble: private::GCAblePrivate {
 
 #[doc(hidden)]
 mod private {
-    use crate::memory::{ObjString, Object};
+    use crate::memory::{ObjFunction, ObjMap, ObjNative, ObjString, Object};
 
     pub trait GCAblePrivate {}
     impl GCAblePrivate for Object {}
     impl GCAblePrivate for ObjString {}
+    impl GCAblePrivate for ObjNative {}
+    impl GCAblePrivate for ObjFunction {}
+    impl GCAblePrivate for ObjMap {}
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Copy, Clone)]
 pub enum Object {
     String(VMHeap<ObjString>),
+    Native(VMHeap<ObjNative>),
+    Function(VMHeap<ObjFunction>),
+    Map(VMHeap<ObjMap>),
 }
 
 impl Object {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Object::String(_) => "String",
+            Object::Native(_) => "Native",
+            Object::Function(_) => "Function",
+            Object::Map(_) => "Map",
+        }
+    }
+
     unsafe fn drop_in_place(self) {
         match self {
             Object::String(s) => s.0.as_ptr().drop_in_place(),
+            Object::Native(n) => n.0.as_ptr().drop_in_place(),
+            Object::Function(f) => f.0.as_ptr().drop_in_place(),
+            Object::Map(m) => m.0.as_ptr().drop_in_place(),
         }
     }
 
     fn as_ptr_u8(self) -> NonNull<u8> {
         match self {
             Object::String(s) => s.as_ptr_u8(),
+            Object::Native(n) => n.as_ptr_u8(),
+            Object::Function(f) => f.as_ptr_u8(),
+            Object::Map(m) => m.as_ptr_u8(),
         }
     }
 }
 
+/// Objects compare by identity. Strings are always interned, so for them identity is the same as
+/// comparing their contents.
 impl PartialEq for Object {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Object::String(a), Object::String(b)) => a == b,
+        self.as_ptr_u8() == other.as_ptr_u8()
+    }
+}
+
+impl Eq for Object {}
+
+impl From<VMHeap<ObjString>> for Value {
+    fn from(value: VMHeap<ObjString>) -> Self {
+        Value::Obj(Object::String(value))
+    }
+}
+
+/// Strings hash by the hash computed when they were created, everything else by identity.
+impl Hash for Object {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Object::String(s) => state.write_u32(s.hash),
+            _ => self.as_ptr_u8().hash(state),
+        }
+    }
+}
+
+impl Debug for Object {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Object::String(s) => write!(f, "String({:?})", s.as_str()),
+            Object::Native(n) => write!(f, "Native({})", **n),
+            Object::Function(function) => write!(f, "Function({})", **function),
+            Object::Map(m) => write!(f, "Map({} entries)", m.len()),
         }
     }
 }
@@ -208,6 +397,9 @@ impl Display for Object {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Object::String(s) => Display::fmt(s, f),
+            Object::Native(n) => Display::fmt(n, f),
+            Object::Function(function) => Display::fmt(function, f),
+            Object::Map(m) => Display::fmt(m, f),
         }
     }
 }
@@ -216,12 +408,18 @@ unsafe impl GCAble for Object {
     fn next_obj(&mut self) -> &mut Option<Object> {
         match self {
             Object::String(s) => s.next_obj(),
+            Object::Native(n) => n.next_obj(),
+            Object::Function(f) => f.next_obj(),
+            Object::Map(m) => m.next_obj(),
         }
     }
 
     fn layout(&self) -> Layout {
         match self {
             Object::String(s) => s.layout(),
+            Object::Native(n) => n.layout(),
+            Object::Function(f) => f.layout(),
+            Object::Map(m) => m.layout(),
         }
     }
 }
@@ -232,6 +430,9 @@ pub struct ObjString {
     hash: u32,
     ptr: NonNull<u8>,
     alloc: Arc<Allocator>,
+    /// Byte offset of every grapheme followed by the length, built the first time the string is
+    /// measured or indexed so walking it by index doesn't segment it again for every grapheme.
+    grapheme_starts: OnceCell<VMHeapVec<usize>>,
     next: Option<Object>,
 }
 
@@ -255,6 +456,7 @@ impl ObjString {
             hash,
             ptr: str_ptr,
             alloc,
+            grapheme_starts: OnceCell::new(),
             next: None,
         }
     }
@@ -280,18 +482,47 @@ impl ObjString {
                 str_ptr
             }
         };
+        // FNV-1a folds in one byte at a time, so the hash of `self` can be continued over `other`.
+        #[cfg(not(feature = "fx_hash"))]
+        let hash = Self::continue_hash(self.hash, other.ptr, other.len);
+        #[cfg(feature = "fx_hash")]
         let hash = Self::make_hash(str_ptr, len);
         Self {
             len,
             hash,
             ptr: str_ptr,
             alloc,
+            grapheme_starts: OnceCell::new(),
             next: None,
         }
     }
 
+    /// Hashes are only kept in memory, bytecode files re-intern their strings when loaded, so
+    /// switching the hash function doesn't invalidate them.
+    #[cfg(not(feature = "fx_hash"))]
     fn make_hash(chars: NonNull<u8>, len: usize) -> u32 {
-        let mut hash = 2166136261;
+        Self::continue_hash(2166136261, chars, len)
+    }
+
+    /// FxHash, which mixes in eight bytes at a time.
+    #[cfg(feature = "fx_hash")]
+    fn make_hash(chars: NonNull<u8>, len: usize) -> u32 {
+        const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+        let bytes = unsafe { slice::from_raw_parts(chars.as_ptr() as *const u8, len) };
+        let mut hash: u64 = 0;
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let word = u64::from_le_bytes(word.try_into().unwrap());
+            hash = (hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+        }
+        for byte in words.remainder() {
+            hash = (hash.rotate_left(5) ^ *byte as u64).wrapping_mul(SEED);
+        }
+        ((hash >> 32) ^ hash) as u32
+    }
+
+    #[cfg(not(feature = "fx_hash"))]
+    fn continue_hash(mut hash: u32, chars: NonNull<u8>, len: usize) -> u32 {
         for i in 0..len {
             hash ^= unsafe { *chars.as_ptr().add(i) } as u32;
             hash = hash.wrapping_mul(16777619);
@@ -299,16 +530,38 @@ impl ObjString {
         hash
     }
 
-    fn hash(s: NonNull<ObjString>) -> u32 {
-        unsafe { (*s.as_ptr()).hash }
-    }
-
-    fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &str {
         unsafe {
             let slice = slice::from_raw_parts(self.ptr.as_ptr() as *const _, self.len);
             std::str::from_utf8_unchecked(slice)
         }
     }
+
+    fn grapheme_starts(&self) -> &[usize] {
+        self.grapheme_starts.get_or_init(|| {
+            let mut starts = VMHeapVec::new(self.alloc.clone());
+            for (start, _) in self.as_str().grapheme_indices(true) {
+                starts.push(start);
+            }
+            starts.push(self.len);
+            starts
+        })
+    }
+
+    /// The length in graphemes, which is how Lox measures strings.
+    pub fn grapheme_count(&self) -> usize {
+        self.grapheme_starts().len() - 1
+    }
+
+    /// The graphemes from `start` up to but not including `end`, or `None` if they aren't in the
+    /// string.
+    pub fn graphemes(&self, start: usize, end: usize) -> Option<&str> {
+        let starts = self.grapheme_starts();
+        if start > end {
+            return None;
+        }
+        Some(&self.as_str()[*starts.get(start)?..*starts.get(end)?])
+    }
 }
 
 impl PartialEq for ObjString {
@@ -342,6 +595,137 @@ impl Drop for ObjString {
     }
 }
 
+/// A function implemented in Rust.
+#[derive(Debug)]
+pub struct ObjNative {
+    name: VMHeap<ObjString>,
+    arity: Option<u8>,
+    function: NativeFn,
+    next: Option<Object>,
+}
+
+impl ObjNative {
+    /// `None` if the native takes any number of arguments.
+    pub fn arity(&self) -> Option<u8> {
+        self.arity
+    }
+
+    pub fn function(&self) -> NativeFn {
+        self.function
+    }
+}
+
+unsafe impl GCAble for ObjNative {
+    fn next_obj(&mut self) -> &mut Option<Object> {
+        &mut self.next
+    }
+}
+
+impl Display for ObjNative {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", *self.name)
+    }
+}
+
+/// A function compiled from Lox source, with its own chunk.
+#[derive(Debug)]
+pub struct ObjFunction {
+    /// `None` for anonymous functions.
+    name: Option<VMHeap<ObjString>>,
+    arity: u8,
+    chunk: Chunk,
+    next: Option<Object>,
+}
+
+impl ObjFunction {
+    pub fn name(&self) -> Option<VMHeap<ObjString>> {
+        self.name
+    }
+
+    pub fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+}
+
+unsafe impl GCAble for ObjFunction {
+    fn next_obj(&mut self) -> &mut Option<Object> {
+        &mut self.next
+    }
+}
+
+impl Display for ObjFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "<fn {}>", *name),
+            None => write!(f, "<fn>"),
+        }
+    }
+}
+
+/// A map from any value but `NaN` to a value.
+#[derive(Debug)]
+pub struct ObjMap {
+    table: HashTable,
+    next: Option<Object>,
+}
+
+impl ObjMap {
+    /// `nil` for keys that aren't in the map.
+    pub fn get(&self, key: Value) -> Value {
+        self.table.get(key).copied().unwrap_or(Value::Nil)
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) {
+        self.table.insert(key, value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+unsafe impl GCAble for ObjMap {
+    fn next_obj(&mut self) -> &mut Option<Object> {
+        &mut self.next
+    }
+}
+
+thread_local! {
+    /// Maps whose `Display` is in progress on this thread, innermost last.
+    static MAPS_BEING_DISPLAYED: RefCell<Vec<*const ObjMap>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A map that contains itself, directly or not, is shown as `{...}` where it recurs.
+impl Display for ObjMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let this = self as *const ObjMap;
+        if MAPS_BEING_DISPLAYED.with_borrow(|maps| maps.contains(&this)) {
+            return write!(f, "{{...}}");
+        }
+        MAPS_BEING_DISPLAYED.with_borrow_mut(|maps| maps.push(this));
+        let result = (|| {
+            write!(f, "{{")?;
+            for (i, (key, value)) in self.table.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{key}: {value}")?;
+            }
+            write!(f, "}}")
+        })();
+        MAPS_BEING_DISPLAYED.with_borrow_mut(|maps| maps.pop());
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -351,12 +735,171 @@ mod tests {
         let alloc = Allocator::new();
         let strings = HashTable::new(alloc.clone());
         let mut memory_manager = MemoryManager::new(alloc, strings);
-        let a = memory_manager.new_str_copied("hi!");
-        let b = memory_manager.new_str_copied("hi!");
-        let c = memory_manager.new_str_copied("hi!hi!");
+        let a = memory_manager.intern("hi!");
+        let b = memory_manager.intern("hi!");
+        let c = memory_manager.intern("hi!hi!");
         assert_eq!(a, b);
         assert_ne!(a, c);
         let d = memory_manager.new_str_concat(&a, &b);
         assert_eq!(c, d);
+        assert_eq!(c.0, d.0);
+    }
+
+    #[test]
+    fn object_count() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        assert_eq!(memory_manager.object_count(), 0);
+        let a = memory_manager.intern("a");
+        let b = memory_manager.intern("b");
+        memory_manager.intern("a");
+        memory_manager.new_str_concat(&a, &b);
+        assert_eq!(memory_manager.object_count(), 3);
+        assert_eq!(
+            memory_manager.object_histogram(),
+            HashMap::from([("String", 3)])
+        );
+    }
+
+    #[test]
+    fn intern_and_lookup() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        assert!(memory_manager.get_interned("native").is_none());
+        let a = memory_manager.intern("native");
+        let b = memory_manager.intern("native");
+        assert_eq!(a.0, b.0);
+        assert_eq!(
+            memory_manager.get_interned("native").map(|s| s.0),
+            Some(a.0)
+        );
+        assert!(memory_manager.get_interned("nativ").is_none());
+        assert_eq!(memory_manager.intern("").as_str(), "");
+        assert!(memory_manager.get_interned("").is_some());
+    }
+
+    #[test]
+    fn object_identity() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let a = Object::String(memory_manager.intern("same"));
+        let b = Object::String(memory_manager.intern("same"));
+        assert_eq!(a, b);
+        assert_eq!(a, a);
+
+        // Bypasses interning, so it is a distinct object with the same contents
+        let not_interned = VMHeap::new(ObjString::new_copied("same", alloc.clone()), alloc);
+        let c = Object::String(not_interned);
+        memory_manager.register_obj(c);
+        assert_ne!(a, c);
+        assert_eq!(c, c);
+    }
+
+    #[test]
+    fn global_slots_are_stable() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let a = memory_manager.global_slot("a").unwrap();
+        let b = memory_manager.global_slot("b").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(memory_manager.global_slot("a"), Some(a));
+        assert_eq!(memory_manager.global_name(b).unwrap().as_str(), "b");
+    }
+
+    #[test]
+    fn interned_strings_are_always_found() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let count = if cfg!(miri) { 20 } else { 5000 };
+        // Lengths cross the 8-byte words FxHash reads at a time
+        let corpus: Vec<String> = (0..count)
+            .map(|i| format!("{i}{}", "x".repeat(i % 19)))
+            .collect();
+        let interned: Vec<_> = corpus.iter().map(|s| memory_manager.intern(s)).collect();
+        for (s, obj) in corpus.iter().zip(&interned) {
+            assert_eq!(memory_manager.get_interned(s), Some(*obj), "{s:?}");
+            assert_eq!(memory_manager.intern(s), *obj, "{s:?}");
+        }
+        for (s, obj) in corpus.iter().zip(&interned).take(count / 10) {
+            let (head, tail) = s.split_at(s.len() / 2);
+            let head = memory_manager.intern(head);
+            let tail = memory_manager.intern(tail);
+            assert_eq!(memory_manager.new_str_concat(&head, &tail), *obj, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn concat_hash_matches_full_hash() {
+        let alloc = Allocator::new();
+        let count = if cfg!(miri) { 10 } else { 1000 };
+        // Simple LCG so the test is deterministic without pulling in a rand crate
+        let mut seed: u32 = 12345;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            seed >> 16
+        };
+        let random_string = |next: &mut dyn FnMut() -> u32| {
+            let len = next() % 16;
+            (0..len)
+                .map(|_| char::from_u32(0x20 + next() % 0x5f).unwrap())
+                .collect::<String>()
+        };
+        for _ in 0..count {
+            let a = random_string(&mut next);
+            let b = random_string(&mut next);
+            let a_obj = ObjString::new_copied(&a, alloc.clone());
+            let b_obj = ObjString::new_copied(&b, alloc.clone());
+            let concat = a_obj.new_concat(&b_obj);
+            let full = ObjString::new_copied(&format!("{a}{b}"), alloc.clone());
+            assert_eq!(concat.hash, full.hash, "{a:?} + {b:?}");
+            assert_eq!(concat.as_str(), full.as_str());
+        }
+    }
+
+    /// Keeps every log line, since the logger is global for the whole test binary.
+    #[cfg(feature = "gc_log")]
+    struct CapturingLogger(std::sync::Mutex<Vec<String>>);
+
+    #[cfg(feature = "gc_log")]
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(feature = "gc_log")]
+    #[test]
+    fn gc_log_logs_allocation_and_freeing() {
+        static LOGGER: CapturingLogger = CapturingLogger(std::sync::Mutex::new(Vec::new()));
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let string = Object::String(memory_manager.intern("logged"));
+        let (ptr, size) = (string.as_ptr_u8(), string.layout().size());
+        // Objects are only freed when their memory manager is dropped
+        drop(memory_manager);
+
+        let lines = LOGGER.0.lock().unwrap();
+        let allocate = format!("{ptr:p} allocate {size} bytes for String");
+        assert!(lines.contains(&allocate), "{lines:?}");
+        let free = format!("{ptr:p} free {size} bytes for ");
+        assert!(
+            lines.iter().any(|line| line.starts_with(&free)),
+            "{lines:?}"
+        );
     }
 }
@@ -1,5 +1,6 @@
 use crate::memory::allocator::Allocator;
 use std::alloc::Layout;
+use std::fmt::{Debug, Formatter};
 use std::ops::{Deref, DerefMut};
 use std::ptr::NonNull;
 use std::sync::Arc;
@@ -23,6 +24,10 @@ impl<T> VMHeapVec<T> {
         }
     }
 
+    pub fn alloc(&self) -> &Arc<Allocator> {
+        &self.alloc
+    }
+
     pub fn push(&mut self, elem: T) {
         if self.len == self.cap {
             self.grow()
@@ -43,6 +48,28 @@ impl<T> VMHeapVec<T> {
         }
     }
 
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+
+    /// Removes the element at `index` in constant time by moving the last element into its
+    /// place. Panics if `index` is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "swap_remove index {index} out of bounds for length {}",
+            self.len
+        );
+        let last = self.pop().unwrap();
+        if index == self.len {
+            last
+        } else {
+            mem::replace(&mut self[index], last)
+        }
+    }
+
     fn grow(&mut self) {
         let (new_cap, new_layout) = if self.cap == 0 {
             let initial_capacity = 1;
@@ -91,3 +118,74 @@ impl<T> DerefMut for VMHeapVec<T> {
         unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
     }
 }
+
+impl<T: Debug> Debug for VMHeapVec<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(n: usize) -> (Arc<Allocator>, VMHeapVec<usize>) {
+        let alloc = Allocator::new();
+        let mut vec = VMHeapVec::new(alloc.clone());
+        for i in 0..n {
+            vec.push(i);
+        }
+        (alloc, vec)
+    }
+
+    #[test]
+    fn truncate_keeps_capacity() {
+        let (alloc, mut vec) = filled(5);
+        let allocated = alloc.bytes_allocated();
+        vec.truncate(7);
+        assert_eq!(&*vec, &[0, 1, 2, 3, 4]);
+        vec.truncate(2);
+        assert_eq!(&*vec, &[0, 1]);
+        assert_eq!(alloc.bytes_allocated(), allocated);
+        vec.truncate(0);
+        assert!(vec.is_empty());
+        assert_eq!(alloc.bytes_allocated(), allocated);
+    }
+
+    #[test]
+    fn truncate_drops_elements() {
+        let alloc = Allocator::new();
+        let shared = Arc::new(());
+        let mut vec = VMHeapVec::new(alloc);
+        for _ in 0..3 {
+            vec.push(shared.clone());
+        }
+        vec.truncate(1);
+        assert_eq!(Arc::strong_count(&shared), 2);
+    }
+
+    #[test]
+    fn swap_remove() {
+        let (_alloc, mut vec) = filled(4);
+        assert_eq!(vec.swap_remove(1), 1);
+        assert_eq!(&*vec, &[0, 3, 2]);
+        assert_eq!(vec.swap_remove(2), 2);
+        assert_eq!(&*vec, &[0, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn swap_remove_out_of_bounds() {
+        let (_alloc, mut vec) = filled(2);
+        vec.swap_remove(2);
+    }
+
+    #[test]
+    fn iter_mut() {
+        let (_alloc, mut vec) = filled(3);
+        for elem in vec.iter_mut() {
+            *elem *= 10;
+        }
+        assert_eq!(&*vec, &[0, 10, 20]);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::memory::{MemoryManager, Object};
+use crate::value::Value;
+use crate::vm::{Output, RuntimeError};
+use std::io::BufRead;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What a native function can reach of the VM that called it.
+pub struct NativeContext<'n> {
+    pub output: &'n mut dyn Output,
+    /// `None` to read from stdin.
+    pub input: Option<&'n mut dyn BufRead>,
+    pub memory_manager: &'n mut MemoryManager,
+}
+
+/// Errors returned by a native get the line of the call attached by the VM, type errors have
+/// their line replaced by it.
+pub type NativeFn = fn(&mut NativeContext, &[Value]) -> Result<Value, RuntimeError>;
+
+/// Name of the native that expects its argument to fail when called. Calling a Lox function
+/// takes the VM, so the VM runs it instead of its [`NativeFn`].
+pub const EXPECT_ERROR: &str = "expect_error";
+
+/// Natives defined as globals in every VM, with their arity or `None` if they take any number of
+/// arguments.
+pub const NATIVES: &[(&str, Option<u8>, NativeFn)] = &[
+    ("assert", Some(2), assert),
+    ("expect", Some(2), expect),
+    (EXPECT_ERROR, Some(1), expect_error),
+    ("clock", Some(0), clock),
+    ("str", Some(1), str),
+    ("num", Some(1), num),
+    ("print", Some(1), println),
+    ("write", Some(1), write),
+    ("println", Some(1), println),
+    ("len", Some(1), len),
+    ("substring", Some(3), substring),
+    ("format", Some(2), format),
+    ("copy", Some(1), copy),
+    ("min", None, min),
+    ("max", None, max),
+    ("floor", Some(1), floor),
+    ("ceil", Some(1), ceil),
+    ("sqrt", Some(1), sqrt),
+    ("abs", Some(1), abs),
+    ("read_line", Some(0), read_line),
+    ("read_file", Some(1), read_file),
+];
+
+fn assert(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    if args[0].is_falsey() {
+        Err(RuntimeError::AssertionFailed(args[1].to_string()))
+    } else {
+        Ok(Value::Nil)
+    }
+}
+
+/// Fails unless `actual` equals `expected`, quoting strings so `"1"` and `1` can be told apart.
+fn expect(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let describe = |value: Value| match value {
+        Value::Obj(Object::String(s)) => format!("\"{}\"", *s),
+        other => other.to_string(),
+    };
+    let (actual, expected) = (args[0], args[1]);
+    if actual == expected {
+        Ok(Value::Nil)
+    } else {
+        Err(RuntimeError::AssertionFailed(format!(
+            "expected {}, got {}.",
+            describe(expected),
+            describe(actual)
+        )))
+    }
+}
+
+/// Never called, see [`EXPECT_ERROR`].
+fn expect_error(_ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, RuntimeError> {
+    unreachable!("the VM calls {EXPECT_ERROR} itself")
+}
+
+fn clock(_ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| RuntimeError::InvalidArgument(e.to_string()))?;
+    Ok(Value::Number(now.as_secs_f64()))
+}
+
+fn str(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = ctx.memory_manager.intern(&args[0].to_string());
+    Ok(Value::Obj(Object::String(s)))
+}
+
+fn num(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    match args[0] {
+        Value::Number(n) => Ok(Value::Number(n)),
+        Value::Obj(Object::String(s)) => {
+            s.to_string()
+                .trim()
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| {
+                    RuntimeError::InvalidArgument(format!("Can't convert '{}' to a number.", *s))
+                })
+        }
+        other => Err(RuntimeError::InvalidArgument(format!(
+            "Can't convert {other} to a number."
+        ))),
+    }
+}
+
+/// Like `print`, but without the trailing newline.
+fn write(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    ctx.output.write(&args[0]);
+    Ok(Value::Nil)
+}
+
+fn println(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    ctx.output.print(&args[0]);
+    Ok(Value::Nil)
+}
+
+/// Length of a string in graphemes, so an emoji counts as one.
+fn len(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    match args[0] {
+        Value::Obj(Object::String(s)) => Ok(Value::Number(s.grapheme_count() as f64)),
+        other => Err(RuntimeError::InvalidArgument(format!(
+            "Can't take the length of {other}."
+        ))),
+    }
+}
+
+/// The graphemes of `s` from `start` up to but not including `end`.
+fn substring(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let (s, start, end) = match (args[0], args[1], args[2]) {
+        (Value::Obj(Object::String(s)), Value::Number(start), Value::Number(end)) => {
+            (s, start, end)
+        }
+        _ => {
+            return Err(RuntimeError::InvalidArgument(
+                "substring expects a string and two numbers.".to_string(),
+            ))
+        }
+    };
+    let len = s.grapheme_count();
+    let in_bounds = |i: f64| i.fract() == 0.0 && i >= 0.0 && i <= len as f64;
+    let sub = (in_bounds(start) && in_bounds(end))
+        .then(|| s.graphemes(start as usize, end as usize))
+        .flatten()
+        .ok_or(RuntimeError::IndexOutOfBounds { start, end, len })?;
+    Ok(Value::Obj(Object::String(ctx.memory_manager.intern(sub))))
+}
+
+/// Keeps `format(n, ".N")` from building huge strings.
+const MAX_FORMAT_DECIMALS: usize = 100;
+
+/// Formats a value according to a small spec: `""` formats it like `str`, `".N"` formats a number
+/// with `N` decimals, at most [`MAX_FORMAT_DECIMALS`], and `"x"` formats a whole number in
+/// hexadecimal.
+fn format(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let spec = match args[1] {
+        Value::Obj(Object::String(s)) => s.to_string(),
+        other => {
+            return Err(RuntimeError::InvalidArgument(format!(
+                "Format spec must be a string, got {other}."
+            )))
+        }
+    };
+    let invalid =
+        || RuntimeError::InvalidArgument(format!("Can't format {} with spec '{spec}'.", args[0]));
+    let formatted = match (spec.as_str(), args[0]) {
+        ("", value) => value.to_string(),
+        // `u64::MAX as f64` rounds up to 2^64, which doesn't fit
+        ("x", Value::Number(n)) if n.fract() == 0.0 && n.abs() < u64::MAX as f64 => {
+            let sign = if n < 0.0 { "-" } else { "" };
+            format!("{sign}{:x}", n.abs() as u64)
+        }
+        (spec, Value::Number(n)) if spec.starts_with('.') => {
+            let decimals: usize = spec[1..].parse().map_err(|_| invalid())?;
+            if decimals > MAX_FORMAT_DECIMALS {
+                return Err(RuntimeError::InvalidArgument(format!(
+                    "Can't format with more than {MAX_FORMAT_DECIMALS} decimals, got '{spec}'."
+                )));
+            }
+            format!("{n:.decimals$}")
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(Value::Obj(Object::String(
+        ctx.memory_manager.intern(&formatted),
+    )))
+}
+
+/// A shallow copy of a map, so changes to the copy don't show up in the original.
+fn copy(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    match args[0] {
+        Value::Obj(Object::Map(map)) => {
+            Ok(Value::Obj(Object::Map(ctx.memory_manager.copy_map(&map))))
+        }
+        other => Err(RuntimeError::InvalidArgument(format!(
+            "Can't copy {other}."
+        ))),
+    }
+}
+
+/// The VM replaces the line of the type error with the line of the call.
+fn number_arg(value: Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Number(n) => Ok(n),
+        _ => Err(RuntimeError::InvalidType(0, "number")),
+    }
+}
+
+/// Folds any number of numbers with `f`, at least one is needed.
+fn fold_numbers(name: &str, args: &[Value], f: fn(f64, f64) -> f64) -> Result<Value, RuntimeError> {
+    let (&first, rest) = args.split_first().ok_or_else(|| {
+        RuntimeError::InvalidArgument(format!("{name} expects at least one number."))
+    })?;
+    rest.iter()
+        .try_fold(number_arg(first)?, |acc, &value| {
+            Ok(f(acc, number_arg(value)?))
+        })
+        .map(Value::Number)
+}
+
+fn min(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    fold_numbers("min", args, f64::min)
+}
+
+fn max(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    fold_numbers("max", args, f64::max)
+}
+
+fn floor(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(number_arg(args[0])?.floor()))
+}
+
+fn ceil(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(number_arg(args[0])?.ceil()))
+}
+
+fn sqrt(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(number_arg(args[0])?.sqrt()))
+}
+
+fn abs(_ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(number_arg(args[0])?.abs()))
+}
+
+/// The next line of input without its line ending, or `nil` at the end of the input.
+fn read_line(ctx: &mut NativeContext, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut line = String::new();
+    let read = match &mut ctx.input {
+        Some(input) => input.read_line(&mut line),
+        None => std::io::stdin().read_line(&mut line),
+    }
+    .map_err(|e| RuntimeError::Io(e.to_string()))?;
+    if read == 0 {
+        return Ok(Value::Nil);
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    Ok(Value::Obj(Object::String(ctx.memory_manager.intern(line))))
+}
+
+fn read_file(ctx: &mut NativeContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = match args[0] {
+        Value::Obj(Object::String(s)) => s.to_string(),
+        other => {
+            return Err(RuntimeError::InvalidArgument(format!(
+                "read_file expects a path string, got {other}."
+            )))
+        }
+    };
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| RuntimeError::Io(format!("Can't read '{path}': {e}")))?;
+    Ok(Value::Obj(Object::String(
+        ctx.memory_manager.intern(&contents),
+    )))
+}
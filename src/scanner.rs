@@ -1,24 +1,12 @@
 use std::fmt::{Display, Formatter};
-use std::iter::FusedIterator;
+use std::iter::{FusedIterator, Peekable};
 use thiserror::Error;
-use unicode_segmentation::UnicodeSegmentation;
+use unicode_segmentation::{Graphemes, UnicodeSegmentation};
 
 pub type ScanResult<A> = Result<A, ScanError>;
 
 static NEWLINE_GRAPHEMES: &[&str] = &["\r", "\n", "\r\n"];
 static DIGITS: &[&str] = &["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
-static LOWERCASE_LETTERS: &[&str] = &[
-    "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s",
-    "t", "u", "v", "w", "x", "y", "z",
-];
-
-static UPPERCASE_LETTERS: &[&str] = &[
-    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S",
-    "T", "U", "V", "W", "X", "Y", "Z",
-];
-
-static UNDERSCORE: &[&str] = &["_"];
-
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenContents<'a> {
     // One-character tokens
@@ -26,6 +14,9 @@ pub enum TokenContents<'a> {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -33,6 +24,7 @@ pub enum TokenContents<'a> {
     Semicolon,
     Slash,
     Asterisk,
+    Percent,
     // One- or two-character tokens
     Bang,
     BangEqual,
@@ -42,18 +34,32 @@ pub enum TokenContents<'a> {
     GreaterEqual,
     Less,
     LessEqual,
+    PlusEqual,
+    MinusEqual,
+    AsteriskEqual,
+    SlashEqual,
+    PercentEqual,
+    PlusPlus,
+    /// Only scanned right after a variable and when no operand follows, otherwise `--` is two
+    /// `Minus` tokens like in standard Lox, so `1--1` is `1 - -1`.
+    MinusMinus,
     // Literals
     Identifier(&'a str),
     String(&'a str),
     Number(&'a str),
+    /// Only produced by `Scanner::new_with_comments`, without the leading `//`.
+    LineComment(&'a str),
     // Keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     For,
     Fun,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -75,6 +81,9 @@ impl<'a> Display for TokenContents<'a> {
                 TokenContents::RightParen => ")",
                 TokenContents::LeftBrace => "{",
                 TokenContents::RightBrace => "}",
+                TokenContents::LeftBracket => "[",
+                TokenContents::RightBracket => "]",
+                TokenContents::Colon => ":",
                 TokenContents::Comma => ",",
                 TokenContents::Dot => ".",
                 TokenContents::Minus => "-",
@@ -82,8 +91,16 @@ impl<'a> Display for TokenContents<'a> {
                 TokenContents::Semicolon => ";",
                 TokenContents::Slash => "/",
                 TokenContents::Asterisk => "*",
+                TokenContents::Percent => "%",
                 TokenContents::Bang => "!",
                 TokenContents::BangEqual => "!=",
+                TokenContents::PlusEqual => "+=",
+                TokenContents::MinusEqual => "-=",
+                TokenContents::AsteriskEqual => "*=",
+                TokenContents::SlashEqual => "/=",
+                TokenContents::PercentEqual => "%=",
+                TokenContents::PlusPlus => "++",
+                TokenContents::MinusMinus => "--",
                 TokenContents::Equal => "=",
                 TokenContents::EqualEqual => "==",
                 TokenContents::Greater => ">",
@@ -93,13 +110,17 @@ impl<'a> Display for TokenContents<'a> {
                 TokenContents::Identifier(id) => *id,
                 TokenContents::String(s) => *s,
                 TokenContents::Number(num) => *num,
+                TokenContents::LineComment(comment) => comment,
                 TokenContents::And => "and",
+                TokenContents::Break => "break",
                 TokenContents::Class => "class",
+                TokenContents::Continue => "continue",
                 TokenContents::Else => "else",
                 TokenContents::False => "false",
                 TokenContents::For => "for",
                 TokenContents::Fun => "fun",
                 TokenContents::If => "if",
+                TokenContents::In => "in",
                 TokenContents::Nil => "nil",
                 TokenContents::Or => "or",
                 TokenContents::Print => "print",
@@ -128,90 +149,134 @@ impl<'a> Token<'a> {
 
 pub struct Scanner<'a> {
     source: &'a str,
+    keep_comments: bool,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
-        Self { source }
+        Self {
+            source,
+            keep_comments: false,
+        }
+    }
+
+    /// Like `new`, but comments are returned as tokens instead of being skipped.
+    pub fn new_with_comments(source: &'a str) -> Self {
+        Self {
+            source,
+            keep_comments: true,
+        }
     }
 
     pub fn iter(&self) -> SourceIterator<'a> {
-        SourceIterator::new(self.source)
+        SourceIterator::new(self.source, self.keep_comments)
     }
 }
 
+#[derive(Clone)]
 pub struct SourceIterator<'a> {
     source: &'a str,
-    graphemes: Vec<&'a str>,
+    /// The graphemes from `cur_byte` on, split as they're needed.
+    graphemes: Peekable<Graphemes<'a>>,
     line: usize,
-    cur_char: usize,
+    /// Byte offsets in `source` of the start of the current token and of the next grapheme.
+    start_byte: usize,
+    cur_byte: usize,
+    /// Byte offset of the start of the last token returned.
+    token_start: usize,
+    keep_comments: bool,
+    /// Whether the last token returned was an identifier, which `--` can decrement.
+    after_identifier: bool,
 }
 
 impl<'a> SourceIterator<'a> {
-    fn new(source: &'a str) -> Self {
+    fn new(source: &'a str, keep_comments: bool) -> Self {
+        // Editors on Windows like to start UTF-8 files with a byte order mark
+        let source = source.strip_prefix('\u{FEFF}').unwrap_or(source);
         Self {
             source,
-            graphemes: source.graphemes(true).collect(),
+            graphemes: source.graphemes(true).peekable(),
             line: 1,
-            cur_char: 0,
+            start_byte: 0,
+            cur_byte: 0,
+            token_start: 0,
+            keep_comments,
+            after_identifier: false,
         }
     }
 
+    /// 1-based column, in graphemes, where the last token returned starts.
+    pub fn last_token_column(&self) -> usize {
+        let before = &self.source[..self.token_start];
+        let line_start = before.rfind(['\n', '\r']).map_or(0, |idx| idx + 1);
+        before[line_start..].graphemes(true).count() + 1
+    }
+
     // TODO why these lifetimes?
     fn get_and_advance<'b>(&'b mut self) -> Option<&'a str> {
-        let res = *self.graphemes.get(self.cur_char)?;
-        self.cur_char += 1;
+        let res = self.graphemes.next()?;
+        self.cur_byte += res.len();
         Some(res)
     }
 
     fn peek<'b>(&'b mut self) -> Option<&'a str> {
-        self.graphemes.get(self.cur_char).copied()
+        self.graphemes.peek().copied()
     }
 
     fn peek_peek<'b>(&'b mut self) -> Option<&'a str> {
-        if self.cur_char + 1 > self.graphemes.len() {
-            None
-        } else {
-            self.graphemes.get(self.cur_char + 1).copied()
-        }
+        // Cloning only copies the position, no graphemes are collected
+        self.graphemes.clone().nth(1)
     }
 
     fn advance_if_matches<'b>(&'b mut self, c: &'a str) -> bool {
-        let res = self.graphemes.get(self.cur_char);
-        if let Some(&res) = res {
-            if res == c {
-                self.cur_char += 1;
-                true
-            } else {
-                false
-            }
+        if self.graphemes.next_if_eq(&c).is_some() {
+            self.cur_byte += c.len();
+            true
         } else {
             false
         }
     }
 
+    /// Whether the `--` starting at the current `-` decrements the variable before it, rather than
+    /// subtracting a negated operand that follows.
+    fn is_postfix_decrement(&self) -> bool {
+        if !self.after_identifier {
+            return false;
+        }
+        let mut ahead = self.clone();
+        let _ = ahead.get_and_advance();
+        ahead.reset();
+        ahead.after_identifier = false;
+        !matches!(
+            ahead.next(),
+            Some(Ok(Token {
+                contents: TokenContents::Number(_)
+                    | TokenContents::String(_)
+                    | TokenContents::Identifier(_)
+                    | TokenContents::LeftParen
+                    | TokenContents::LeftBrace
+                    | TokenContents::True
+                    | TokenContents::False
+                    | TokenContents::Nil
+                    | TokenContents::Fun
+                    | TokenContents::Bang,
+                ..
+            }))
+        )
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.peek() {
             match c {
                 " " | "\t" => {
                     let _ = self.get_and_advance();
                 }
-                "\n" | "\r" | "\r\n" => {
+                c if is_newline(c) => {
                     let _ = self.get_and_advance();
                     self.line += 1;
                 }
-                "/" => {
-                    if let Some("/") = self.peek_peek() {
-                        while let Some(c) = self.peek() {
-                            if !NEWLINE_GRAPHEMES.contains(&c) {
-                                let _ = self.get_and_advance();
-                            } else {
-                                break;
-                            }
-                        }
-                    } else {
-                        break;
-                    }
+                "/" if !self.keep_comments && self.peek_peek() == Some("/") => {
+                    self.line_comment();
                 }
                 _ => {
                     break;
@@ -221,32 +286,37 @@ impl<'a> SourceIterator<'a> {
         self.reset()
     }
 
+    /// Consumes everything up to, but not including, the end of the line.
+    fn line_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if is_newline(c) {
+                break;
+            }
+            let _ = self.get_and_advance();
+        }
+    }
+
+    /// Starts the next token at the current grapheme.
     fn reset(&mut self) {
-        let advance_len = self
-            .graphemes
-            .iter()
-            .take(self.cur_char)
-            .map(|c| c.len())
-            .sum();
-        self.graphemes.drain(0..self.cur_char);
-        self.source = &self.source[advance_len..];
-        self.cur_char = 0;
+        self.start_byte = self.cur_byte;
     }
 
     fn get_cur_str<'b>(&'b self) -> Option<&'a str> {
-        let advance_len = self
-            .graphemes
-            .iter()
-            .take(self.cur_char)
-            .map(|c| c.len())
-            .sum();
-        self.source.get(0..advance_len)
+        self.source.get(self.start_byte..self.cur_byte)
     }
 
-    fn string<'b>(&'b mut self) -> ScanResult<Token<'a>> {
+    /// `opening_len` is the length of the opening delimiter, `"` or `r"`. An unterminated string is
+    /// reported with its first line, and scanning resumes on the line after it so later errors
+    /// still surface.
+    fn string<'b>(&'b mut self, opening_len: usize) -> ScanResult<Token<'a>> {
         let starting_line = self.line;
+        // Where the first line of the string ends, to go back to if it's never closed
+        let mut first_line_end = None;
         while let Some(c) = self.peek() {
-            if NEWLINE_GRAPHEMES.contains(&c) {
+            if is_newline(c) {
+                if first_line_end.is_none() {
+                    first_line_end = Some((self.graphemes.clone(), self.cur_byte, self.line));
+                }
                 self.line += 1;
             }
             if c == "\"" {
@@ -254,57 +324,58 @@ impl<'a> SourceIterator<'a> {
                 let contents = self
                     .get_cur_str()
                     .expect("Should not find empty string, including start/end quotes");
-                let contents = TokenContents::String(&contents[1..(contents.len() - 1)]);
+                let contents = TokenContents::String(&contents[opening_len..(contents.len() - 1)]);
                 return Ok(Token::new(contents, starting_line));
             } else {
                 let _ = self.get_and_advance();
             }
         }
 
-        return Err(ScanError::UnterminatedString(
-            self.get_cur_str()
-                .unwrap_or("")
-                .to_string()
-                .graphemes(true)
-                .take_while(|c| !NEWLINE_GRAPHEMES.contains(c))
-                .collect(),
+        if let Some((graphemes, cur_byte, line)) = first_line_end {
+            self.graphemes = graphemes;
+            self.cur_byte = cur_byte;
+            self.line = line;
+        }
+        Err(ScanError::UnterminatedString(
+            self.get_cur_str().unwrap_or("").to_string(),
             starting_line,
-        ));
+            self.start_byte,
+        ))
+    }
+
+    /// Digits may be separated by single underscores, like `1_000.5`.
+    fn digit<'b>(&'b mut self) -> ScanResult<Token<'a>> {
+        self.digits();
+        if self.peek() == Some(".") && self.peek_peek().is_some_and(is_digit) {
+            // Consume .
+            let _ = self.get_and_advance();
+            self.digits();
+        }
+
+        let num = self.get_cur_str().expect("Should not find empty number");
+        if num.contains("__") || num.contains("_.") || num.ends_with('_') {
+            return Err(ScanError::InvalidNumber(
+                num.to_string(),
+                self.line,
+                self.start_byte,
+            ));
+        }
+        Ok(Token::new(TokenContents::Number(num), self.line))
     }
 
-    fn digit<'b>(&'b mut self) -> Token<'a> {
+    fn digits(&mut self) {
         while let Some(c) = self.peek() {
-            if is_digit(c) {
+            if is_digit(c) || c == "_" {
                 let _ = self.get_and_advance();
             } else {
                 break;
             }
         }
-        if let Some(c) = self.peek() {
-            if c == "." {
-                if let Some(c) = self.peek_peek() {
-                    if is_digit(c) {
-                        // Consume .
-                        let _ = self.get_and_advance();
-                        while let Some(c) = self.peek() {
-                            if is_digit(c) {
-                                let _ = self.get_and_advance();
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        let num = self.get_cur_str().expect("Should not find empty number");
-        Token::new(TokenContents::Number(num), self.line)
     }
 
     fn identifier<'b>(&'b mut self) -> Token<'a> {
         while let Some(c) = self.peek() {
-            if is_letter_or_underscore(c) || is_digit(c) {
+            if is_identifier_continue(c) {
                 let _ = self.get_and_advance();
             } else {
                 break;
@@ -319,12 +390,15 @@ impl<'a> SourceIterator<'a> {
         Token::new(
             match identifier {
                 "and" => And,
+                "break" => Break,
                 "class" => Class,
+                "continue" => Continue,
                 "else" => Else,
                 "false" => False,
                 "for" => For,
                 "fun" => Fun,
                 "if" => If,
+                "in" => In,
                 "nil" => Nil,
                 "or" => Or,
                 "print" => Print,
@@ -347,13 +421,62 @@ impl<'a> SourceIterator<'a> {
             ")" => Some(Ok(Token::new(RightParen, self.line))),
             "{" => Some(Ok(Token::new(LeftBrace, self.line))),
             "}" => Some(Ok(Token::new(RightBrace, self.line))),
+            "[" => Some(Ok(Token::new(LeftBracket, self.line))),
+            "]" => Some(Ok(Token::new(RightBracket, self.line))),
+            ":" => Some(Ok(Token::new(Colon, self.line))),
             ";" => Some(Ok(Token::new(Semicolon, self.line))),
             "," => Some(Ok(Token::new(Comma, self.line))),
             "." => Some(Ok(Token::new(Dot, self.line))),
-            "-" => Some(Ok(Token::new(Minus, self.line))),
-            "+" => Some(Ok(Token::new(Plus, self.line))),
-            "/" => Some(Ok(Token::new(Slash, self.line))),
-            "*" => Some(Ok(Token::new(Asterisk, self.line))),
+            "-" => Some(Ok(Token::new(
+                if self.advance_if_matches("=") {
+                    MinusEqual
+                } else if self.peek() == Some("-") && self.is_postfix_decrement() {
+                    let _ = self.get_and_advance();
+                    MinusMinus
+                } else {
+                    Minus
+                },
+                self.line,
+            ))),
+            "+" => Some(Ok(Token::new(
+                if self.advance_if_matches("=") {
+                    PlusEqual
+                } else if self.advance_if_matches("+") {
+                    PlusPlus
+                } else {
+                    Plus
+                },
+                self.line,
+            ))),
+            "/" if self.advance_if_matches("/") => {
+                self.line_comment();
+                let comment = self.get_cur_str().expect("Comment should be in the source");
+                Some(Ok(Token::new(LineComment(&comment[2..]), self.line)))
+            }
+            "/" => Some(Ok(Token::new(
+                if self.advance_if_matches("=") {
+                    SlashEqual
+                } else {
+                    Slash
+                },
+                self.line,
+            ))),
+            "*" => Some(Ok(Token::new(
+                if self.advance_if_matches("=") {
+                    AsteriskEqual
+                } else {
+                    Asterisk
+                },
+                self.line,
+            ))),
+            "%" => Some(Ok(Token::new(
+                if self.advance_if_matches("=") {
+                    PercentEqual
+                } else {
+                    Percent
+                },
+                self.line,
+            ))),
             "!" => {
                 if self.advance_if_matches("=") {
                     Some(Ok(Token::new(BangEqual, self.line)))
@@ -382,10 +505,15 @@ impl<'a> SourceIterator<'a> {
                     Some(Ok(Token::new(Greater, self.line)))
                 }
             }
-            "\"" => Some(self.string()),
+            "\"" => Some(self.string(1)),
+            // There are no escape sequences yet, so a raw string scans just like a normal one
+            "r" if self.peek() == Some("\"") => {
+                let _ = self.get_and_advance();
+                Some(self.string(2))
+            }
             _ => {
                 if is_digit(c) {
-                    Some(Ok(self.digit()))
+                    Some(self.digit())
                 } else if is_letter_or_underscore(c) {
                     Some(Ok(self.identifier()))
                 } else {
@@ -396,12 +524,37 @@ impl<'a> SourceIterator<'a> {
     }
 }
 
+/// The value of a number literal scanned as [`TokenContents::Number`].
+pub fn number_value(lexeme: &str) -> f64 {
+    let parsed = if lexeme.contains('_') {
+        lexeme.replace('_', "").parse()
+    } else {
+        lexeme.parse()
+    };
+    parsed.expect("The scanner only accepts valid numbers")
+}
+
+/// `\r\n` is a single grapheme, so it counts as one line just like a lone `\r` or `\n`.
+fn is_newline(c: &str) -> bool {
+    NEWLINE_GRAPHEMES.contains(&c)
+}
+
 fn is_digit(c: &str) -> bool {
     DIGITS.contains(&c)
 }
 
+/// Whether the grapheme can start an identifier, based on its first `char` so combining marks are
+/// allowed.
 fn is_letter_or_underscore(c: &str) -> bool {
-    LOWERCASE_LETTERS.contains(&c) || UPPERCASE_LETTERS.contains(&c) || UNDERSCORE.contains(&c)
+    c.chars()
+        .next()
+        .is_some_and(|ch| ch == '_' || ch.is_alphabetic())
+}
+
+fn is_identifier_continue(c: &str) -> bool {
+    c.chars()
+        .next()
+        .is_some_and(|ch| ch == '_' || ch.is_alphanumeric())
 }
 
 impl<'a> Iterator for SourceIterator<'a> {
@@ -409,11 +562,23 @@ impl<'a> Iterator for SourceIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.skip_whitespace();
+        self.token_start = self.start_byte;
         let c = self.get_and_advance()?;
-        let res = self
-            .match_token(c)
-            .or_else(|| Some(Err(ScanError::UnknownToken(c.to_string(), self.line))));
+        let res = self.match_token(c).or_else(|| {
+            Some(Err(ScanError::UnknownToken(
+                c.to_string(),
+                self.line,
+                self.token_start,
+            )))
+        });
         self.reset();
+        self.after_identifier = matches!(
+            res,
+            Some(Ok(Token {
+                contents: TokenContents::Identifier(_),
+                ..
+            }))
+        );
         res
     }
 }
@@ -423,9 +588,49 @@ impl<'a> FusedIterator for SourceIterator<'a> {}
 #[derive(Error, Debug, PartialEq, Clone)]
 pub enum ScanError {
     #[error("Unknown token {0}")]
-    UnknownToken(String, usize),
+    UnknownToken(String, usize, usize),
     #[error("[line {1}] Error: Unterminated string. First line: '{0}'")]
-    UnterminatedString(String, usize),
+    UnterminatedString(String, usize, usize),
+    #[error("[line {1}] Error: Invalid number '{0}'.")]
+    InvalidNumber(String, usize, usize),
+}
+
+impl ScanError {
+    pub fn line(&self) -> usize {
+        match self {
+            ScanError::UnknownToken(_, line, _)
+            | ScanError::UnterminatedString(_, line, _)
+            | ScanError::InvalidNumber(_, line, _) => *line,
+        }
+    }
+
+    /// Byte offset in the source where the lexeme starts.
+    pub fn offset(&self) -> usize {
+        match self {
+            ScanError::UnknownToken(_, _, offset)
+            | ScanError::UnterminatedString(_, _, offset)
+            | ScanError::InvalidNumber(_, _, offset) => *offset,
+        }
+    }
+
+    /// The source text the error is about, as it appears on [`ScanError::line`].
+    pub fn lexeme(&self) -> &str {
+        match self {
+            ScanError::UnknownToken(lexeme, _, _)
+            | ScanError::UnterminatedString(lexeme, _, _)
+            | ScanError::InvalidNumber(lexeme, _, _) => lexeme,
+        }
+    }
+
+    /// 1-based column, in graphemes, of the lexeme within its line of `source`, the source it was
+    /// scanned from.
+    pub fn column(&self, source: &str) -> Option<usize> {
+        // Offsets are counted after the byte order mark the scanner skips
+        let source = source.strip_prefix('\u{FEFF}').unwrap_or(source);
+        let before = source.get(..self.offset())?;
+        let line_start = before.rfind(['\n', '\r']).map_or(0, |idx| idx + 1);
+        Some(before[line_start..].graphemes(true).count() + 1)
+    }
 }
 
 #[cfg(test)]
@@ -441,15 +646,91 @@ mod tests {
         assert_eq!(iter.count(), 3);
     }
 
+    /// Scanning used to shift all remaining graphemes after every token, which made this quadratic.
+    #[test]
+    fn large_source_scans_in_linear_time() {
+        let copies = 10_000;
+        let source = "var café_1 = \"ünïcode\" + 12.5; // cömment\n".repeat(copies);
+        let tokens: Vec<_> = Scanner::new(&source).iter().map(Result::unwrap).collect();
+        assert_eq!(tokens.len(), 7 * copies);
+        for (i, line) in tokens.chunks(7).enumerate() {
+            let expected = [
+                Var,
+                Identifier("café_1"),
+                Equal,
+                String("ünïcode"),
+                Plus,
+                Number("12.5"),
+                Semicolon,
+            ]
+            .map(|contents| Token::new(contents, i + 1));
+            assert_eq!(line, expected);
+        }
+
+        // Quadrupling the source would take ~16 times as long if scanning were quadratic. The bound
+        // is loose so a noisy machine doesn't fail the test, and each size takes its best of three.
+        let scan_time = |copies: usize| {
+            let source = "var café_1 = \"ünïcode\" + 12.5; // cömment\n".repeat(copies);
+            (0..3)
+                .map(|_| {
+                    let start = std::time::Instant::now();
+                    assert_eq!(Scanner::new(&source).iter().count(), 7 * copies);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+        let small = scan_time(copies / 2);
+        let large = scan_time(copies * 2);
+        assert!(
+            large < small * 10,
+            "scanning 4x the source took {large:?}, against {small:?}"
+        );
+    }
+
+    #[test]
+    fn comments_are_kept_on_request() {
+        let source = "var a; // foo\n// bar\na";
+        let tokens: Vec<_> = Scanner::new_with_comments(source)
+            .iter()
+            .map(Result::unwrap)
+            .collect();
+        let expected = [
+            Token::new(Var, 1),
+            Token::new(Identifier("a"), 1),
+            Token::new(Semicolon, 1),
+            Token::new(LineComment(" foo"), 1),
+            Token::new(LineComment(" bar"), 2),
+            Token::new(Identifier("a"), 3),
+        ];
+        assert_eq!(tokens, expected);
+
+        let skipped: Vec<_> = Scanner::new(source).iter().map(Result::unwrap).collect();
+        assert_eq!(skipped, [&expected[..3], &expected[5..]].concat());
+    }
+
     #[test]
     fn single_char() {
-        let source = "(){};,.-+/*";
+        let source = "(){}[]:;,.-+/*%";
         let scanner = Scanner::new(source);
         let iter = scanner.iter();
         let res: Vec<_> = iter.map(|t| t.unwrap().contents).collect();
         let expected = [
-            LeftParen, RightParen, LeftBrace, RightBrace, Semicolon, Comma, Dot, Minus, Plus,
-            Slash, Asterisk,
+            LeftParen,
+            RightParen,
+            LeftBrace,
+            RightBrace,
+            LeftBracket,
+            RightBracket,
+            Colon,
+            Semicolon,
+            Comma,
+            Dot,
+            Minus,
+            Plus,
+            Slash,
+            Asterisk,
+            Percent,
         ];
         assert_eq!(&res, &expected);
     }
@@ -475,6 +756,57 @@ mod tests {
         assert_eq!(&res, &expected);
     }
 
+    #[test]
+    fn compound_assignment() {
+        let source = "+= -= *= /= %= + =";
+        let scanner = Scanner::new(source);
+        let iter = scanner.iter();
+        let res: Vec<_> = iter.map(|t| t.unwrap().contents).collect();
+        let expected = [
+            PlusEqual,
+            MinusEqual,
+            AsteriskEqual,
+            SlashEqual,
+            PercentEqual,
+            Plus,
+            Equal,
+        ];
+        assert_eq!(&res, &expected);
+    }
+
+    #[test]
+    fn increment_decrement() {
+        let source = "i++ --i +++ i-- - 1 1--1 i--1 i-- and";
+        let scanner = Scanner::new(source);
+        let iter = scanner.iter();
+        let res: Vec<_> = iter.map(|t| t.unwrap().contents).collect();
+        let expected = [
+            Identifier("i"),
+            PlusPlus,
+            Minus,
+            Minus,
+            Identifier("i"),
+            PlusPlus,
+            Plus,
+            Identifier("i"),
+            MinusMinus,
+            Minus,
+            Number("1"),
+            Number("1"),
+            Minus,
+            Minus,
+            Number("1"),
+            Identifier("i"),
+            Minus,
+            Minus,
+            Number("1"),
+            Identifier("i"),
+            MinusMinus,
+            And,
+        ];
+        assert_eq!(&res, &expected);
+    }
+
     #[test]
     fn string() {
         let source = "\n\"hi!\nsup\"\n\"how are you?\"";
@@ -488,6 +820,53 @@ mod tests {
         assert_eq!(&res, &expected);
     }
 
+    #[test]
+    fn leading_bom_is_skipped() {
+        let source = "\u{FEFF}print;\r\n";
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [Ok(Token::new(Print, 1)), Ok(Token::new(Semicolon, 1))];
+        assert_eq!(&res, &expected);
+        assert_eq!(Scanner::new("\u{FEFF}").iter().count(), 0);
+    }
+
+    #[test]
+    fn raw_string() {
+        let source = r#"r"C:\path\n" rate r"#;
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [
+            Ok(Token::new(String(r"C:\path\n"), 1)),
+            Ok(Token::new(Identifier("rate"), 1)),
+            Ok(Token::new(Identifier("r"), 1)),
+        ];
+        assert_eq!(&res, &expected);
+    }
+
+    #[test]
+    fn unterminated_raw_string() {
+        let source = "r\"no close";
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [Err(ScanError::UnterminatedString(
+            "r\"no close".to_string(),
+            1,
+            0,
+        ))];
+        assert_eq!(&res, &expected);
+    }
+
+    #[test]
+    fn string_crlf() {
+        let source = "\"a\r\nb\rc\nd\"\r\ne\r\r\nf";
+        let scanner = Scanner::new(source);
+        let iter = scanner.iter();
+        let res: Vec<_> = iter.map(|t| t.unwrap()).collect();
+        let expected = [
+            Token::new(String("a\r\nb\rc\nd"), 1),
+            Token::new(Identifier("e"), 5),
+            Token::new(Identifier("f"), 7),
+        ];
+        assert_eq!(&res, &expected);
+    }
+
     #[test]
     fn unterminated_string() {
         let source = r#"// [line 2] Error: Unterminated string.
@@ -499,10 +878,54 @@ mod tests {
         let expected = [ScanError::UnterminatedString(
             "\"this string has no close quote".to_string(),
             2,
+            40,
         )];
         assert_eq!(&res, &expected);
     }
 
+    #[test]
+    fn scanning_resumes_after_unterminated_string() {
+        let source = "\"closed\" var a = \"no close;\nprint a;\n";
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [
+            Ok(Token::new(String("closed"), 1)),
+            Ok(Token::new(Var, 1)),
+            Ok(Token::new(Identifier("a"), 1)),
+            Ok(Token::new(Equal, 1)),
+            Err(ScanError::UnterminatedString(
+                "\"no close;".to_string(),
+                1,
+                17,
+            )),
+            Ok(Token::new(Print, 2)),
+            Ok(Token::new(Identifier("a"), 2)),
+            Ok(Token::new(Semicolon, 2)),
+        ];
+        assert_eq!(&res, &expected);
+    }
+
+    #[test]
+    fn token_columns() {
+        let source = "var a;\n  print \"é\" + b;";
+        let mut iter = Scanner::new(source).iter();
+        let mut columns = Vec::new();
+        while iter.next().is_some() {
+            columns.push(iter.last_token_column());
+        }
+        assert_eq!(columns, [1, 5, 6, 3, 9, 13, 15, 16]);
+    }
+
+    #[test]
+    fn repeated_error_lexemes_have_their_own_columns() {
+        let source = "var a;\né @ @ 1;";
+        let columns: Vec<_> = Scanner::new(source)
+            .iter()
+            .filter_map(Result::err)
+            .map(|e| e.column(source))
+            .collect();
+        assert_eq!(columns, [Some(3), Some(5)]);
+    }
+
     #[test]
     fn digit() {
         let source = "0.123456789\n14482.148210:";
@@ -512,11 +935,87 @@ mod tests {
         let expected = [
             Ok(Token::new(Number("0.123456789"), 1)),
             Ok(Token::new(Number("14482.148210"), 2)),
-            Err(ScanError::UnknownToken(":".to_owned(), 2)),
+            Ok(Token::new(Colon, 2)),
+        ];
+        assert_eq!(&res, &expected);
+    }
+
+    #[test]
+    fn digit_separators() {
+        let source = "1_000 1.234_5 1_0.0_1 _1";
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [
+            Ok(Token::new(Number("1_000"), 1)),
+            Ok(Token::new(Number("1.234_5"), 1)),
+            Ok(Token::new(Number("1_0.0_1"), 1)),
+            // A leading underscore starts an identifier
+            Ok(Token::new(Identifier("_1"), 1)),
+        ];
+        assert_eq!(&res, &expected);
+        assert_eq!(number_value("1_000"), 1000.0);
+        assert_eq!(number_value("1.234_5"), 1.2345);
+    }
+
+    #[test]
+    fn misplaced_digit_separators() {
+        for num in ["1_", "1__0", "1_.5", "2.5_", "1_a"] {
+            let res: Vec<_> = Scanner::new(num).iter().collect();
+            let lexeme = num.trim_end_matches('a');
+            assert_eq!(
+                res[0],
+                Err(ScanError::InvalidNumber(lexeme.to_string(), 1, 0)),
+                "{num}"
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_dot() {
+        let source = "1.;";
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [
+            Ok(Token::new(Number("1"), 1)),
+            Ok(Token::new(Dot, 1)),
+            Ok(Token::new(Semicolon, 1)),
         ];
         assert_eq!(&res, &expected);
     }
 
+    #[test]
+    fn leading_dot() {
+        let source = ".5";
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [Ok(Token::new(Dot, 1)), Ok(Token::new(Number("5"), 1))];
+        assert_eq!(&res, &expected);
+    }
+
+    #[test]
+    fn decimal_point_at_eof() {
+        let source = "1.";
+        let res: Vec<_> = Scanner::new(source).iter().collect();
+        let expected = [Ok(Token::new(Number("1"), 1)), Ok(Token::new(Dot, 1))];
+        assert_eq!(&res, &expected);
+    }
+
+    #[test]
+    fn unicode_identifier() {
+        let source = "var café = über_1 + 名前; ё";
+        let scanner = Scanner::new(source);
+        let iter = scanner.iter();
+        let res: Vec<_> = iter.collect();
+        let expected = [
+            Ok(Token::new(Var, 1)),
+            Ok(Token::new(Identifier("café"), 1)),
+            Ok(Token::new(Equal, 1)),
+            Ok(Token::new(Identifier("über_1"), 1)),
+            Ok(Token::new(Plus, 1)),
+            Ok(Token::new(Identifier("名前"), 1)),
+            Ok(Token::new(Semicolon, 1)),
+            Ok(Token::new(Identifier("ё"), 1)),
+        ];
+        assert_eq!(&res, &expected)
+    }
+
     #[test]
     fn identifier() {
         let source = "a Beta _c class";
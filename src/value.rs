@@ -1,7 +1,10 @@
 use crate::memory::Object;
-use std::fmt::{Display, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Copy, Clone)]
+/// Copying an `Obj` only copies the pointer, so both copies see changes to a mutable object like
+/// a map. Scripts use the `copy` native to get a separate map.
+#[derive(Copy, Clone)]
 pub enum Value {
     Number(f64),
     Boolean(bool),
@@ -21,10 +24,54 @@ impl PartialEq for Value {
     }
 }
 
+/// `NaN` is not equal to itself, so strictly this breaks the reflexivity `Eq` promises. Maps and
+/// sets must refuse `NaN` keys, see [`Value::is_valid_key`], every other value is fine.
+impl Eq for Value {}
+
+/// Consistent with [`PartialEq`]: `0` and `-0` hash the same, as do all `NaN`s.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Number(n) => {
+                let n = if *n == 0.0 {
+                    0.0
+                } else if n.is_nan() {
+                    f64::NAN
+                } else {
+                    *n
+                };
+                n.to_bits().hash(state)
+            }
+            Value::Boolean(b) => b.hash(state),
+            Value::Nil => {}
+            Value::Obj(object) => object.hash(state),
+        }
+    }
+}
+
 impl Value {
     pub fn is_falsey(&self) -> bool {
         matches!(self, Value::Boolean(false) | Value::Nil)
     }
+
+    /// Whether the value can be used as a map or set key, which is anything that equals itself.
+    pub fn is_valid_key(&self) -> bool {
+        !matches!(self, Value::Number(n) if n.is_nan())
+    }
+}
+
+/// Unlike [`Display`], tags every value with its type and quotes strings, so the string `"nil"`
+/// can be told apart from `nil`.
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(num) => write!(f, "Number({num})"),
+            Value::Boolean(bool) => write!(f, "Boolean({bool})"),
+            Value::Nil => write!(f, "Nil"),
+            Value::Obj(object) => Debug::fmt(object, f),
+        }
+    }
 }
 
 impl Display for Value {
@@ -33,6 +80,8 @@ impl Display for Value {
             f,
             "{}",
             match self {
+                // Match clox's `%g` rather than Rust's `NaN`
+                Value::Number(num) if num.is_nan() => "nan".to_string(),
                 Value::Number(num) => num.to_string(),
                 Value::Boolean(bool) => bool.to_string(),
                 Value::Nil => "nil".to_string(),
@@ -41,3 +90,61 @@ impl Display for Value {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::allocator::Allocator;
+    use crate::memory::hash_table::HashTable;
+    use crate::memory::MemoryManager;
+
+    #[test]
+    fn non_finite_numbers_display_like_clox() {
+        assert_eq!(Value::Number(f64::NAN).to_string(), "nan");
+        assert_eq!(Value::Number(-f64::NAN).to_string(), "nan");
+        assert_eq!(Value::Number(f64::INFINITY).to_string(), "inf");
+        assert_eq!(Value::Number(f64::NEG_INFINITY).to_string(), "-inf");
+    }
+
+    #[test]
+    fn debug_quotes_strings() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let string = Value::Obj(Object::String(memory_manager.intern("nil")));
+        assert_eq!(format!("{string}"), "nil");
+        assert_eq!(format!("{string:?}"), "String(\"nil\")");
+        assert_eq!(format!("{:?}", Value::Nil), "Nil");
+        assert_ne!(format!("{string:?}"), format!("{:?}", Value::Nil));
+        assert_eq!(format!("{:?}", Value::Number(1.5)), "Number(1.5)");
+    }
+
+    fn hash_of(value: Value) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn equal_values_hash_equally() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let a = memory_manager.intern("a");
+        let b = memory_manager.intern("b");
+        let concat = Value::Obj(Object::String(memory_manager.new_str_concat(&a, &b)));
+        let interned = Value::Obj(Object::String(memory_manager.intern("ab")));
+        assert_eq!(concat, interned);
+        assert_eq!(hash_of(concat), hash_of(interned));
+        assert_ne!(hash_of(interned), hash_of(Value::Obj(Object::String(a))));
+
+        assert_eq!(hash_of(Value::Number(0.0)), hash_of(Value::Number(-0.0)));
+        assert_eq!(
+            hash_of(Value::Number(f64::NAN)),
+            hash_of(Value::Number(-f64::NAN))
+        );
+        assert_ne!(hash_of(Value::Nil), hash_of(Value::Boolean(false)));
+        assert!(!Value::Number(f64::NAN).is_valid_key());
+        assert!(Value::Nil.is_valid_key());
+    }
+}
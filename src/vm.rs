@@ -1,58 +1,369 @@
-use crate::chunk::{Chunk, Opcode};
+use crate::chunk::{Chunk, ChunkError, Opcode};
 use crate::memory::allocator::Allocator;
-use crate::memory::hash_table::HashTable;
-use crate::memory::{MemoryManager, Object};
+use crate::memory::{MemoryManager, ObjFunction, ObjMap, ObjString, Object, VMHeap, VMHeapVec};
+use crate::native::{NativeContext, EXPECT_ERROR, NATIVES};
 use crate::value::Value;
 use log::{error, trace};
 use num_enum::TryFromPrimitiveError;
-use std::io::Write;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{BufRead, Write};
 use std::sync::Arc;
 use thiserror::Error;
 
 type VMResult<A> = Result<A, VMError>;
 
+/// Where a running program sends what it prints, and where the VM reports problems that don't
+/// stop the program.
+pub trait Output {
+    /// Shows `value` on a line of its own, for `print` and `println`.
+    fn print(&mut self, value: &Value);
+
+    /// Shows `value` without ending the line, for `write`.
+    fn write(&mut self, value: &Value) {
+        self.print(value)
+    }
+
+    /// Reports a problem outside the program's own output, such as a failed write or the state of
+    /// the VM when one of its invariants broke.
+    fn error(&mut self, msg: &str);
+}
+
+/// Writes values as text and logs errors, including failures to write.
+impl<W: Write + ?Sized> Output for W {
+    fn print(&mut self, value: &Value) {
+        if let Err(e) = writeln!(self, "{value}") {
+            Output::error(self, &format!("Error writing output value: {e}"));
+        }
+    }
+
+    fn write(&mut self, value: &Value) {
+        if let Err(e) = write!(self, "{value}") {
+            Output::error(self, &format!("Error writing output value: {e}"));
+        }
+    }
+
+    fn error(&mut self, msg: &str) {
+        error!("{msg}")
+    }
+}
+
+/// Callbacks invoked by the VM while it runs, for debuggers and tracing tools.
+pub trait VmHook {
+    /// Called before the instruction at `ip` is executed.
+    fn before_instruction(&mut self, _ip: usize, _chunk: &Chunk, _stack: &[Value]) {}
+}
+
+impl VmHook for () {}
+
+impl<H: VmHook + ?Sized> VmHook for &mut H {
+    fn before_instruction(&mut self, ip: usize, chunk: &Chunk, stack: &[Value]) {
+        (**self).before_instruction(ip, chunk, stack)
+    }
+}
+
+/// Logs the stack and the upcoming instruction at trace level.
+#[derive(Debug, Default)]
+pub struct TraceHook;
+
+impl VmHook for TraceHook {
+    fn before_instruction(&mut self, ip: usize, chunk: &Chunk, stack: &[Value]) {
+        trace!("Stack:\n{stack:?}");
+        trace!(
+            "Instruction at {ip}: {instruction}",
+            instruction = chunk
+                .disassemble_instruction_at(ip)
+                .unwrap_or_else(|| "Not found, crash imminent".to_string())
+        );
+    }
+}
+
+/// Default upper bound on the number of values on the stack. Leaves room for a full set of 256
+/// locals plus the temporaries of the expressions using them.
+pub const DEFAULT_STACK_SIZE: usize = 1024;
+
+/// Default upper bound on the number of nested calls, like clox.
+pub const DEFAULT_MAX_FRAMES: usize = 64;
+
+/// Settings that change how the VM executes.
+#[derive(Debug, Clone)]
+pub struct VMOptions {
+    /// Raise a runtime error when an integer is divided by zero, instead of producing infinity or
+    /// NaN.
+    pub strict_math: bool,
+    /// Maximum number of values on the stack before a [`RuntimeError::StackOverflow`].
+    pub stack_size: usize,
+    /// Maximum number of nested calls before a [`RuntimeError::CallDepthExceeded`].
+    pub max_frames: usize,
+}
+
+impl Default for VMOptions {
+    fn default() -> Self {
+        Self {
+            strict_math: false,
+            stack_size: DEFAULT_STACK_SIZE,
+            max_frames: DEFAULT_MAX_FRAMES,
+        }
+    }
+}
+
+/// A function call in progress.
 #[derive(Debug)]
-pub struct VM<'a, W: Write> {
-    write: &'a mut W,
+struct CallFrame {
+    /// `None` for the top-level script.
+    function: Option<VMHeap<ObjFunction>>,
+    /// Where to continue in this frame once the function it called returns.
+    ip: usize,
+    /// Stack index of the callee, local slot 0 of this frame.
+    slots: usize,
+    /// Line of the `expect_error` call that runs this frame, errors in it unwind to the caller.
+    expecting_error: Option<usize>,
+}
+
+pub struct VM<'a, W: Output> {
+    output: &'a mut W,
+    /// What `read_line` reads from, stdin if `None`. Stdin is only locked while a line is read.
+    input: Option<Box<dyn BufRead + 'a>>,
     ip: usize,
+    frames: Vec<CallFrame>,
     memory_manager: MemoryManager,
-    globals: HashTable,
+    globals: VMHeapVec<Option<Value>>,
+    hook: Box<dyn VmHook + 'a>,
+    options: VMOptions,
+    /// The `expect_error` native, which the VM calls itself since it has to run a Lox function.
+    expect_error: Option<Object>,
 }
 
-impl<'a, W: Write> VM<'a, W> {
-    pub fn new(write: &'a mut W, memory_manager: MemoryManager, allocator: Arc<Allocator>) -> Self {
-        Self {
-            write,
+impl<'a, W: Output> VM<'a, W> {
+    pub fn new(
+        output: &'a mut W,
+        memory_manager: MemoryManager,
+        allocator: Arc<Allocator>,
+    ) -> Self {
+        let mut vm = Self {
+            output,
+            input: None,
             ip: 0,
+            frames: Vec::new(),
             memory_manager,
-            globals: HashTable::new(allocator),
+            globals: VMHeapVec::new(allocator),
+            hook: Box::new(TraceHook),
+            options: VMOptions::default(),
+            expect_error: None,
+        };
+        vm.define_natives();
+        vm
+    }
+
+    fn define_natives(&mut self) {
+        for &(name, arity, function) in NATIVES {
+            // Without a free slot the name can't be referenced by any script either
+            if let Some(slot) = self.memory_manager.global_slot(name) {
+                let native = Object::Native(self.memory_manager.new_native(name, arity, function));
+                if name == EXPECT_ERROR {
+                    self.expect_error = Some(native);
+                }
+                self.define_global(slot as usize, Value::Obj(native));
+            }
+        }
+    }
+
+    fn define_global(&mut self, slot: usize, value: Value) {
+        while self.globals.len() <= slot {
+            self.globals.push(None);
         }
+        self.globals[slot] = Some(value);
+    }
+
+    pub fn with_options(mut self, options: VMOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_input(mut self, input: impl BufRead + 'a) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn with_hook(mut self, hook: impl VmHook + 'a) -> Self {
+        self.hook = Box::new(hook);
+        self
+    }
+
+    /// Clears the stack and instruction pointer so another chunk can be run, keeping the globals
+    /// and interned strings.
+    pub fn reset(&mut self) {
+        self.ip = 0;
+        self.frames.clear();
+        self.memory_manager.stack_mut().truncate(0);
+    }
+
+    /// For compiling further chunks that share this VM's globals.
+    pub fn memory_manager_mut(&mut self) -> &mut MemoryManager {
+        &mut self.memory_manager
     }
 
     pub fn run(&mut self, chunk: &Chunk) -> VMResult<()> {
+        self.eval(chunk).map(|_| ())
+    }
+
+    /// Like [`VM::run`], but returns the script's result, `nil` unless it returned early.
+    pub fn eval(&mut self, chunk: &Chunk) -> VMResult<Value> {
+        match self.run_chunk(chunk) {
+            Err(VMError::RuntimeError(e, _)) => Err(VMError::RuntimeError(
+                e,
+                StackTrace(self.stack_trace(chunk)),
+            )),
+            Err(VMError::IncorrectInvariantError(e)) => {
+                let diagnostic = self.invariant_diagnostic(chunk, &e);
+                self.output.error(&diagnostic);
+                Err(VMError::IncorrectInvariantError(e))
+            }
+            res => res,
+        }
+    }
+
+    /// Describes the state of the VM when `error` happened, to make bug reports actionable: the
+    /// instruction that was executing, the instruction pointer and the stack.
+    fn invariant_diagnostic(&self, script: &Chunk, error: &IncorrectInvariantError) -> String {
+        if let IncorrectInvariantError::InvalidChunk(_) = error {
+            return format!(
+                "Invariant violated before running {}: {error}",
+                script.name()
+            );
+        }
+        let function = self.frames.last().and_then(|frame| frame.function);
+        let chunk = match &function {
+            Some(function) => function.chunk(),
+            None => script,
+        };
+        // The instruction pointer has already moved past the instruction, or into its operands
+        let instruction = chunk
+            .instructions()
+            .take_while(|(offset, _, _)| *offset < self.ip)
+            .last()
+            .and_then(|(offset, _, _)| chunk.disassemble_instruction_at(offset))
+            .unwrap_or_else(|| "(none)".to_string());
+        format!(
+            "Invariant violated: {error}\n  in {} at ip {}\n  instruction: {instruction}\n  stack: {:?}",
+            chunk.name(),
+            self.ip,
+            self.memory_manager.stack(),
+        )
+    }
+
+    /// Function names and current lines of all active frames, innermost first.
+    pub fn stack_trace(&self, script: &Chunk) -> Vec<(String, usize)> {
+        self.frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, frame)| {
+                let ip = if depth == 0 { self.ip } else { frame.ip };
+                let (name, line) = match frame.function {
+                    Some(function) => {
+                        let name = match function.name() {
+                            Some(name) => format!("{}()", *name),
+                            None => "lambda()".to_string(),
+                        };
+                        (name, line_before(function.chunk(), ip))
+                    }
+                    None => ("script".to_string(), line_before(script, ip)),
+                };
+                (name, line)
+            })
+            .collect()
+    }
+
+    fn run_chunk(&mut self, script: &Chunk) -> VMResult<Value> {
+        script.validate().map_err(IncorrectInvariantError::from)?;
+        self.frames.clear();
+        self.frames.push(CallFrame {
+            function: None,
+            ip: 0,
+            slots: 0,
+            expecting_error: None,
+        });
+        let mut function: Option<VMHeap<ObjFunction>> = None;
+        loop {
+            match self.run_frames(script, &mut function) {
+                Err(VMError::RuntimeError(error, trace)) => {
+                    let Some(depth) = self
+                        .frames
+                        .iter()
+                        .rposition(|frame| frame.expecting_error.is_some())
+                    else {
+                        return Err(VMError::RuntimeError(error, trace));
+                    };
+                    // Unwind to the caller of `expect_error`, which gets the error's message
+                    let slots = self.frames[depth].slots;
+                    self.frames.truncate(depth);
+                    let caller = self.frames.last().expect("no active call frame");
+                    self.ip = caller.ip;
+                    function = caller.function;
+                    self.memory_manager.stack_mut().truncate(slots);
+                    self.push_error_message(&error)?;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Runs the innermost frame, which runs `function` or the script if `None`, until the script
+    /// returns or an error is raised.
+    fn run_frames(
+        &mut self,
+        script: &Chunk,
+        function: &mut Option<VMHeap<ObjFunction>>,
+    ) -> VMResult<Value> {
         // TODO some kind of iterator?
         loop {
-            trace!("Stack:\n{stack:?}", stack = self.memory_manager.stack());
-            trace!(
-                "Instruction at {ip}: {instruction}",
-                ip = self.ip,
-                instruction = chunk
-                    .disassemble_instruction_at(self.ip)
-                    .unwrap_or_else(|| "Not found, crash imminent".to_string())
-            );
-            let opcode =
-                Opcode::try_from(self.read_byte(chunk)?).map_err(IncorrectInvariantError::from)?;
+            let chunk = match &*function {
+                Some(function) => function.chunk(),
+                None => script,
+            };
+            self.hook
+                .before_instruction(self.ip, chunk, self.memory_manager.stack());
+            let byte = self.read_byte(chunk)?;
+            // SAFETY: the chunk was validated before entering the loop, and jumps only land on
+            // instruction boundaries
+            #[cfg(feature = "fast_dispatch")]
+            let opcode = unsafe { Opcode::from_byte_unchecked(byte) };
+            #[cfg(not(feature = "fast_dispatch"))]
+            let opcode = Opcode::try_from(byte).map_err(IncorrectInvariantError::from)?;
             match opcode {
                 Opcode::Constant => {
                     let constant = *self.read_constant(chunk)?;
                     self.push(constant)?;
                 }
-                Opcode::Return => break,
+                Opcode::SmallInt => {
+                    let value = self.read_byte(chunk)?;
+                    self.push(Value::Number(value as f64))?;
+                }
+                Opcode::Return => {
+                    let frame = self.frames.pop().expect("no active call frame");
+                    let result = self.pop()?;
+                    let Some(caller) = self.frames.last() else {
+                        return Ok(result);
+                    };
+                    self.ip = caller.ip;
+                    *function = caller.function;
+                    self.memory_manager.stack_mut().truncate(frame.slots);
+                    if let Some(line) = frame.expecting_error {
+                        return Err(missing_error(line, result));
+                    }
+                    self.push(result)?;
+                }
                 Opcode::Negate => {
                     let value = self.pop()?;
                     let value = match value {
                         Value::Number(num) => Value::Number(-num),
-                        _ => return Err(RuntimeError::InvalidType("number").into()),
+                        _ => {
+                            return Err(RuntimeError::InvalidType(
+                                chunk.line_for(self.ip),
+                                "number",
+                            )
+                            .into());
+                        }
                     };
                     self.push(value)?;
                 }
@@ -80,8 +391,13 @@ impl<'a, W: Write> VM<'a, W> {
                     self.binary_op(|a, b| a * b, Value::Number, chunk.line_for(self.ip))?
                 }
                 Opcode::Divide => {
+                    self.check_division(chunk.line_for(self.ip))?;
                     self.binary_op(|a, b| a / b, Value::Number, chunk.line_for(self.ip))?
                 }
+                Opcode::Modulo => {
+                    self.check_division(chunk.line_for(self.ip))?;
+                    self.binary_op(|a, b| a % b, Value::Number, chunk.line_for(self.ip))?
+                }
                 Opcode::True => self.push(Value::Boolean(true))?,
                 Opcode::False => self.push(Value::Boolean(false))?,
                 Opcode::Nil => self.push(Value::Nil)?,
@@ -94,66 +410,86 @@ impl<'a, W: Write> VM<'a, W> {
                     let a = self.pop()?;
                     self.push(Value::Boolean(a == b))?
                 }
+                Opcode::NotEqual => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(Value::Boolean(a != b))?
+                }
                 Opcode::Greater => {
                     self.binary_op(|a, b| a > b, Value::Boolean, chunk.line_for(self.ip))?
                 }
                 Opcode::Less => {
                     self.binary_op(|a, b| a < b, Value::Boolean, chunk.line_for(self.ip))?
                 }
-                Opcode::Print => {
-                    let value = self.pop()?;
-                    self.print_value(value)?;
+                Opcode::Call => {
+                    let line = chunk.line_for(self.ip);
+                    let arg_count = self.read_byte(chunk)?;
+                    if let Some(callee) = self.call_value(arg_count, line)? {
+                        *function = Some(callee);
+                    }
+                }
+                Opcode::TailCall => {
+                    let line = chunk.line_for(self.ip);
+                    let arg_count = self.read_byte(chunk)?;
+                    if let Some(callee) = self.tail_call_value(arg_count, line)? {
+                        *function = Some(callee);
+                    }
                 }
                 Opcode::Pop => {
                     let _ = self.pop()?;
                 }
+                Opcode::Dup => {
+                    let value = *self.peek(0)?;
+                    self.push(value)?;
+                }
+                Opcode::PopN => {
+                    let count = self.read_byte(chunk)? as usize;
+                    let stack = self.memory_manager.stack_mut();
+                    let len = stack
+                        .len()
+                        .checked_sub(count)
+                        .ok_or(IncorrectInvariantError::StackUnderflow)?;
+                    stack.truncate(len);
+                }
                 Opcode::DefineGlobal => {
-                    let name = self.read_constant(chunk)?;
-                    match name {
-                        Value::Obj(obj) => {
-                            let Object::String(s) = obj;
-                            let value = self.peek(0)?;
-                            self.globals.insert(*s, *value);
-                            let _ = self.pop();
-                        }
-                        _ => return Err(IncorrectInvariantError::InvalidTypes.into()),
-                    }
+                    let slot = self.read_byte(chunk)? as usize;
+                    let value = self.pop()?;
+                    self.define_global(slot, value);
+                }
+                Opcode::DefineGlobalLong => {
+                    let slot = self.read_long(chunk)? as usize;
+                    let value = self.pop()?;
+                    self.define_global(slot, value);
                 }
                 Opcode::GetGlobal => {
-                    let name = self.read_constant(chunk)?;
-                    match name {
-                        Value::Obj(obj) => {
-                            let Object::String(s) = obj;
-                            if let Some(v) = self.globals.get(*s) {
-                                self.push(*v)?;
-                            } else {
-                                return Err(RuntimeError::UndefinedVariable(obj.to_string()).into());
-                            }
-                        }
-                        _ => return Err(IncorrectInvariantError::InvalidTypes.into()),
-                    }
+                    let line = chunk.line_for(self.ip);
+                    let slot = self.read_byte(chunk)? as u32;
+                    self.get_global(slot, line)?;
+                }
+                Opcode::GetGlobalLong => {
+                    let line = chunk.line_for(self.ip);
+                    let slot = self.read_long(chunk)?;
+                    self.get_global(slot, line)?;
                 }
                 Opcode::SetGlobal => {
-                    let name = self.read_constant(chunk)?;
-                    match name {
-                        Value::Obj(obj) => {
-                            let Object::String(s) = obj;
-                            if self.globals.insert(*s, *self.peek(0)?) {
-                                self.globals.delete(*s);
-                                return Err(RuntimeError::UndefinedVariable(obj.to_string()).into());
-                            }
-                        }
-                        _ => return Err(IncorrectInvariantError::InvalidTypes.into()),
-                    }
+                    let line = chunk.line_for(self.ip);
+                    let slot = self.read_byte(chunk)? as u32;
+                    self.set_global(slot, line)?;
+                }
+                Opcode::SetGlobalLong => {
+                    let line = chunk.line_for(self.ip);
+                    let slot = self.read_long(chunk)?;
+                    self.set_global(slot, line)?;
                 }
                 Opcode::SetLocal => {
                     let slot = self.read_byte(chunk)?;
-                    self.memory_manager.stack_mut()[slot as usize] = *self.peek(0)?;
+                    let value = *self.peek(0)?;
+                    *self.local_mut(slot)? = value;
                 }
                 Opcode::GetLocal => {
                     let slot = self.read_byte(chunk)?;
-                    let val = self.memory_manager.stack_mut()[slot as usize];
-                    self.push(val)?;
+                    let value = *self.local_mut(slot)?;
+                    self.push(value)?;
                 }
                 Opcode::JumpIfFalse => {
                     let offset = self.read_short(chunk)?;
@@ -161,6 +497,12 @@ impl<'a, W: Write> VM<'a, W> {
                         self.ip += offset as usize;
                     }
                 }
+                Opcode::JumpIfTrue => {
+                    let offset = self.read_short(chunk)?;
+                    if !self.peek(0)?.is_falsey() {
+                        self.ip += offset as usize;
+                    }
+                }
                 Opcode::Jump => {
                     let offset = self.read_short(chunk)?;
                     self.ip += offset as usize;
@@ -169,17 +511,86 @@ impl<'a, W: Write> VM<'a, W> {
                     let offset = self.read_short(chunk)?;
                     self.ip -= offset as usize;
                 }
+                Opcode::BuildMap => {
+                    let line = chunk.line_for(self.ip);
+                    let count = self.read_byte(chunk)? as usize;
+                    let start = self
+                        .memory_manager
+                        .stack()
+                        .len()
+                        .checked_sub(2 * count)
+                        .ok_or(IncorrectInvariantError::StackUnderflow)?;
+                    let mut map = self.memory_manager.new_map_with_room_for(count);
+                    for i in 0..count {
+                        let key = self.memory_manager.stack()[start + 2 * i];
+                        if !key.is_valid_key() {
+                            return Err(RuntimeError::InvalidKey(line).into());
+                        }
+                        map.insert(key, self.memory_manager.stack()[start + 2 * i + 1]);
+                    }
+                    self.memory_manager.stack_mut().truncate(start);
+                    self.push(Value::Obj(Object::Map(map)))?;
+                }
+                Opcode::GetIndex => {
+                    let line = chunk.line_for(self.ip);
+                    let key = self.pop()?;
+                    let value = match self.pop()? {
+                        Value::Obj(Object::Map(map)) => map.get(key),
+                        Value::Obj(Object::String(s)) => self.grapheme_at(&s, key, line)?,
+                        _ => return Err(RuntimeError::InvalidType(line, "map or string").into()),
+                    };
+                    self.push(value)?;
+                }
+                Opcode::Len => {
+                    let line = chunk.line_for(self.ip);
+                    let len = match self.pop()? {
+                        Value::Obj(Object::String(s)) => s.grapheme_count(),
+                        _ => return Err(RuntimeError::InvalidType(line, "string").into()),
+                    };
+                    self.push(Value::Number(len as f64))?;
+                }
+                Opcode::SetIndex => {
+                    let line = chunk.line_for(self.ip);
+                    let value = self.pop()?;
+                    let key = self.pop()?;
+                    let mut map = self.pop_map(line)?;
+                    if !key.is_valid_key() {
+                        return Err(RuntimeError::InvalidKey(line).into());
+                    }
+                    map.insert(key, value);
+                    self.push(value)?;
+                }
             }
         }
+    }
 
-        Ok(())
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no active call frame")
     }
 
-    fn print_value(&mut self, value: Value) -> VMResult<()> {
-        if let Err(e) = writeln!(self.write, "{}", value) {
-            error!("Error writing output value: {e}")
+    fn get_global(&mut self, slot: u32, line: usize) -> VMResult<()> {
+        match self.globals.get(slot as usize).copied().flatten() {
+            Some(v) => self.push(v),
+            None => Err(self.undefined_global(slot, line)),
+        }
+    }
+
+    fn set_global(&mut self, slot: u32, line: usize) -> VMResult<()> {
+        let value = *self.peek(0)?;
+        match self.globals.get_mut(slot as usize) {
+            Some(global @ Some(_)) => {
+                *global = Some(value);
+                Ok(())
+            }
+            _ => Err(self.undefined_global(slot, line)),
+        }
+    }
+
+    fn undefined_global(&self, slot: u32, line: usize) -> VMError {
+        match self.memory_manager.global_name(slot) {
+            Some(name) => RuntimeError::UndefinedVariable(line, name.to_string()).into(),
+            None => IncorrectInvariantError::InvalidGlobalSlot { slot }.into(),
         }
-        Ok(())
     }
 
     fn read_byte(&mut self, chunk: &Chunk) -> VMResult<u8> {
@@ -200,6 +611,13 @@ impl<'a, W: Write> VM<'a, W> {
         Ok(((h as u16) << 8) | (l as u16))
     }
 
+    fn read_long(&mut self, chunk: &Chunk) -> VMResult<u32> {
+        let h = self.read_byte(chunk)?;
+        let m = self.read_byte(chunk)?;
+        let l = self.read_byte(chunk)?;
+        Ok(u32::from_be_bytes([0, h, m, l]))
+    }
+
     fn read_constant<'c>(&mut self, chunk: &'c Chunk) -> VMResult<&'c Value> {
         let byte = self.read_byte(chunk)?;
         let constant = chunk
@@ -209,10 +627,12 @@ impl<'a, W: Write> VM<'a, W> {
     }
 
     fn push(&mut self, value: Value) -> VMResult<()> {
-        self.memory_manager
-            .stack_mut()
-            .try_push(value)
-            .map_err(|_| RuntimeError::StackOverflow.into())
+        let stack = self.memory_manager.stack_mut();
+        if stack.len() >= self.options.stack_size {
+            return Err(RuntimeError::StackOverflow.into());
+        }
+        stack.push(value);
+        Ok(())
     }
 
     fn pop(&mut self) -> VMResult<Value> {
@@ -222,6 +642,41 @@ impl<'a, W: Write> VM<'a, W> {
             .ok_or_else(|| IncorrectInvariantError::StackUnderflow.into())
     }
 
+    /// The grapheme at `index` as a new string, strings are indexed like they are measured by `len`.
+    fn grapheme_at(&mut self, s: &ObjString, index: Value, line: usize) -> VMResult<Value> {
+        let Value::Number(index) = index else {
+            return Err(RuntimeError::InvalidType(line, "number").into());
+        };
+        let grapheme = (index.fract() == 0.0 && index >= 0.0)
+            .then(|| s.graphemes(index as usize, (index as usize).saturating_add(1)))
+            .flatten()
+            .ok_or_else(|| RuntimeError::IndexOutOfBounds {
+                start: index,
+                end: index + 1.0,
+                len: s.grapheme_count(),
+            })?;
+        Ok(Value::Obj(Object::String(
+            self.memory_manager.intern(grapheme),
+        )))
+    }
+
+    /// Local `slot` of the current frame, which bytecode that wasn't compiled from Lox may point
+    /// past the top of the stack.
+    fn local_mut(&mut self, slot: u8) -> VMResult<&mut Value> {
+        let index = self.frame().slots + slot as usize;
+        self.memory_manager
+            .stack_mut()
+            .get_mut(index)
+            .ok_or_else(|| IncorrectInvariantError::InvalidLocalSlot { slot }.into())
+    }
+
+    fn pop_map(&mut self, line: usize) -> VMResult<VMHeap<ObjMap>> {
+        match self.pop()? {
+            Value::Obj(Object::Map(map)) => Ok(map),
+            _ => Err(RuntimeError::InvalidType(line, "map").into()),
+        }
+    }
+
     fn binary_op<T>(
         &mut self,
         f: impl Fn(f64, f64) -> T,
@@ -234,19 +689,146 @@ impl<'a, W: Write> VM<'a, W> {
         let res = match (a, b) {
             (Value::Number(a), Value::Number(b)) => v(f(a, b)),
             (_, _) => {
-                return Err(VMError::RuntimeError(RuntimeError::InvalidTypes(
-                    line, "numbers",
-                )));
+                return Err(RuntimeError::InvalidTypes(line, "numbers").into());
             }
         };
         self.push(res)?;
         Ok(())
     }
 
+    /// Calls the value below the arguments. Returns the function if a new frame was entered,
+    /// natives run to completion right away.
+    fn call_value(&mut self, arg_count: u8, line: usize) -> VMResult<Option<VMHeap<ObjFunction>>> {
+        match *self.peek(arg_count as usize)? {
+            Value::Obj(Object::Function(function)) => {
+                if function.arity() != arg_count {
+                    return Err(RuntimeError::WrongArity(line, function.arity(), arg_count).into());
+                }
+                if self.frames.len() >= self.options.max_frames {
+                    return Err(RuntimeError::CallDepthExceeded {
+                        max: self.options.max_frames,
+                    }
+                    .into());
+                }
+                let slots = self.memory_manager.stack().len() - arg_count as usize - 1;
+                self.frames.last_mut().expect("no active call frame").ip = self.ip;
+                self.frames.push(CallFrame {
+                    function: Some(function),
+                    ip: 0,
+                    slots,
+                    expecting_error: None,
+                });
+                self.ip = 0;
+                Ok(Some(function))
+            }
+            Value::Obj(object @ Object::Native(native)) => {
+                if let Some(arity) = native.arity().filter(|&arity| arity != arg_count) {
+                    return Err(RuntimeError::WrongArity(line, arity, arg_count).into());
+                }
+                if Some(object) == self.expect_error {
+                    return self.call_expecting_error(line);
+                }
+                let stack = self.memory_manager.stack();
+                let args_start = stack.len() - arg_count as usize;
+                let args = stack[args_start..].to_vec();
+                let mut ctx = NativeContext {
+                    output: &mut *self.output,
+                    input: self
+                        .input
+                        .as_mut()
+                        .map(|input| &mut **input as &mut dyn BufRead),
+                    memory_manager: &mut self.memory_manager,
+                };
+                let result = (native.function())(&mut ctx, &args).map_err(|e| match e {
+                    RuntimeError::InvalidType(_, expected) => {
+                        RuntimeError::InvalidType(line, expected)
+                    }
+                    e => RuntimeError::NativeFailed(line, Box::new(e)),
+                })?;
+                self.memory_manager.stack_mut().truncate(args_start - 1);
+                self.push(result)?;
+                Ok(None)
+            }
+            _ => Err(RuntimeError::NotCallable(line).into()),
+        }
+    }
+
+    /// Calls the argument of `expect_error` without arguments in its place. Its result is the
+    /// message of the error the call raises, a call that doesn't raise one is an error itself.
+    fn call_expecting_error(&mut self, line: usize) -> VMResult<Option<VMHeap<ObjFunction>>> {
+        let callee = self.pop()?;
+        self.pop()?;
+        self.push(callee)?;
+        match self.call_value(0, line) {
+            Ok(Some(function)) => {
+                self.frames
+                    .last_mut()
+                    .expect("no active call frame")
+                    .expecting_error = Some(line);
+                Ok(Some(function))
+            }
+            Ok(None) => {
+                let result = self.pop()?;
+                Err(missing_error(line, result))
+            }
+            Err(VMError::RuntimeError(error, _)) => {
+                self.pop()?;
+                self.push_error_message(&error)?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn push_error_message(&mut self, error: &RuntimeError) -> VMResult<()> {
+        let message = self.memory_manager.intern(&error.to_string());
+        self.push(Value::Obj(Object::String(message)))
+    }
+
+    /// Like [`VM::call_value`], but a called function takes over the current frame instead of
+    /// pushing a new one.
+    fn tail_call_value(
+        &mut self,
+        arg_count: u8,
+        line: usize,
+    ) -> VMResult<Option<VMHeap<ObjFunction>>> {
+        let callee = *self.peek(arg_count as usize)?;
+        let frame = self.frames.last_mut().expect("no active call frame");
+        let function = match callee {
+            // The script's frame is never replaced
+            Value::Obj(Object::Function(function)) if frame.function.is_some() => function,
+            _ => return self.call_value(arg_count, line),
+        };
+        if function.arity() != arg_count {
+            return Err(RuntimeError::WrongArity(line, function.arity(), arg_count).into());
+        }
+        // Move the callee and its arguments down over the slots of the current frame
+        let stack = self.memory_manager.stack_mut();
+        let callee_start = stack.len() - arg_count as usize - 1;
+        stack.copy_within(callee_start.., frame.slots);
+        stack.truncate(frame.slots + arg_count as usize + 1);
+        frame.function = Some(function);
+        self.ip = 0;
+        Ok(Some(function))
+    }
+
+    fn check_division(&self, line: usize) -> VMResult<()> {
+        if self.options.strict_math {
+            if let (Value::Number(a), Value::Number(b)) = (self.peek(1)?, self.peek(0)?) {
+                if *b == 0.0 && a.fract() == 0.0 {
+                    return Err(RuntimeError::DivisionByZero(line).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn peek(&self, distance: usize) -> VMResult<&Value> {
-        self.memory_manager
-            .stack()
-            .get(self.memory_manager.stack().len() - distance - 1)
+        let stack = self.memory_manager.stack();
+        stack
+            .len()
+            .checked_sub(distance + 1)
+            .and_then(|idx| stack.get(idx))
             .ok_or_else(|| IncorrectInvariantError::StackUnderflow.into())
     }
 
@@ -255,19 +837,74 @@ impl<'a, W: Write> VM<'a, W> {
         let a = self.pop()?;
         let (a, b) = match (a, b) {
             (Value::Obj(Object::String(a)), Value::Obj(Object::String(b))) => (a, b),
-            _ => unreachable!(),
+            _ => return Err(IncorrectInvariantError::InvalidTypes.into()),
         };
         let value = Value::Obj(Object::String(self.memory_manager.new_str_concat(&a, &b)));
         self.push(value)
     }
 }
 
+/// The error for an `expect_error` call at `line` whose function returned `result` instead.
+fn missing_error(line: usize, result: Value) -> VMError {
+    RuntimeError::NativeFailed(
+        line,
+        Box::new(RuntimeError::AssertionFailed(format!(
+            "expected an error, got {result}."
+        ))),
+    )
+    .into()
+}
+
+/// The line of the instruction before `ip`, the one that was executing in a frame, or 0 if the
+/// chunk has no code.
+fn line_before(chunk: &Chunk, ip: usize) -> usize {
+    match chunk.len().checked_sub(1) {
+        Some(last) => chunk.line_for(ip.saturating_sub(1).min(last)),
+        None => 0,
+    }
+}
+
+impl<'a, W: Output> Debug for VM<'a, W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VM")
+            .field("ip", &self.ip)
+            .field("frames", &self.frames)
+            .field("memory_manager", &self.memory_manager)
+            .field("globals", &self.globals)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Error, Debug, Clone)]
 pub enum VMError {
     #[error("Compilation error: {0}")]
     IncorrectInvariantError(#[from] IncorrectInvariantError),
-    #[error("runtime error: {0}")]
-    RuntimeError(#[from] RuntimeError),
+    #[error("runtime error: {0}{1}")]
+    RuntimeError(RuntimeError, StackTrace),
+}
+
+impl From<RuntimeError> for VMError {
+    fn from(value: RuntimeError) -> Self {
+        VMError::RuntimeError(value, StackTrace::default())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StackTrace(Vec<(String, usize)>);
+
+impl StackTrace {
+    pub fn frames(&self) -> &[(String, usize)] {
+        &self.0
+    }
+}
+
+impl Display for StackTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (name, line) in self.0.iter() {
+            write!(f, "\n[line {line}] in {name}")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug, Clone)]
@@ -280,18 +917,313 @@ pub enum IncorrectInvariantError {
     StackUnderflow,
     #[error("invalid compile time types")]
     InvalidTypes,
+    #[error("invalid chunk? {0}")]
+    InvalidChunk(#[from] ChunkError),
+    #[error("invalid global slot? {slot}")]
+    InvalidGlobalSlot { slot: u32 },
+    #[error("invalid local slot? {slot}")]
+    InvalidLocalSlot { slot: u8 },
 }
 
 #[derive(Error, Debug, Clone)]
 pub enum RuntimeError {
     #[error("invalid instruction pointer {pointer}, max length {chunk_length}")]
     InvalidInstructionPointer { pointer: usize, chunk_length: usize },
-    #[error("stack overflow")]
+    #[error("Stack overflow: too many values on the stack.")]
     StackOverflow,
+    #[error("Stack overflow: more than {max} nested calls.")]
+    CallDepthExceeded { max: usize },
     #[error("Invalid types: Operands must be {1}. [line {0}]")]
     InvalidTypes(usize, &'static str),
-    #[error("Invalid type: Operand must be a {0}.")]
-    InvalidType(&'static str),
-    #[error("Undefined variable '{0}'.")]
-    UndefinedVariable(String),
+    #[error("Invalid type: Operand must be a {1}. [line {0}]")]
+    InvalidType(usize, &'static str),
+    #[error("Division by zero. [line {0}]")]
+    DivisionByZero(usize),
+    #[error("Undefined variable '{1}'. [line {0}]")]
+    UndefinedVariable(usize, String),
+    #[error("Can only call functions and classes. [line {0}]")]
+    NotCallable(usize),
+    #[error("Expected {1} arguments but got {2}. [line {0}]")]
+    WrongArity(usize, u8, u8),
+    #[error("{1} [line {0}]")]
+    NativeFailed(usize, Box<RuntimeError>),
+    #[error("Assertion failed: {0}")]
+    AssertionFailed(String),
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("I/O error: {0}")]
+    Io(String),
+    #[error("NaN can't be used as a map key. [line {0}]")]
+    InvalidKey(usize),
+    #[error("Range {start}..{end} is out of bounds for length {len}.")]
+    IndexOutOfBounds { start: f64, end: f64, len: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::memory::hash_table::HashTable;
+    use crate::scanner::Scanner;
+
+    #[derive(Default)]
+    struct RecordingHook {
+        opcodes: Vec<Opcode>,
+    }
+
+    impl VmHook for RecordingHook {
+        fn before_instruction(&mut self, ip: usize, chunk: &Chunk, _stack: &[Value]) {
+            self.opcodes.push(Opcode::try_from(chunk[ip]).unwrap());
+        }
+    }
+
+    #[test]
+    fn add_on_empty_stack() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let mut chunk = Chunk::new("test".to_string(), alloc.clone());
+        chunk.add_opcode(Opcode::Add, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        let mut out = Vec::new();
+        let err = VM::new(&mut out, memory_manager, alloc)
+            .run(&chunk)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            VMError::IncorrectInvariantError(IncorrectInvariantError::StackUnderflow)
+        ));
+    }
+
+    /// Keeps the errors the VM reports so tests can check them.
+    #[derive(Default)]
+    struct CapturingOutput {
+        errors: Vec<String>,
+    }
+
+    impl Output for CapturingOutput {
+        fn print(&mut self, _value: &Value) {}
+
+        fn error(&mut self, msg: &str) {
+            self.errors.push(msg.to_string());
+        }
+    }
+
+    #[test]
+    fn invariant_error_reports_diagnostic() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let mut chunk = Chunk::new("crafted".to_string(), alloc.clone());
+        chunk.add_opcode(Opcode::True, 1);
+        // No global was ever given slot 200
+        chunk.add_opcode_and_operand(Opcode::GetGlobal, 200, 2);
+        chunk.add_opcode(Opcode::Return, 2);
+        let mut out = CapturingOutput::default();
+        let err = VM::new(&mut out, memory_manager, alloc)
+            .run(&chunk)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            VMError::IncorrectInvariantError(IncorrectInvariantError::InvalidGlobalSlot {
+                slot: 200
+            })
+        ));
+
+        let diagnostic = out
+            .errors
+            .iter()
+            .find(|message| message.contains("invalid global slot? 200"))
+            .expect("no diagnostic reported");
+        assert!(diagnostic.contains("in crafted at ip 3"), "{diagnostic}");
+        assert!(
+            diagnostic.contains("instruction: 0x0001 0002 GetGlobal 200"),
+            "{diagnostic}"
+        );
+        assert!(
+            diagnostic.contains("stack: [Boolean(true)]"),
+            "{diagnostic}"
+        );
+    }
+
+    #[test]
+    fn empty_chunk_is_rejected_before_running() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let chunk = Chunk::new("empty".to_string(), alloc.clone());
+        assert_eq!(line_before(&chunk, 0), 0);
+        let mut out = CapturingOutput::default();
+        let err = VM::new(&mut out, memory_manager, alloc)
+            .run(&chunk)
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                VMError::IncorrectInvariantError(IncorrectInvariantError::InvalidChunk(
+                    ChunkError::MissingReturn
+                ))
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn out_of_range_local_slot_is_an_error() {
+        for opcode in [Opcode::GetLocal, Opcode::SetLocal] {
+            let alloc = Allocator::new();
+            let strings = HashTable::new(alloc.clone());
+            let memory_manager = MemoryManager::new(alloc.clone(), strings);
+            let mut chunk = Chunk::new("crafted".to_string(), alloc.clone());
+            chunk.add_opcode(Opcode::True, 1);
+            chunk.add_opcode_and_operand(opcode, 7, 1);
+            chunk.add_opcode(Opcode::Return, 1);
+            let mut out = Vec::new();
+            let err = VM::new(&mut out, memory_manager, alloc)
+                .run(&chunk)
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    VMError::IncorrectInvariantError(IncorrectInvariantError::InvalidLocalSlot {
+                        slot: 7
+                    })
+                ),
+                "{opcode:?}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn dup_copies_top_of_stack() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let print = memory_manager.global_slot("print").unwrap();
+        let mut chunk = Chunk::new("test".to_string(), alloc.clone());
+        let constant = chunk.add_constant(Value::Number(2.5)).unwrap();
+        chunk.add_global_opcode(Opcode::GetGlobal, print, 1);
+        chunk.add_opcode_and_operand(Opcode::Constant, constant, 1);
+        chunk.add_opcode(Opcode::Dup, 1);
+        chunk.add_opcode(Opcode::Add, 1);
+        chunk.add_opcode_and_operand(Opcode::Call, 1, 1);
+        chunk.add_opcode(Opcode::Pop, 1);
+        chunk.add_opcode(Opcode::Nil, 1);
+        chunk.add_opcode(Opcode::Return, 1);
+        assert_eq!(chunk.check_stack_balance(), Ok(()));
+        let mut out = Vec::new();
+        VM::new(&mut out, memory_manager, alloc)
+            .run(&chunk)
+            .unwrap();
+        assert_eq!(out, b"5\n");
+    }
+
+    #[test]
+    fn native_error_surfaces_as_runtime_error() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let chunk = compile(
+            &mut Scanner::new("print 1;\nnum(\"one\");").iter(),
+            &mut memory_manager,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let err = VM::new(&mut out, memory_manager, alloc)
+            .run(&chunk)
+            .unwrap_err();
+        match err {
+            VMError::RuntimeError(RuntimeError::NativeFailed(line, e), _) => {
+                assert_eq!(line, 2);
+                assert!(matches!(*e, RuntimeError::InvalidArgument(_)));
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+        assert_eq!(out, b"1\n");
+    }
+
+    #[test]
+    fn globals_survive_reset() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let mut out = Vec::new();
+        let mut vm = VM::new(&mut out, memory_manager, alloc);
+        let first = compile(
+            &mut Scanner::new("var a = \"hi\";").iter(),
+            vm.memory_manager_mut(),
+        )
+        .unwrap();
+        vm.run(&first).unwrap();
+        vm.reset();
+        let second = compile(
+            &mut Scanner::new("print a + \"!\";").iter(),
+            vm.memory_manager_mut(),
+        )
+        .unwrap();
+        vm.run(&second).unwrap();
+        drop(vm);
+        assert_eq!(out, b"hi!\n");
+    }
+
+    #[test]
+    fn hook_records_opcodes() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+        let chunk = compile(
+            &mut Scanner::new(r#"print "a" + "b";"#).iter(),
+            &mut memory_manager,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let mut hook = RecordingHook::default();
+        VM::new(&mut out, memory_manager, alloc)
+            .with_hook(&mut hook)
+            .run(&chunk)
+            .unwrap();
+        assert_eq!(
+            hook.opcodes,
+            [
+                Opcode::GetGlobal,
+                Opcode::Constant,
+                Opcode::Constant,
+                Opcode::Add,
+                Opcode::Call,
+                Opcode::Pop,
+                Opcode::Nil,
+                Opcode::Return
+            ]
+        );
+        assert_eq!(out, b"ab\n");
+    }
+
+    #[test]
+    fn script_evaluates_to_nil() {
+        for source in ["", "var a = 1; a + 2;", "fun f() { return 3; } print f();"] {
+            let alloc = Allocator::new();
+            let strings = HashTable::new(alloc.clone());
+            let mut memory_manager = MemoryManager::new(alloc.clone(), strings);
+            let chunk = compile(&mut Scanner::new(source).iter(), &mut memory_manager).unwrap();
+            let mut out = Vec::new();
+            let mut vm = VM::new(&mut out, memory_manager, alloc);
+            assert_eq!(vm.eval(&chunk).unwrap(), Value::Nil, "{source}");
+            assert!(vm.memory_manager.stack().is_empty(), "{source}");
+        }
+    }
+
+    #[test]
+    fn implicit_return_has_the_last_line() {
+        let alloc = Allocator::new();
+        let strings = HashTable::new(alloc.clone());
+        let mut memory_manager = MemoryManager::new(alloc, strings);
+        let chunk = compile(
+            &mut Scanner::new("var a;\n\na = 1;\n").iter(),
+            &mut memory_manager,
+        )
+        .unwrap();
+        let (offset, _, line) = chunk.instructions().last().unwrap();
+        assert_eq!(Opcode::try_from(chunk[offset]), Ok(Opcode::Return));
+        assert_eq!(line, 3);
+    }
 }
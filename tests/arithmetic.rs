@@ -1,4 +1,4 @@
-use lox::interpret;
+use lox::{interpret, interpret_with_options, VMOptions};
 
 #[test]
 fn simple_arithmetic_1() {
@@ -19,3 +19,66 @@ fn simple_arithmetic_2() {
     let expected = "7\n";
     assert_eq!(&out, expected);
 }
+
+#[test]
+fn division_by_zero_prints_like_clox() {
+    let source = "print 0/0; print 1/0; print -1/0;";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "nan\ninf\n-inf\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn modulo() {
+    let source = "print 7 % 3; print -7 % 3; print 5.5 % 2; print 1 + 7 % 4;";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "1\n-1\n1.5\n4\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn divide_by_zero_ieee() {
+    let source = "print 5 / 0; print -5 / 0; print 5 % 0 == 5 % 0;";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "inf\n-inf\nfalse\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn divide_by_zero_strict() {
+    let options = VMOptions {
+        strict_math: true,
+        ..VMOptions::default()
+    };
+    for source in ["print 1;\nprint 5 / 0;", "print 1;\nprint 5 % 0;"] {
+        let mut out = Vec::new();
+        let err = interpret_with_options(source, &mut out, options.clone()).unwrap_err();
+        assert!(
+            err.to_string().contains("Division by zero. [line 2]"),
+            "{err}"
+        );
+        assert_eq!(out, b"1\n");
+    }
+
+    let source = "print 5 / 2; print 0.5 / 0;";
+    let mut out = Vec::new();
+    interpret_with_options(source, &mut out, options).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "2.5\ninf\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn digit_separators() {
+    let source = "print 1_000 + 1; print 3.141_5 * 2; var _1 = 1_0; print _1;";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "1001\n6.283\n10\n");
+}
@@ -14,3 +14,18 @@ print "a";
     let expected = "a\nb\nc\n";
     assert_eq!(&out, expected);
 }
+
+#[test]
+fn empty_block_is_noop() {
+    let source = r#"
+print "a";
+{}
+{ }
+{ {} }
+print "b";"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "a\nb\n";
+    assert_eq!(&out, expected);
+}
@@ -0,0 +1,61 @@
+use lox::{compile_to_bytes, interpret, run_bytes, InterpretError};
+
+#[test]
+fn bytecode_round_trip() {
+    let source = r#"
+var greeting = "Hello";
+var count = 0;
+{
+    var name = "World";
+    while (count < 3) {
+        print greeting + " " + name;
+        count = count + 1;
+    }
+}
+print count == 3 and !nil;"#;
+    let mut expected = Vec::new();
+    interpret(source, &mut expected).unwrap();
+
+    let bytes = compile_to_bytes(source).unwrap();
+    let mut out = Vec::new();
+    run_bytes(&bytes, &mut out).unwrap();
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn bytecode_invalid() {
+    let mut out = Vec::new();
+    let err = run_bytes(b"not bytecode", &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::InvalidBytecode(_)));
+}
+
+#[test]
+fn bytecode_round_trip_many_globals() {
+    let mut source = String::new();
+    for i in 0..300 {
+        source.push_str(&format!("var g{i} = {};\n", i % 10));
+    }
+    source.push_str("g299 = g299 + 1;\nprint g299;\nprint g0;");
+    let bytes = compile_to_bytes(&source).unwrap();
+    let mut out = Vec::new();
+    run_bytes(&bytes, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "10\n0\n");
+}
+
+#[test]
+fn bytecode_round_trip_functions() {
+    let source = r#"
+var total = 0;
+fun add(n) { total = total + n; return total; }
+var apply = fun (f, x) { return f(x); };
+print apply(add, 2) + apply(add, 3);
+print add;"#;
+    let mut expected = Vec::new();
+    interpret(source, &mut expected).unwrap();
+
+    let bytes = compile_to_bytes(source).unwrap();
+    let mut out = Vec::new();
+    run_bytes(&bytes, &mut out).unwrap();
+    assert_eq!(out, expected);
+    assert_eq!(String::from_utf8(out).unwrap(), "7\n<fn add>\n");
+}
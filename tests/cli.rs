@@ -0,0 +1,169 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[test]
+fn scan_error_shows_caret_under_column() {
+    let path = std::env::temp_dir().join(format!("lox_cli_scan_error_{}.lox", std::process::id()));
+    std::fs::write(&path, "var a = 1;\nprint a @ 2;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-f")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(65));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Unknown token @\n2 | print a @ 2;\n  |         ^\n"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn warnings_go_to_stderr() {
+    let path = std::env::temp_dir().join(format!("lox_cli_warnings_{}.lox", std::process::id()));
+    std::fs::write(&path, "{\n  var unused = 1;\n}\nprint 2;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-f")
+        .arg(&path)
+        .output()
+        .unwrap();
+    let strict = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-f")
+        .arg(&path)
+        .arg("--strict")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // A line of its own, not just inside a log record
+    assert!(
+        stderr
+            .lines()
+            .any(|line| line == "[line 2] Warning: Local variable 'unused' is never used."),
+        "{stderr}"
+    );
+    assert_eq!(strict.status.code(), Some(65));
+    let stderr = String::from_utf8(strict.stderr).unwrap();
+    assert!(
+        stderr.contains("Local variable 'unused' is never used."),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn time_flag_reports_phases() {
+    let path = std::env::temp_dir().join(format!("lox_cli_time_{}.lox", std::process::id()));
+    std::fs::write(&path, "{\n  var unused = 1;\n}\nprint 1 + 2;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-f")
+        .arg(&path)
+        .arg("--time")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    for label in ["compile: ", "run: ", "peak allocated: "] {
+        assert!(stderr.contains(label), "{stderr}");
+    }
+    // Timing doesn't change what else is reported
+    assert!(
+        stderr
+            .lines()
+            .any(|line| line == "[line 2] Warning: Local variable 'unused' is never used."),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn tokens_flag_dumps_tokens() {
+    let path = std::env::temp_dir().join(format!("lox_cli_tokens_{}.lox", std::process::id()));
+    std::fs::write(&path, "var a = 1_0;\n  print \"hi\" @;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-f")
+        .arg(&path)
+        .arg("--tokens")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(65));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected = r#"1:1 Var
+1:5 Identifier("a")
+1:7 Equal
+1:9 Number("1_0")
+1:12 Semicolon
+2:3 Print
+2:9 String("hi")
+2:14 error: Unknown token @
+2:15 Semicolon
+"#;
+    assert_eq!(stdout, expected);
+}
+
+#[test]
+fn ast_flag_dumps_tree() {
+    let path = std::env::temp_dir().join(format!("lox_cli_ast_{}.lox", std::process::id()));
+    std::fs::write(&path, "print -x;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-f")
+        .arg(&path)
+        .arg("--ast")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Print(\n"), "{stdout}");
+    assert!(stdout.contains("op: Negate"), "{stdout}");
+    assert!(stdout.contains("Variable(\n"), "{stdout}");
+    assert!(stdout.contains("\"x\""), "{stdout}");
+}
+
+#[test]
+fn repl_reads_piped_input() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"print 1;\nprint 2;\n\n")
+        .unwrap();
+    // The REPL used to deadlock on stdin, so don't wait forever
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("REPL didn't exit");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    let mut stdout = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+
+    assert!(status.success());
+    assert_eq!(stdout, ">1\n>2\n>");
+}
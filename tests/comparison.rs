@@ -19,3 +19,22 @@ fn comparisons_2() {
     let expected = "true\n";
     assert_eq!(&out, expected);
 }
+
+#[test]
+fn not_equal() {
+    let source = r#"
+var nan = 0 / 0;
+print nan != nan;
+print nan == nan;
+print 1 != 1;
+print 1 != 2;
+print "a" != "a";
+print "a" != "b";
+print nil != false;
+print 1 != 2 == true;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "true\nfalse\nfalse\ntrue\nfalse\ntrue\ntrue\ntrue\n";
+    assert_eq!(&out, expected);
+}
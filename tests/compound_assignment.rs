@@ -0,0 +1,90 @@
+use lox::{interpret, InterpretError};
+
+#[test]
+fn compound_assignment_global() {
+    let source = "var x = 1; x += 4; print x; x -= 2; print x; x *= 6; print x; x /= 4; print x; x %= 3; print x;";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "5\n3\n18\n4.5\n1.5\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn compound_assignment_local() {
+    let source = r#"
+{
+    var x = 1;
+    x += 4;
+    print x;
+    var s = "a";
+    s += "b";
+    print s;
+    print x *= 2;
+}"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "5\nab\n10\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn compound_assignment_invalid_target() {
+    let source = "var a = 1; var b = 2; a + b += 3;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::CompileErrors(_)), "{err}");
+    assert!(
+        err.to_string().contains("Invalid assignment target."),
+        "{err}"
+    );
+}
+
+#[test]
+fn postfix_increment_and_decrement() {
+    let source = r#"
+var i = 1;
+print i++;
+print i;
+{
+    var j = 5;
+    print j--;
+    print j;
+}
+print --(3);
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "1\n2\n5\n4\n3\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn postfix_increment_invalid_target() {
+    let source = "var a = 1; var b = 2; (a + b)++;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::CompileErrors(_)), "{err}");
+    assert!(
+        err.to_string().contains("Invalid increment target."),
+        "{err}"
+    );
+}
+
+#[test]
+fn double_minus_before_an_operand_subtracts_a_negation() {
+    let source = r#"
+print 1--1;
+var a = 3;
+print a--1;
+print a;
+print --a;
+print a-- - 1;
+print a;
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    assert_eq!(out, b"2\n4\n3\n3\n2\n2\n");
+}
@@ -1,4 +1,4 @@
-use lox::{interpret, InterpretError};
+use lox::{interpret, interpret_with_hook, Chunk, InterpretError, Opcode, Value, VmHook};
 
 #[test]
 fn errors() {
@@ -10,7 +10,138 @@ naf;
     let err = interpret(source, &mut out).unwrap_err();
     let errs = match err {
         InterpretError::CompileErrors(e) => e,
-        InterpretError::InterpretError(_) => panic!(),
+        _ => panic!(),
     };
     assert_eq!(errs.errors().len(), 2);
 }
+
+#[test]
+fn runtime_error_stack_trace() {
+    let source = r#"var a = 1;
+print a;
+print a + "b";
+"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::InterpretError(_)));
+    assert!(err.to_string().ends_with("\n[line 3] in script"), "{err}");
+}
+
+#[test]
+fn error_in_branch_reports_branch_line() {
+    let source = r#"var x = "a";
+if (x
+    == "a")
+{
+    print 1;
+    x = -x;
+}
+"#;
+    let err = interpret(source, &mut Vec::new()).unwrap_err();
+    assert!(err.to_string().ends_with("\n[line 6] in script"), "{err}");
+
+    let source = r#"var x = "a";
+if (!x) print 1;
+else if (x)
+    print -x;
+"#;
+    let err = interpret(source, &mut Vec::new()).unwrap_err();
+    assert!(err.to_string().ends_with("\n[line 4] in script"), "{err}");
+
+    // The branch's jump belongs to the `if`, not to the line the condition ends on
+    let source = "var x = true;\nif (x\n    == false)\n    print 1;";
+    let mut lines = JumpLines::default();
+    interpret_with_hook(source, &mut Vec::new(), &mut lines).unwrap();
+    assert_eq!(lines.0, [2]);
+}
+
+/// The lines of the conditional jumps executed.
+#[derive(Default)]
+struct JumpLines(Vec<usize>);
+
+impl VmHook for JumpLines {
+    fn before_instruction(&mut self, ip: usize, chunk: &Chunk, _stack: &[Value]) {
+        if chunk[ip] == Opcode::JumpIfFalse.as_byte() {
+            self.0.push(chunk.line_for(ip));
+        }
+    }
+}
+
+#[test]
+fn negate_error_line() {
+    let source = r#"print 1;
+
+-"x";
+"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Operand must be a number. [line 3]"),
+        "{err}"
+    );
+}
+
+#[test]
+fn errors_after_scan_error() {
+    let source = r#"@
+print 1;
+#
+print 2;
+"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    let errs = match err {
+        InterpretError::CompileErrors(e) => e,
+        _ => panic!(),
+    };
+    assert_eq!(errs.errors().len(), 2);
+    assert!(errs.errors()[0].to_string().contains('@'));
+    assert!(errs.errors()[1].to_string().contains('#'));
+}
+
+#[test]
+fn errors_after_unterminated_string() {
+    let source = r#"var a = "no close;
+print a;
+print -;
+"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    let errs = match err {
+        InterpretError::CompileErrors(e) => e,
+        _ => panic!(),
+    };
+    let errs: Vec<_> = errs.errors().iter().map(|e| e.to_string()).collect();
+    assert_eq!(errs.len(), 2, "{errs:?}");
+    assert!(errs[0].contains("Unterminated string"), "{errs:?}");
+    assert!(errs[1].contains("line 3"), "{errs:?}");
+}
+
+#[test]
+fn errors_capped() {
+    let source = "@\n".repeat(100);
+    let mut out = Vec::new();
+    let err = interpret(&source, &mut out).unwrap_err();
+    let errs = match err {
+        InterpretError::CompileErrors(e) => e,
+        _ => panic!(),
+    };
+    assert_eq!(errs.errors().len(), 25);
+}
+
+#[test]
+fn print_without_argument() {
+    let source = "print 1;\nprint;\nprint 2;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    let errs = match err {
+        InterpretError::CompileErrors(e) => e,
+        _ => panic!(),
+    };
+    assert_eq!(errs.errors().len(), 1);
+    assert_eq!(
+        errs.errors()[0].to_string(),
+        "[line 2] Error at ';': Expect expression."
+    );
+}
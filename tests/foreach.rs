@@ -0,0 +1,96 @@
+use lox::interpret;
+
+#[test]
+fn foreach_over_string_graphemes() {
+    let source = r#"
+for (var c in "a👍🏽b") {
+    print c;
+}
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "a\n👍🏽\nb\n");
+}
+
+#[test]
+fn foreach_sums_digits() {
+    let source = r#"
+var sum = 0;
+for (var digit in "12345") sum = sum + num(digit);
+print sum;
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "15\n");
+}
+
+#[test]
+fn foreach_break_and_continue() {
+    let source = r#"
+fun firstVowels(s) {
+    var found = "";
+    for (var c in s) {
+        var x = c;
+        if (x == "x") break;
+        if (x != "a" and x != "e" and x != "o") continue;
+        found = found + x;
+    }
+    return found;
+}
+print firstVowels("banana boat");
+print firstVowels("hexagon");
+print firstVowels("");
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "aaaoa\ne\n\n");
+}
+
+#[test]
+fn string_indexing() {
+    let source = r#"
+var s = "héllo";
+print s[1];
+print s[5];
+"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert_eq!(String::from_utf8(out).unwrap(), "é\n");
+    assert!(
+        err.contains("Range 5..6 is out of bounds for length 5."),
+        "{err}"
+    );
+}
+
+#[test]
+fn foreach_over_non_string_is_an_error() {
+    let source = "for (var x in 3) print x;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(err.contains("Operand must be a string."), "{err}");
+}
+
+#[test]
+fn foreach_over_long_string() {
+    // Each step used to segment the whole string again, which took minutes for this length
+    let long = "aé👍🏽".repeat(20_000);
+    let source = format!(
+        r#"
+var count = 0;
+var thumbs = 0;
+for (var c in "{long}") {{
+    count = count + 1;
+    if (c == "👍🏽") thumbs = thumbs + 1;
+}}
+print count;
+print thumbs;
+"#
+    );
+    let mut out = Vec::new();
+    interpret(&source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "60000\n20000\n");
+}
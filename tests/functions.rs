@@ -0,0 +1,97 @@
+use lox::{interpret, InterpretError};
+
+#[test]
+fn lambda_assigned_and_called() {
+    let source = r#"var add = fun (a, b) { return a + b; };
+print add(1, 2);
+print add;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "3\n<fn>\n");
+}
+
+#[test]
+fn named_function_prints_its_declared_name() {
+    let source = r#"fun greet() {}
+var alias = greet;
+print greet;
+print alias;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "<fn greet>\n<fn greet>\n");
+}
+
+#[test]
+fn lambda_passed_as_argument() {
+    let source = r#"fun twice(f, x) { return f(f(x)); }
+print twice(fun (n) { return n * 3; }, 2);
+print fun () {}();"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "18\nnil\n");
+}
+
+#[test]
+fn locals_of_enclosing_function_are_not_captured() {
+    let source = r#"{
+  var a = 1;
+  var f = fun () { return a; };
+}"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::CompileErrors(_)));
+    assert!(err.to_string().contains(
+        "[line 3] Error at 'a': Can't capture local variables of an enclosing function."
+    ));
+}
+
+#[test]
+fn stack_trace_lists_frames() {
+    let source = r#"fun inner() {
+  return -"x";
+}
+var outer = fun () {
+  inner();
+};
+outer();"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(
+        err.ends_with("[line 2] in inner()\n[line 5] in lambda()\n[line 7] in script"),
+        "{err}"
+    );
+}
+
+#[test]
+fn tail_calls_reuse_the_frame() {
+    let source = r#"fun countdown(n) {
+  if (n == 0) return "done";
+  return countdown(n - 1);
+}
+print countdown(10000);
+fun isEven(n) { if (n == 0) return true; return isOdd(n - 1); }
+fun isOdd(n) { if (n == 0) return false; return isEven(n - 1); }
+print isEven(10001);"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "done\nfalse\n");
+}
+
+#[test]
+fn non_tail_recursion_overflows() {
+    let source = r#"fun count(n) {
+  if (n == 0) return 0;
+  return 1 + count(n - 1);
+}
+print count(10000);"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(
+        err.to_string().contains("more than 64 nested calls"),
+        "{err}"
+    );
+}
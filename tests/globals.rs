@@ -1,4 +1,5 @@
-use lox::{interpret, InterpretError};
+use lox::{interpret, interpret_with_hook, Chunk, InterpretError, Value, VmHook};
+use std::time::{Duration, Instant};
 
 #[test]
 fn var_declaration_1() {
@@ -65,7 +66,131 @@ a * b = c + d;
     let err = interpret(source, &mut out).unwrap_err();
     let errs = match err {
         InterpretError::CompileErrors(e) => e,
-        InterpretError::InterpretError(_) => panic!(),
+        _ => panic!(),
     };
     assert_eq!(errs.errors().len(), 1);
 }
+
+#[test]
+fn redefine_global() {
+    let source = r#"
+var a = "first";
+print a;
+var a = "second";
+print a;
+a = "third";
+print a;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "first\nsecond\nthird\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn assign_undefined_global() {
+    let source = "a = 1;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::InterpretError(_)));
+    assert!(err.to_string().contains("Undefined variable 'a'."));
+}
+
+#[test]
+fn undefined_global_reports_line() {
+    let source = "var a = 1;\nprint a;\nprint b;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(
+        err.to_string().contains("Undefined variable 'b'. [line 3]"),
+        "{err}"
+    );
+    assert_eq!(String::from_utf8(out).unwrap(), "1\n");
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn global_hot_loop() {
+    let source = r#"
+var total = 0;
+var step = 1;
+for (var i = 0; i < 100000; i = i + 1) {
+  total = total + step;
+}
+print total;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "100000\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn many_globals() {
+    let mut source = String::new();
+    for i in 0..300 {
+        // Keep the number of distinct constants below the one-byte limit
+        source.push_str(&format!("var g{i} = {};\n", i % 100));
+    }
+    source.push_str("g299 = g299 + g1;\nprint g299;\nprint g255 + g256;");
+    let mut out = Vec::new();
+    interpret(&source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "100\n111\n");
+}
+
+/// Reads `g` a million times, as a global or, inside a block, as a local.
+fn million_reads(global: bool) -> String {
+    let body = r#"
+var g = 1;
+var total = 0;
+for (var i = 0; i < 250000; i = i + 1) {
+  total = total + g + g + g + g;
+}
+print total;"#;
+    if global {
+        body.to_string()
+    } else {
+        format!("{{{body}\n}}")
+    }
+}
+
+#[derive(Default)]
+struct InstructionCount(usize);
+
+impl VmHook for InstructionCount {
+    fn before_instruction(&mut self, _ip: usize, _chunk: &Chunk, _stack: &[Value]) {
+        self.0 += 1;
+    }
+}
+
+fn run_million_reads(global: bool) -> (usize, Duration) {
+    let source = million_reads(global);
+    let mut count = InstructionCount::default();
+    let mut out = Vec::new();
+    interpret_with_hook(&source, &mut out, &mut count).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "1000000\n");
+
+    let mut out = Vec::new();
+    let start = Instant::now();
+    interpret(&source, &mut out).unwrap();
+    (count.0, start.elapsed())
+}
+
+/// A global read is a single slot lookup like a local one, rather than a hash of its name.
+#[test]
+#[cfg_attr(miri, ignore)]
+fn global_reads_cost_like_local_reads() {
+    let (global_count, global_time) = run_million_reads(true);
+    let (local_count, local_time) = run_million_reads(false);
+    // Only the setup and the end of the block differ, not the loop
+    assert!(
+        global_count.abs_diff(local_count) < 5,
+        "{global_count} instructions for globals, {local_count} for locals"
+    );
+    // Loose, so a noisy machine doesn't fail the test
+    assert!(
+        global_time < local_time * 3,
+        "{global_time:?} for globals, {local_time:?} for locals"
+    );
+}
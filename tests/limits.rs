@@ -0,0 +1,162 @@
+use lox::{interpret, interpret_with_options, VMOptions};
+
+fn block_with_locals(count: usize) -> String {
+    let mut source = String::from("{\n");
+    for i in 0..count {
+        source.push_str(&format!("var v{i} = {};\n", i % 10));
+    }
+    source.push_str(&format!("print v{};\n}}\n", count - 1));
+    source
+}
+
+fn block_with_constants(count: usize) -> String {
+    let mut source = String::from("{\n");
+    for i in 0..count {
+        source.push_str(&format!("{i}.5;\n"));
+    }
+    source.push_str("}\n");
+    source
+}
+
+#[test]
+fn locals_255() {
+    let mut out = Vec::new();
+    interpret(&block_with_locals(255), &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "4\n");
+}
+
+#[test]
+fn locals_256() {
+    let mut out = Vec::new();
+    interpret(&block_with_locals(256), &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "5\n");
+}
+
+/// Slot 0 of a function holds the function itself, leaving 255 slots for its locals.
+fn function_with_locals(count: usize) -> String {
+    format!("fun f() {{\n{}}}\nf();\n", block_with_locals(count))
+}
+
+#[test]
+fn function_locals_255() {
+    let mut out = Vec::new();
+    interpret(&function_with_locals(255), &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "4\n");
+}
+
+#[test]
+fn function_locals_256() {
+    let mut out = Vec::new();
+    let err = interpret(&function_with_locals(256), &mut out).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("[line 258] Error at 'v255': Too many local variables in function."),
+        "{err}"
+    );
+}
+
+#[test]
+fn too_many_locals() {
+    let mut out = Vec::new();
+    let err = interpret(&block_with_locals(257), &mut out).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("[line 258] Error at 'v256': Too many local variables in function."),
+        "{err}"
+    );
+}
+
+#[test]
+fn constants_256() {
+    let mut out = Vec::new();
+    interpret(&block_with_constants(256), &mut out).unwrap();
+}
+
+#[test]
+fn too_many_constants() {
+    let mut source = block_with_constants(256);
+    source.insert_str(source.len() - 2, "\"oops\";\n");
+    let mut out = Vec::new();
+    let err = interpret(&source, &mut out).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("[line 258] Error at '\"oops\"': Too many constants in one chunk."),
+        "{err}"
+    );
+}
+
+/// Uses a variable so constant folding can't collapse the sum.
+fn nested_sum(depth: usize) -> String {
+    format!(
+        "var one = 1; print {}one{};",
+        "one + (".repeat(depth),
+        ")".repeat(depth)
+    )
+}
+
+fn with_stack_size(stack_size: usize) -> VMOptions {
+    VMOptions {
+        stack_size,
+        ..VMOptions::default()
+    }
+}
+
+#[test]
+fn small_stack_overflows() {
+    let mut out = Vec::new();
+    let err = interpret_with_options(&nested_sum(100), &mut out, with_stack_size(50)).unwrap_err();
+    assert!(
+        err.to_string().contains("too many values on the stack"),
+        "{err}"
+    );
+}
+
+#[test]
+fn large_stack_fits() {
+    let mut out = Vec::new();
+    interpret_with_options(&nested_sum(100), &mut out, with_stack_size(200)).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "101\n");
+}
+
+#[test]
+fn call_depth_is_configurable() {
+    let source = r#"fun forever(n) {
+  return 1 + forever(n + 1);
+}
+forever(0);"#;
+    let options = VMOptions {
+        max_frames: 10,
+        ..VMOptions::default()
+    };
+    let mut out = Vec::new();
+    let err = interpret_with_options(source, &mut out, options).unwrap_err();
+    assert!(
+        err.to_string().contains("more than 10 nested calls"),
+        "{err}"
+    );
+}
+
+/// A body of more than 65535 bytes of code, which no jump can cross.
+fn huge_body() -> String {
+    format!("{{ {} }}", "a = a + 1;".repeat(9000))
+}
+
+#[test]
+fn jump_over_huge_if_body_fails() {
+    let source = format!("var a = 0;\nif (a == 0) {}", huge_body());
+    let err = interpret(&source, &mut Vec::new()).unwrap_err().to_string();
+    assert!(
+        err.contains("[line 2] Error in 'if': Too much code to jump over."),
+        "{err}"
+    );
+}
+
+#[test]
+fn loop_over_huge_while_body_fails() {
+    let source = format!("var a = 0;\nwhile (a < 1) {}", huge_body());
+    let err = interpret(&source, &mut Vec::new()).unwrap_err().to_string();
+    assert!(
+        err.contains("[line 2] Error in 'while': Too much code to jump over."),
+        "{err}"
+    );
+}
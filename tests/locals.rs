@@ -1,4 +1,4 @@
-use lox::interpret;
+use lox::{interpret, interpret_with_hook, Chunk, Opcode, Value, VmHook};
 
 #[test]
 fn locals_1() {
@@ -19,3 +19,73 @@ print a;
     let expected = "a\nb\nc\n";
     assert_eq!(&out, expected);
 }
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn locals_tight_loop() {
+    let source = r#"
+{
+    var total = 0;
+    for (var i = 0; i < 100000; i = i + 1) {
+        total = total + i;
+    }
+    print total;
+}"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "4999950000\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn locals_many_in_block() {
+    let source = r#"
+var a = "outer";
+{
+    var b = 1;
+    var c = 2;
+    var d = 3;
+    print b + c + d;
+}
+{
+    var e = "inner";
+    print e;
+}
+print a;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "6\ninner\nouter\n";
+    assert_eq!(&out, expected);
+}
+
+/// Stack depth before every `Return`, the last one being the end of the script.
+#[derive(Default)]
+struct ReturnDepths(Vec<usize>);
+
+impl VmHook for ReturnDepths {
+    fn before_instruction(&mut self, ip: usize, chunk: &Chunk, stack: &[Value]) {
+        if chunk[ip] == Opcode::Return.as_byte() {
+            self.0.push(stack.len());
+        }
+    }
+}
+
+#[test]
+fn uninitialized_local_at_end_of_block_is_popped() {
+    let source = r#"
+{ var a = 1; var b; }
+{ print "block"; var c; }
+fun f() { var d; }
+print f();
+{ { var e; } var g; }
+"#;
+    let mut out = Vec::new();
+    let mut depths = ReturnDepths::default();
+    interpret_with_hook(source, &mut out, &mut depths).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "block\nnil\n");
+    // `f` returns with its callee slot, its local and the result on top of the `print` native, the
+    // script with just its `nil` result
+    assert_eq!(depths.0, vec![4, 1]);
+}
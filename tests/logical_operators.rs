@@ -0,0 +1,107 @@
+use lox::interpret;
+
+#[test]
+fn and_short_circuits() {
+    let source = r#"
+var evaluated = false;
+print false and (evaluated = true);
+print evaluated;
+print nil and 1 / 0;
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "false\nfalse\nnil\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn or_short_circuits() {
+    let source = r#"
+var evaluated = false;
+print "first" or (evaluated = true);
+print evaluated;
+print nil or "default";
+print false or nil;
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "first\nfalse\ndefault\nnil\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn logical_operators_leave_one_value() {
+    // Each expression statement pops exactly one value, so leaked operands would shift the
+    // local slots and print the wrong values.
+    let source = r#"
+{
+    var a = "a";
+    false and true;
+    true and false;
+    nil or false;
+    1 or 2;
+    true and nil or "x" and false;
+    var b = "b";
+    print a;
+    print b;
+    print (nil or 1) and (false or 2);
+}
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "a\nb\n2\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn non_boolean_operands_use_truthiness() {
+    // Only `false` and `nil` are falsey, and the deciding operand is the result, not a boolean
+    let source = r#"
+print 0 and "x";
+print "" and "y";
+print nil or 5;
+print 0 or "unused";
+print nil and "unused";
+var zero = 0;
+var empty = "";
+print zero and empty and "both";
+print zero or "unused";
+print nil or false or empty;
+{
+    var a = "a";
+    print 0 and nil or "fallback";
+    print a;
+}
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "x\ny\n5\n0\nnil\nboth\n0\n\nfallback\na\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn or_short_circuits_in_loops_and_functions() {
+    let source = r#"
+var calls = 0;
+fun touch(v) { calls = calls + 1; return v; }
+fun first(a, b) { return a or touch(b); }
+print first(1, 2);
+print first(false, 2);
+print first(nil, nil);
+print calls;
+var i = 0;
+while (i < 2 or false) i = i + 1;
+print i;
+print (false or nil) or (0 or touch("unused"));
+print calls;
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "1\n2\nnil\n2\n2\n0\n2\n";
+    assert_eq!(&out, expected);
+}
@@ -0,0 +1,210 @@
+use lox::{interpret, InterpretError};
+
+#[test]
+fn map_literal_get_and_set() {
+    let source = r#"
+var m = {"a": 1, "b": 2};
+print m["a"];
+m["a"] = m["a"] + 10;
+print m["a"];
+print m["b"];
+print m["missing"];
+print m["c"] = 3;
+print m["c"];
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "1\n11\n2\nnil\n3\n3\n");
+}
+
+#[test]
+fn map_keys_can_be_any_value() {
+    let source = r#"
+var m = {1: "one", true: "yes", nil: "nothing", "1": "string one",};
+print m[1];
+print m[0 + 1];
+print m[true];
+print m[nil];
+print m["1"];
+print {:}[1];
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "one\none\nyes\nnothing\nstring one\nnil\n");
+}
+
+#[test]
+fn braces_at_statement_start_are_blocks() {
+    let source = r#"
+{ var m = {"k": "v"}; print m["k"]; }
+{}
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "v\n");
+}
+
+#[test]
+fn empty_braces_are_not_a_map() {
+    let mut out = Vec::new();
+    let err = interpret("var m = {};", &mut out).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("[line 1] Error at '{': Expect expression."),
+        "{err}"
+    );
+
+    let mut out = Vec::new();
+    interpret("var m = {:}; print m; print m[1];", &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "{}\nnil\n");
+}
+
+#[test]
+fn print_map() {
+    let source = r#"print {"a": 1};"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "{a: 1}\n");
+}
+
+#[test]
+fn maps_print_in_insertion_order() {
+    let source = r#"
+var m = {"z": 1, "a": 2, 3: 3, nil: 4, true: 5};
+m["b"] = 6;
+m["z"] = 7;
+print m;
+print copy(m);
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "{z: 7, a: 2, 3: 3, nil: 4, true: 5, b: 6}\n";
+    assert_eq!(out, expected.repeat(2));
+}
+
+#[test]
+fn indexing_a_number_is_an_error() {
+    let source = "var n = 1;\nprint n[0];";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(
+        err.contains("Operand must be a map or string. [line 2]"),
+        "{err}"
+    );
+}
+
+#[test]
+fn nan_key_is_an_error() {
+    let source = "var m = {:};\nm[0 / 0] = 1;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(
+        err.contains("NaN can't be used as a map key. [line 2]"),
+        "{err}"
+    );
+}
+
+#[test]
+fn missing_colon_is_a_compile_error() {
+    let source = r#"var m = {"a" 1};"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(err.contains("Expect ':' after map key."), "{err}");
+}
+
+#[test]
+fn assignment_aliases_but_copy_does_not() {
+    let source = r#"
+var a = {"k": 1};
+var alias = a;
+var b = copy(a);
+alias["k"] = 2;
+b["k"] = 3;
+b["new"] = true;
+print a["k"];
+print a["new"];
+print b["k"];
+print copy({:});
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "2\nnil\n3\n{}\n");
+}
+
+#[test]
+fn copying_a_non_map_is_an_error() {
+    let source = "print copy(1);";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(err.contains("Can't copy 1."), "{err}");
+}
+
+#[test]
+fn index_on_call_result_is_assignable() {
+    let source = r#"
+var m = {:};
+fun get() { return m; }
+get()[0] = 9;
+get()["k"] = get()[0] + 1;
+print m[0];
+print m["k"];
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "9\n10\n");
+}
+
+#[test]
+fn only_variables_and_indexes_are_assignable() {
+    for source in [
+        "fun foo() {} foo() = 9;",
+        "var m = {:}; m[0] + 1 = 9;",
+        "var m = {:}; -m[0] = 9;",
+        "fun foo() {} foo = foo() = 9;",
+    ] {
+        let mut out = Vec::new();
+        let err = interpret(source, &mut out).unwrap_err();
+        assert!(matches!(err, InterpretError::CompileErrors(_)), "{err}");
+        assert!(
+            err.to_string()
+                .contains("[line 1] Error at '=': Invalid assignment target."),
+            "{source}: {err}"
+        );
+    }
+}
+
+#[test]
+fn self_referential_map_prints() {
+    let source = r#"
+var a = {:};
+a["self"] = a;
+print a;
+var b = {:};
+var c = {"b": b};
+b["c"] = c;
+print b;
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "{self: {...}}\n{c: {b: {...}}}\n");
+}
+
+#[test]
+fn map_seen_twice_without_a_cycle_prints_in_full() {
+    let source = r#"
+var shared = {1: 2};
+print {"x": shared, "y": shared};
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.matches("{1: 2}").count(), 2, "{out}");
+}
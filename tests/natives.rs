@@ -0,0 +1,250 @@
+use lox::{interpret, interpret_with_input, InterpretError, RuntimeError, VMError};
+
+#[test]
+fn assert_passes() {
+    let source = r#"print assert(1 < 2, "math works");"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "nil\n");
+}
+
+#[test]
+fn assert_fails() {
+    let source = r#"print "before";
+assert(1 > 2, "math " + "broke");
+print "after";"#;
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::InterpretError(_)));
+    let err = err.to_string();
+    assert!(
+        err.contains("Assertion failed: math broke [line 2]"),
+        "{err}"
+    );
+    assert!(err.contains("[line 2] in script"), "{err}");
+    assert_eq!(String::from_utf8(out).unwrap(), "before\n");
+}
+
+#[test]
+fn native_wrong_arity() {
+    let source = "assert(true);";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(
+        err.contains("Expected 2 arguments but got 1. [line 1]"),
+        "{err}"
+    );
+}
+
+#[test]
+fn call_non_callable() {
+    let source = "var a = 1;\na(2);";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(
+        err.contains("Can only call functions and classes. [line 2]"),
+        "{err}"
+    );
+}
+
+#[test]
+fn print_native() {
+    let source = "print assert;";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "<native fn assert>\n");
+}
+
+#[test]
+fn str_and_num() {
+    let source = r#"print str(1.5) + "!"; print num(" 42 ") + 1; print num(3); print clock() > 0;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "1.5!\n43\n3\ntrue\n");
+}
+
+#[test]
+fn num_rejects_non_numbers() {
+    let mut out = Vec::new();
+    let err = interpret("num(\"abc\");", &mut out)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.contains("Can't convert 'abc' to a number. [line 1]"),
+        "{err}"
+    );
+    let err = interpret("num(nil);", &mut out).unwrap_err().to_string();
+    assert!(err.contains("Can't convert nil to a number."), "{err}");
+}
+
+#[test]
+fn write_without_newline() {
+    let source = r#"write("a"); write("b"); print "c"; println(1 + 1); write(nil);"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "abc\n2\nnil");
+}
+
+#[test]
+fn len_counts_graphemes() {
+    let source = r#"print len("abc"); print len(""); print len("a👍🏽b");"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "3\n0\n3\n");
+}
+
+#[test]
+fn substring_uses_grapheme_boundaries() {
+    let source = r#"var s = "a👍🏽bé";
+print substring(s, 1, 2);
+print substring(s, 2, 4);
+print substring(s, 0, 0) == "";
+print substring(s, 0, 4) == s;"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "👍🏽\nbé\ntrue\ntrue\n");
+}
+
+#[test]
+fn substring_out_of_bounds() {
+    for range in ["0, 5", "2, 1", "-1, 1", "0.5, 1"] {
+        let source = format!(r#"substring("a👍🏽bé", {range});"#);
+        let mut out = Vec::new();
+        let err = interpret(&source, &mut out).unwrap_err().to_string();
+        assert!(err.contains("is out of bounds for length 4."), "{err}");
+    }
+}
+
+#[test]
+fn print_is_a_native_value() {
+    let source = r#"var p = print;
+p("hi");
+print print;
+fun apply(f, x) { return f(x); }
+print apply(print, 1);"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "hi\n<native fn print>\n1\nnil\n");
+}
+
+#[test]
+fn format_numbers() {
+    let source = r#"print format(3.14159, ".2");
+print format(255, "x");
+print format(-255, "x");
+print format(2, ".3");
+print format(2.7, ".0");
+print format("as is", "");
+print format(255, "x") + "!";
+print len(format(1, ".100"));"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "3.14\nff\n-ff\n2.000\n3\nas is\nff!\n102\n");
+}
+
+#[test]
+fn format_rejects_invalid_specs() {
+    for (args, message) in [
+        (r#"1.5, "x""#, "Can't format 1.5 with spec 'x'."),
+        (r#"1, ".a""#, "Can't format 1 with spec '.a'."),
+        (r#"1, "d""#, "Can't format 1 with spec 'd'."),
+        (r#""s", ".2""#, "Can't format s with spec '.2'."),
+        ("1, 2", "Format spec must be a string, got 2."),
+        (
+            r#"18446744073709551616, "x""#,
+            "Can't format 18446744073709552000 with spec 'x'.",
+        ),
+        (
+            r#"1, ".101""#,
+            "Can't format with more than 100 decimals, got '.101'.",
+        ),
+    ] {
+        let source = format!("format({args});");
+        let mut out = Vec::new();
+        let err = interpret(&source, &mut out).unwrap_err().to_string();
+        assert!(err.contains(&format!("{message} [line 1]")), "{err}");
+    }
+}
+
+#[test]
+fn math_natives() {
+    let source = r#"
+print sqrt(9);
+print max(1, 5, 2);
+print min(4, -1, 3);
+print max(7);
+print floor(2.9);
+print ceil(2.1);
+print abs(-3.5);"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "3\n5\n-1\n7\n2\n3\n3.5\n");
+}
+
+#[test]
+fn math_natives_reject_non_numbers() {
+    for call in [r#"sqrt("9")"#, "max(1, nil)", "floor(true)"] {
+        let source = format!("print 1;\n{call};");
+        let mut out = Vec::new();
+        let err = interpret(&source, &mut out).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                InterpretError::InterpretError(VMError::RuntimeError(
+                    RuntimeError::InvalidType(2, "number"),
+                    _
+                ))
+            ),
+            "{call}: {err:?}"
+        );
+    }
+    let mut out = Vec::new();
+    let err = interpret("min();", &mut out).unwrap_err().to_string();
+    assert!(
+        err.contains("min expects at least one number. [line 1]"),
+        "{err}"
+    );
+    let mut out = Vec::new();
+    let err = interpret("floor(1, 2);", &mut out).unwrap_err().to_string();
+    assert!(err.contains("Expected 1 arguments but got 2."), "{err}");
+}
+
+#[test]
+fn read_line_reads_from_the_input() {
+    let source = r#"
+var line = read_line();
+while (line != nil) {
+    print "<" + line + ">";
+    line = read_line();
+}"#;
+    let mut out = Vec::new();
+    interpret_with_input(source, &mut out, &b"first\r\nsecond\n\nlast"[..]).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "<first>\n<second>\n<>\n<last>\n");
+}
+
+#[test]
+fn read_file_returns_contents() {
+    let path = std::env::temp_dir().join(format!("lox_read_file_{}.txt", std::process::id()));
+    std::fs::write(&path, "some\ncontents").unwrap();
+    let source = format!("print read_file(\"{}\");", path.display());
+    let mut out = Vec::new();
+    let result = interpret(&source, &mut out);
+    std::fs::remove_file(&path).unwrap();
+    result.unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "some\ncontents\n");
+
+    let source = format!("read_file(\"{}\");", path.display());
+    let mut out = Vec::new();
+    let err = interpret(&source, &mut out).unwrap_err().to_string();
+    assert!(err.contains("I/O error: Can't read"), "{err}");
+}
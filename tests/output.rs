@@ -0,0 +1,64 @@
+use lox::{interpret, Output, Value};
+
+#[derive(Debug, PartialEq)]
+enum Printed {
+    Number(f64),
+    Boolean(bool),
+    Nil,
+    Other(String),
+}
+
+/// Keeps printed values apart by type instead of as text.
+#[derive(Default)]
+struct Recorder {
+    printed: Vec<Printed>,
+    written: Vec<String>,
+    errors: Vec<String>,
+}
+
+impl Output for Recorder {
+    fn print(&mut self, value: &Value) {
+        self.printed.push(match *value {
+            Value::Number(n) => Printed::Number(n),
+            Value::Boolean(b) => Printed::Boolean(b),
+            Value::Nil => Printed::Nil,
+            ref other => Printed::Other(other.to_string()),
+        });
+    }
+
+    fn write(&mut self, value: &Value) {
+        self.written.push(value.to_string());
+    }
+
+    fn error(&mut self, msg: &str) {
+        self.errors.push(msg.to_string());
+    }
+}
+
+#[test]
+fn custom_output_records_values() {
+    let mut recorder = Recorder::default();
+    interpret(
+        r#"print 1 + 2; println(true); print nil; print "a" + "b"; write("no newline");"#,
+        &mut recorder,
+    )
+    .unwrap();
+    assert_eq!(
+        recorder.printed,
+        [
+            Printed::Number(3.0),
+            Printed::Boolean(true),
+            Printed::Nil,
+            Printed::Other("ab".to_string()),
+        ]
+    );
+    assert_eq!(recorder.written, ["no newline"]);
+    assert!(recorder.errors.is_empty());
+}
+
+#[test]
+fn writers_still_get_text() {
+    let mut out = Vec::new();
+    interpret(r#"write("a"); print 1;"#, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "a1\n");
+}
@@ -0,0 +1,78 @@
+use lox::{interpret_repl_line, InterpretError, Scanner, Session};
+
+#[test]
+fn repl_expression() {
+    let source = "3 * 4";
+    let mut out = Vec::new();
+    interpret_repl_line(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "12\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn repl_statement() {
+    let source = "var a = 1; print a + 1; a;";
+    let mut out = Vec::new();
+    interpret_repl_line(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "2\n";
+    assert_eq!(&out, expected);
+}
+
+#[test]
+fn repl_statement_error() {
+    let source = "print 1 +;";
+    let mut out = Vec::new();
+    let err = interpret_repl_line(source, &mut out).unwrap_err();
+    assert!(matches!(err, InterpretError::CompileErrors(_)));
+}
+
+#[test]
+fn session_keeps_globals() {
+    let mut out = Vec::new();
+    let mut session = Session::new(&mut out);
+    session.interpret_repl_line("var a = 1;").unwrap();
+    session.interpret_repl_line("a + 1").unwrap();
+    assert!(session.interpret_repl_line("b").is_err());
+    session.interpret("a = a * 10; print a;").unwrap();
+    drop(session);
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(&out, "2\n10\n");
+}
+
+#[test]
+fn session_compiler_is_fed_incrementally() {
+    let mut out = Vec::new();
+    let mut session = Session::new(&mut out);
+    let mut compiler = session.compiler();
+    compiler
+        .feed(&mut Scanner::new("var a = 1;").iter())
+        .unwrap();
+    assert!(compiler
+        .feed(&mut Scanner::new("print a +;").iter())
+        .is_err());
+    compiler
+        .feed(&mut Scanner::new("print a + 1;").iter())
+        .unwrap();
+    let chunk = compiler.finish();
+    session.run(&chunk).unwrap();
+    session.interpret_repl_line("a * 10").unwrap();
+    drop(session);
+    assert_eq!(String::from_utf8(out).unwrap(), "2\n10\n");
+}
+
+#[test]
+fn session_rejects_chunks_of_other_sessions() {
+    let mut out = Vec::new();
+    let mut other = Session::new(&mut out);
+    let mut compiler = other.compiler();
+    compiler.feed(&mut Scanner::new("print 1;").iter()).unwrap();
+    let chunk = compiler.finish();
+    let mut out = Vec::new();
+    let mut session = Session::new(&mut out);
+    assert!(matches!(
+        session.run(&chunk),
+        Err(InterpretError::ForeignChunk)
+    ));
+}
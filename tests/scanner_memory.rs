@@ -0,0 +1,47 @@
+use lox::parse;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Keeps track of the most memory allocated at once.
+struct PeakAllocator {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = self.current.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        self.peak.fetch_max(current, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.current.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+#[test]
+fn scanning_does_not_copy_the_source() {
+    let source = format!(
+        "{}print 1;",
+        "// a comment that produces no tokens\n".repeat(30_000)
+    );
+    let before = ALLOCATOR.current.load(Ordering::SeqCst);
+    ALLOCATOR.peak.store(before, Ordering::SeqCst);
+    let program = parse(&source).unwrap();
+    let peak = ALLOCATOR.peak.load(Ordering::SeqCst) - before;
+    assert_eq!(program.len(), 1);
+    // Collecting the graphemes up front took a slice, 16 bytes, for each byte of this source
+    assert!(
+        peak < source.len() / 10,
+        "{peak} bytes for {} bytes of source",
+        source.len()
+    );
+}
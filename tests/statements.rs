@@ -1,4 +1,7 @@
-use lox::interpret;
+use lox::{
+    interpret, interpret_strict, interpret_with_hook, interpret_with_warnings, parse, tokenize,
+    Chunk, Opcode, Value, VmHook,
+};
 
 #[test]
 fn statements_1() {
@@ -13,3 +16,217 @@ print "How are you!";
     let expected = "Hi!\nHow are you!\n";
     assert_eq!(&out, expected);
 }
+
+#[test]
+fn else_if_chain() {
+    let source = r#"
+for (var i = 0; i < 5; i = i + 1) {
+    if (i == 0) print "zero";
+    else if (i == 1) print "one";
+    else if (i == 2) { var two = "two"; print two; }
+    else if (i == 3) print "three";
+    else print "many";
+}
+if (false) print "no"; else if (false) print "no";
+print "done";
+"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "zero\none\ntwo\nthree\nmany\ndone\n";
+    assert_eq!(&out, expected);
+}
+
+/// Stack depth before every `Return`, the last one being the end of the script.
+#[derive(Default)]
+struct ReturnDepths(Vec<usize>);
+
+impl VmHook for ReturnDepths {
+    fn before_instruction(&mut self, ip: usize, chunk: &Chunk, stack: &[Value]) {
+        if chunk[ip] == Opcode::Return.as_byte() {
+            self.0.push(stack.len());
+        }
+    }
+}
+
+#[test]
+fn nested_if_without_else_keeps_stack_balanced() {
+    let source = r#"
+fun classify(n) {
+    if (n < 10) {
+        if (n < 5) {
+            if (n < 2) return "tiny";
+            else if (n < 3) return "small";
+        } else {
+            if (n == 7) print "seven";
+        }
+    } else if (n < 20) {
+        if (n == 15) print "fifteen"; else {}
+    } else return "big";
+    if (n) {}
+    return "other";
+}
+for (var i = 0; i < 25; i = i + 1) if (i != 3) print classify(i);
+"#;
+    let mut out = Vec::new();
+    let mut depths = ReturnDepths::default();
+    interpret_with_hook(source, &mut out, &mut depths).unwrap();
+    let mut expected = String::new();
+    for i in (0..25).filter(|i| *i != 3) {
+        match i {
+            7 => expected.push_str("seven\n"),
+            15 => expected.push_str("fifteen\n"),
+            _ => {}
+        }
+        expected.push_str(match i {
+            0 | 1 => "tiny\n",
+            2 => "small\n",
+            20.. => "big\n",
+            _ => "other\n",
+        });
+    }
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+    // Every call returns with the loop variable, `print`, the callee, its argument and the result
+    // on the stack, whichever branches it took. The script ends with only its `nil` result.
+    let (script_end, calls) = depths.0.split_last().unwrap();
+    assert_eq!(*script_end, 1);
+    assert_eq!(calls.len(), 24);
+    assert!(calls.iter().all(|d| *d == 5), "{calls:?}");
+}
+
+#[test]
+fn strict_warns_on_possible_infinite_loop() {
+    let source = "var i = 0;\nfor (; i < 3;) {\n    print i;\n}";
+    let mut out = Vec::new();
+    let err = interpret_strict(source, &mut out).unwrap_err().to_string();
+    assert!(
+        err.contains("[line 2] Warning: Possible infinite loop"),
+        "{err}"
+    );
+}
+
+#[test]
+fn warnings_are_handed_to_the_caller() {
+    let source = "{\n    var unused = 1;\n}\nprint 2;";
+    let mut out = Vec::new();
+    let mut warnings = Vec::new();
+    interpret_with_warnings(source, &mut out, |w| warnings.push(w.to_string())).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "2\n");
+    assert_eq!(
+        warnings,
+        ["[line 2] Warning: Local variable 'unused' is never used."]
+    );
+}
+
+#[test]
+fn strict_accepts_updated_loop_condition() {
+    let source = r#"
+for (var i = 0; i < 3;) {
+    print i;
+    i += 1;
+}
+var j = 0;
+for (; j < 2;) {
+    for (var k = 0; k < 1; k = k + 1) {
+        j = j + 1;
+    }
+}
+for (var n = 0; n < 2; n = n + 1) {}
+"#;
+    let mut out = Vec::new();
+    interpret_strict(source, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "0\n1\n2\n");
+}
+
+#[test]
+fn possible_infinite_loop_only_fails_when_strict() {
+    let source = "var i = 5;\nfor (; i < 3;) print i;\nprint \"done\";";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "done\n");
+    assert!(interpret_strict(source, &mut Vec::new()).is_err());
+}
+
+#[test]
+fn break_and_continue() {
+    let source = r#"
+var i = 0;
+while (true) {
+    var doubled = i * 2;
+    i = i + 1;
+    if (i == 2) continue;
+    if (i > 4) break;
+    print doubled;
+}
+for (var j = 0; j < 10; j = j + 1) {
+    var k = j;
+    {
+        var unused = k;
+        if (k == 1) continue;
+    }
+    if (k == 3) break;
+    print k;
+}
+print "done";"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "0\n4\n6\n0\n2\ndone\n");
+}
+
+#[test]
+fn break_outside_loop() {
+    let source = "if (true) {\n    break;\n}\ncontinue;";
+    let mut out = Vec::new();
+    let err = interpret(source, &mut out).unwrap_err().to_string();
+    assert!(
+        err.contains("[line 2] Error at 'break': Can't use 'break' outside of a loop."),
+        "{err}"
+    );
+    assert!(
+        err.contains("[line 4] Error at 'continue': Can't use 'continue' outside of a loop."),
+        "{err}"
+    );
+}
+
+#[test]
+fn unreachable_code_only_fails_when_strict() {
+    let source = "fun f() {\n    return 1;\n    print \"never\";\n}\nprint f();";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "1\n");
+    let err = interpret_strict(source, &mut Vec::new())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("[line 3] Warning: Unreachable code."), "{err}");
+}
+
+#[test]
+fn leading_bom_and_crlf() {
+    let source = "\u{FEFF}print 1;\r\nprint 2;\r\n";
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "1\n2\n");
+
+    let mut out = Vec::new();
+    interpret("\u{FEFF} \r\n\t", &mut out).unwrap();
+    assert!(out.is_empty());
+}
+
+#[test]
+fn empty_input_runs() {
+    for source in [
+        "",
+        "   ",
+        "// just a comment",
+        "\n\t\r\n",
+        "// one\n// two\n",
+        "\u{FEFF}",
+    ] {
+        let mut out = Vec::new();
+        interpret(source, &mut out).unwrap();
+        assert!(out.is_empty(), "{source:?}");
+        interpret_strict(source, &mut out).unwrap();
+        assert!(parse(source).unwrap().is_empty(), "{source:?}");
+        assert!(tokenize(source).is_empty(), "{source:?}");
+    }
+}
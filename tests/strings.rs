@@ -68,3 +68,15 @@ fn strings_expression_statement() {
     let out = String::from_utf8(out).unwrap();
     assert!(out.is_empty());
 }
+
+#[test]
+fn raw_strings_keep_backslashes() {
+    let source = r#"var rate = r"C:\path\n";
+print rate;
+print len(r"\n");"#;
+    let mut out = Vec::new();
+    interpret(source, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = "C:\\path\\n\n2\n";
+    assert_eq!(&out, expected);
+}